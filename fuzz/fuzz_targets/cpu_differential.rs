@@ -0,0 +1,18 @@
+// Runs one instruction on `Cpu` and the reference model from tests/reference
+// and panics on the first difference. Start it with
+// `cargo fuzz run cpu_differential` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/reference/mod.rs"]
+mod reference;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(case) = reference::Case::from_bytes(data) {
+        if let Err(divergence) = reference::check(&case) {
+            panic!("{}", divergence);
+        }
+    }
+});
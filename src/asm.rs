@@ -0,0 +1,966 @@
+use std::collections::HashMap;
+
+use crate::constants::OPERATION_INFORMATION;
+use crate::operation::AddressingModes;
+
+// A small two pass 6502 assembler sharing the opcode table with the Cpu.
+//
+// Supported syntax:
+//     label:          global label, starts a new scope for local labels
+//     @label:         local label, only visible until the next global label
+//     NAME = expr     constant
+//     .org expr       moves the output address forward, padding with zeros
+//     .byte expr, "text", ...
+//     .word expr, ...
+//     LDA a:$0010     forces absolute addressing for a zero page value
+//
+// Expressions understand $hex, %binary, decimal and 'c' literals, symbols,
+// `*` for the current address, unary - ~ < (low byte) > (high byte) and the
+// binary operators * / % + - << >> & ^ |. Square brackets group terms, as
+// parentheses are reserved for indirect addressing at the start of an operand.
+
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, u16>,
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, String> {
+    let mut assembler = Assembler::new();
+
+    assembler.pass(source, Pass::Layout)?;
+    assembler.pass(source, Pass::Emit)?;
+
+    Ok(Assembly {
+        origin: assembler.origin.unwrap_or(0),
+        bytes: assembler.output,
+        labels: assembler
+            .symbols
+            .into_iter()
+            .map(|(name, value)| (name, value as u16))
+            .collect(),
+    })
+}
+
+// Finds the opcode for a mnemonic in the given addressing mode. Official
+// encodings win over the unofficial duplicates (e.g. NOP, SBC #imm).
+pub fn find_opcode(name: &str, mode: AddressingModes) -> Option<u8> {
+    let mut unofficial = None;
+
+    for (opcode, operation) in OPERATION_INFORMATION.iter().enumerate() {
+        if let Some(operation) = operation {
+            if operation.name != name || operation.instruction_addressing_mode != mode {
+                continue;
+            }
+
+            if operation.is_official {
+                return Some(opcode as u8);
+            }
+
+            if unofficial.is_none() {
+                unofficial = Some(opcode as u8);
+            }
+        }
+    }
+
+    unofficial
+}
+
+fn has_mode(name: &str, mode: AddressingModes) -> bool {
+    find_opcode(name, mode).is_some()
+}
+
+// Alternative names used by other assemblers and opcode references
+fn canonical_mnemonic(mnemonic: &str) -> &str {
+    match mnemonic {
+        "ISB" | "INS" => "ISC",
+        "AXS" => "SBX",
+        "XAA" => "ANE",
+        "KIL" | "HLT" => "JAM",
+        "AHX" | "AXA" => "SHA",
+        "SXA" | "XAS" => "SHX",
+        "SYA" | "SAY" => "SHY",
+        "SHS" => "TAS",
+        "LAR" => "LAS",
+        "ASR" => "ALR",
+        "AAC" => "ANC",
+        "ATX" | "OAL" => "LXA",
+        "AAX" => "SAX",
+        "DOP" | "TOP" | "SKB" | "SKW" => "NOP",
+        other => other,
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum Pass {
+    Layout,
+    Emit,
+}
+
+#[derive(Clone, Copy)]
+enum Index {
+    None,
+    X,
+    Y,
+}
+
+enum Operand<'a> {
+    None,
+    Accumulator,
+    Immediate(&'a str),
+    Address {
+        expression: &'a str,
+        index: Index,
+        force_absolute: bool,
+    },
+    Indirect(&'a str),
+    IndexedIndirect(&'a str),
+    IndirectIndexed(&'a str),
+}
+
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    // Addressing mode picked for each instruction line during layout, so that
+    // the emit pass produces exactly the same sizes.
+    modes: HashMap<usize, AddressingModes>,
+    origin: Option<u16>,
+    output: Vec<u8>,
+    program_counter: u32,
+    scope: String,
+    pass: Pass,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler {
+            symbols: HashMap::new(),
+            modes: HashMap::new(),
+            origin: None,
+            output: Vec::new(),
+            program_counter: 0,
+            scope: String::new(),
+            pass: Pass::Layout,
+        }
+    }
+
+    fn pass(&mut self, source: &str, pass: Pass) -> Result<(), String> {
+        self.pass = pass;
+        self.origin = None;
+        self.output.clear();
+        self.program_counter = 0;
+        self.scope.clear();
+
+        for (index, line) in source.lines().enumerate() {
+            self.line(index, line)
+                .map_err(|error| format!("line {}: {}", index + 1, error))?;
+        }
+
+        Ok(())
+    }
+
+    fn line(&mut self, line_index: usize, line: &str) -> Result<(), String> {
+        let mut line = strip_comment(line).trim();
+
+        if let Some(colon) = line.find(':') {
+            let label = &line[..colon];
+            if is_identifier(label) {
+                self.define_label(label)?;
+                line = line[colon + 1..].trim();
+            }
+        }
+
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        if let Some((name, expression)) = line.split_once('=') {
+            let name = name.trim();
+            if is_identifier(name) {
+                return self.define_constant(name, expression.trim());
+            }
+        }
+
+        let (word, rest) = match line.find(char::is_whitespace) {
+            Some(split) => (&line[..split], line[split..].trim()),
+            None => (line, ""),
+        };
+
+        if word.starts_with('.') {
+            return self.directive(&word.to_ascii_lowercase(), rest);
+        }
+
+        self.instruction(line_index, &word.to_ascii_uppercase(), rest)
+    }
+
+    fn define_label(&mut self, label: &str) -> Result<(), String> {
+        let name = if label.starts_with('@') {
+            format!("{}{}", self.scope, label)
+        } else {
+            self.scope = label.to_string();
+            label.to_string()
+        };
+
+        let address = self.program_counter as i64;
+
+        if self.pass == Pass::Layout && self.symbols.insert(name, address).is_some() {
+            return Err(format!("label '{}' defined more than once", label));
+        }
+
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: &str, expression: &str) -> Result<(), String> {
+        let value = self.evaluate(expression)?;
+
+        match value {
+            Some(value) => {
+                if self.pass == Pass::Layout && self.symbols.contains_key(name) {
+                    return Err(format!("symbol '{}' defined more than once", name));
+                }
+                self.symbols.insert(name.to_string(), value);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn directive(&mut self, directive: &str, arguments: &str) -> Result<(), String> {
+        match directive {
+            ".org" => {
+                let address = self
+                    .evaluate(arguments)?
+                    .ok_or("the .org address must not reference later symbols")?;
+                let address = check_range(address, 0, 0xFFFF)? as u32;
+
+                match self.origin {
+                    None if self.output.is_empty() => {
+                        self.origin = Some(address as u16);
+                    }
+                    _ => {
+                        if address < self.program_counter {
+                            return Err(format!(
+                                ".org ${:04X} is behind the current address ${:04X}",
+                                address, self.program_counter
+                            ));
+                        }
+                        let padding = (address - self.program_counter) as usize;
+                        self.emit(&vec![0; padding])?;
+                    }
+                }
+
+                self.program_counter = address;
+                Ok(())
+            }
+            ".byte" | ".db" => {
+                for argument in split_arguments(arguments)? {
+                    if let Some(text) = argument
+                        .strip_prefix('"')
+                        .and_then(|text| text.strip_suffix('"'))
+                    {
+                        self.emit(text.as_bytes())?;
+                        continue;
+                    }
+
+                    let value = self.evaluate_for_emit(argument)?;
+                    let value = check_range(value, -128, 0xFF)?;
+                    self.emit(&[value as u8])?;
+                }
+                Ok(())
+            }
+            ".word" | ".dw" => {
+                for argument in split_arguments(arguments)? {
+                    let value = self.evaluate_for_emit(argument)?;
+                    let value = check_range(value, -32768, 0xFFFF)? as u16;
+                    self.emit(&value.to_le_bytes())?;
+                }
+                Ok(())
+            }
+            _ => Err(format!("unknown directive '{}'", directive)),
+        }
+    }
+
+    fn instruction(
+        &mut self,
+        line_index: usize,
+        mnemonic: &str,
+        operand: &str,
+    ) -> Result<(), String> {
+        let name = canonical_mnemonic(mnemonic);
+
+        if !OPERATION_INFORMATION
+            .iter()
+            .flatten()
+            .any(|operation| operation.name == name)
+        {
+            return Err(format!("unknown mnemonic '{}'", mnemonic));
+        }
+
+        let operand = parse_operand(operand)?;
+
+        let mode = match self.pass {
+            Pass::Layout => {
+                let mode = self.select_mode(name, &operand)?;
+                self.modes.insert(line_index, mode);
+                mode
+            }
+            Pass::Emit => self.modes[&line_index],
+        };
+
+        let opcode = find_opcode(name, mode)
+            .ok_or_else(|| format!("{} does not support {:?} addressing", name, mode))?;
+
+        let expression = match operand {
+            Operand::None | Operand::Accumulator => None,
+            Operand::Immediate(expression)
+            | Operand::Address { expression, .. }
+            | Operand::Indirect(expression)
+            | Operand::IndexedIndirect(expression)
+            | Operand::IndirectIndexed(expression) => Some(expression),
+        };
+
+        let mut bytes = vec![opcode];
+
+        if let Some(expression) = expression {
+            let value = self.evaluate_for_emit(expression)?;
+
+            match mode {
+                AddressingModes::Relative => {
+                    let next = self.program_counter as i64 + 2;
+                    let offset = if self.pass == Pass::Emit {
+                        check_range(value - next, -128, 127)
+                            .map_err(|_| format!("branch target ${:04X} is out of range", value))?
+                    } else {
+                        0
+                    };
+                    bytes.push(offset as u8);
+                }
+                AddressingModes::Immediate => {
+                    bytes.push(check_range(value, -128, 0xFF)? as u8);
+                }
+                AddressingModes::ZeroPage
+                | AddressingModes::ZeroPageX
+                | AddressingModes::ZeroPageY
+                | AddressingModes::IndexedIndirect
+                | AddressingModes::IndirectIndexed => {
+                    bytes.push(check_range(value, 0, 0xFF)? as u8);
+                }
+                _ => {
+                    let value = check_range(value, 0, 0xFFFF)? as u16;
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+
+        self.emit(&bytes)
+    }
+
+    fn select_mode(&self, name: &str, operand: &Operand) -> Result<AddressingModes, String> {
+        let mode = match *operand {
+            Operand::None if has_mode(name, AddressingModes::Implicit) => AddressingModes::Implicit,
+            Operand::None | Operand::Accumulator => AddressingModes::Accumulator,
+            Operand::Immediate(_) => AddressingModes::Immediate,
+            Operand::Indirect(_) => AddressingModes::Indirect,
+            Operand::IndexedIndirect(_) => AddressingModes::IndexedIndirect,
+            Operand::IndirectIndexed(_) => AddressingModes::IndirectIndexed,
+            Operand::Address {
+                expression,
+                index,
+                force_absolute,
+            } => {
+                let (zero_page, absolute) = match index {
+                    Index::None => (AddressingModes::ZeroPage, AddressingModes::Absolute),
+                    Index::X => (AddressingModes::ZeroPageX, AddressingModes::AbsoluteX),
+                    Index::Y => (AddressingModes::ZeroPageY, AddressingModes::AbsoluteY),
+                };
+
+                if matches!(index, Index::None) && has_mode(name, AddressingModes::Relative) {
+                    return Ok(AddressingModes::Relative);
+                }
+
+                let fits_zero_page = match self.evaluate(expression)? {
+                    Some(value) => (0..=0xFF).contains(&value),
+                    None => false,
+                };
+
+                if force_absolute || !has_mode(name, zero_page) {
+                    absolute
+                } else if fits_zero_page || !has_mode(name, absolute) {
+                    zero_page
+                } else {
+                    absolute
+                }
+            }
+        };
+
+        Ok(mode)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if self.origin.is_none() && self.output.is_empty() {
+            self.origin = Some(self.program_counter as u16);
+        }
+
+        self.program_counter += bytes.len() as u32;
+
+        if self.program_counter > 0x10000 {
+            return Err("output runs past $FFFF".to_string());
+        }
+
+        self.output.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    // Unknown symbols are only acceptable while laying out the program
+    fn evaluate_for_emit(&self, expression: &str) -> Result<i64, String> {
+        match self.evaluate(expression)? {
+            Some(value) => Ok(value),
+            None if self.pass == Pass::Layout => Ok(0),
+            None => Err(format!("undefined symbol in '{}'", expression)),
+        }
+    }
+
+    fn evaluate(&self, expression: &str) -> Result<Option<i64>, String> {
+        let tokens = tokenize(expression)?;
+        let mut parser = ExpressionParser {
+            tokens: &tokens,
+            position: 0,
+            assembler: self,
+        };
+
+        let value = parser.parse(0)?;
+
+        if parser.position != tokens.len() {
+            return Err(format!("unexpected input in expression '{}'", expression));
+        }
+
+        Ok(value)
+    }
+
+    fn lookup(&self, symbol: &str) -> Option<i64> {
+        if symbol.starts_with('@') {
+            return self
+                .symbols
+                .get(&format!("{}{}", self.scope, symbol))
+                .copied();
+        }
+
+        self.symbols.get(symbol).copied()
+    }
+}
+
+fn parse_operand(operand: &str) -> Result<Operand<'_>, String> {
+    let operand = operand.trim();
+
+    if operand.is_empty() {
+        return Ok(Operand::None);
+    }
+
+    if operand.eq_ignore_ascii_case("A") {
+        return Ok(Operand::Accumulator);
+    }
+
+    if let Some(expression) = operand.strip_prefix('#') {
+        return Ok(Operand::Immediate(expression.trim()));
+    }
+
+    if operand.starts_with('(') {
+        let upper = operand.to_ascii_uppercase();
+        let compact: String = upper.chars().filter(|c| !c.is_whitespace()).collect();
+
+        if compact.ends_with(",X)") {
+            let comma = operand.rfind(',').unwrap_or(0);
+            return Ok(Operand::IndexedIndirect(operand[1..comma].trim()));
+        }
+
+        if compact.ends_with("),Y") {
+            let close = operand.rfind(')').unwrap_or(0);
+            return Ok(Operand::IndirectIndexed(operand[1..close].trim()));
+        }
+
+        if compact.ends_with(')') {
+            return Ok(Operand::Indirect(operand[1..operand.len() - 1].trim()));
+        }
+
+        return Err(format!("malformed indirect operand '{}'", operand));
+    }
+
+    let (expression, index) = match operand.rsplit_once(',') {
+        Some((expression, register)) => match register.trim().to_ascii_uppercase().as_str() {
+            "X" => (expression.trim(), Index::X),
+            "Y" => (expression.trim(), Index::Y),
+            _ => return Err(format!("unknown index register '{}'", register.trim())),
+        },
+        None => (operand, Index::None),
+    };
+
+    let (expression, force_absolute) = match expression.strip_prefix("a:") {
+        Some(expression) => (expression.trim(), true),
+        None => (expression, false),
+    };
+
+    Ok(Operand::Address {
+        expression,
+        index,
+        force_absolute,
+    })
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut in_char = false;
+
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' if !in_char => in_string = !in_string,
+            '\'' if !in_string => in_char = !in_char,
+            ';' if !in_string && !in_char => return &line[..index],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+fn split_arguments(arguments: &str) -> Result<Vec<&str>, String> {
+    let mut result = Vec::new();
+    let mut in_string = false;
+    let mut start = 0;
+
+    for (index, character) in arguments.char_indices() {
+        match character {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                result.push(arguments[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if in_string {
+        return Err("unterminated string".to_string());
+    }
+
+    result.push(arguments[start..].trim());
+
+    if result.iter().any(|argument| argument.is_empty()) {
+        return Err("empty argument".to_string());
+    }
+
+    Ok(result)
+}
+
+fn is_identifier(text: &str) -> bool {
+    let text = text.strip_prefix('@').unwrap_or(text);
+    let mut characters = text.chars();
+
+    match characters.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            characters.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn check_range(value: i64, min: i64, max: i64) -> Result<i64, String> {
+    if value < min || value > max {
+        return Err(format!("value {} is out of range", value));
+    }
+
+    Ok(value)
+}
+
+#[derive(PartialEq, Clone, Debug)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Operator(&'static str),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let characters: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < characters.len() {
+        let character = characters[index];
+
+        if character.is_whitespace() {
+            index += 1;
+            continue;
+        }
+
+        // `%` starts a binary literal wherever an operand is expected and is
+        // the remainder operator everywhere else
+        let expects_operand = match tokens.last() {
+            None => true,
+            Some(Token::Operator(operator)) => *operator != "]",
+            Some(_) => false,
+        };
+
+        let radix = match character {
+            '$' => Some(16),
+            '%' if expects_operand => Some(2),
+            _ => None,
+        };
+
+        if let Some(radix) = radix {
+            let start = index + 1;
+            let mut end = start;
+            while end < characters.len() && characters[end].is_digit(radix) {
+                end += 1;
+            }
+            let digits: String = characters[start..end].iter().collect();
+            let value = i64::from_str_radix(&digits, radix)
+                .map_err(|_| format!("malformed number in '{}'", expression))?;
+            tokens.push(Token::Number(value));
+            index = end;
+            continue;
+        }
+
+        if character.is_ascii_digit() {
+            let start = index;
+            while index < characters.len() && characters[index].is_ascii_digit() {
+                index += 1;
+            }
+            let digits: String = characters[start..index].iter().collect();
+            let value = digits
+                .parse()
+                .map_err(|_| format!("malformed number in '{}'", expression))?;
+            tokens.push(Token::Number(value));
+            continue;
+        }
+
+        if character == '\'' {
+            match characters.get(index + 1..index + 3) {
+                Some([value, '\'']) => {
+                    tokens.push(Token::Number(*value as i64));
+                    index += 3;
+                    continue;
+                }
+                _ => return Err(format!("malformed character literal in '{}'", expression)),
+            }
+        }
+
+        if character.is_ascii_alphabetic() || character == '_' || character == '@' {
+            let start = index;
+            index += 1;
+            while index < characters.len()
+                && (characters[index].is_ascii_alphanumeric() || characters[index] == '_')
+            {
+                index += 1;
+            }
+            tokens.push(Token::Symbol(characters[start..index].iter().collect()));
+            continue;
+        }
+
+        let two: String = characters[index..characters.len().min(index + 2)]
+            .iter()
+            .collect();
+        let operator = match two.as_str() {
+            "<<" => Some("<<"),
+            ">>" => Some(">>"),
+            _ => None,
+        };
+
+        if let Some(operator) = operator {
+            tokens.push(Token::Operator(operator));
+            index += 2;
+            continue;
+        }
+
+        let operator = match character {
+            '+' => "+",
+            '-' => "-",
+            '*' => "*",
+            '/' => "/",
+            '%' => "%",
+            '&' => "&",
+            '|' => "|",
+            '^' => "^",
+            '~' => "~",
+            '<' => "<",
+            '>' => ">",
+            '[' => "[",
+            ']' => "]",
+            _ => return Err(format!("unexpected '{}' in '{}'", character, expression)),
+        };
+
+        tokens.push(Token::Operator(operator));
+        index += 1;
+    }
+
+    Ok(tokens)
+}
+
+struct ExpressionParser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    assembler: &'a Assembler,
+}
+
+impl<'a> ExpressionParser<'a> {
+    fn binding_power(operator: &str) -> Option<u8> {
+        match operator {
+            "|" => Some(1),
+            "^" => Some(2),
+            "&" => Some(3),
+            "<<" | ">>" => Some(4),
+            "+" | "-" => Some(5),
+            "*" | "/" | "%" => Some(6),
+            _ => None,
+        }
+    }
+
+    // Precedence climbing. Values are `None` while they depend on a symbol
+    // that has not been defined yet.
+    fn parse(&mut self, min_power: u8) -> Result<Option<i64>, String> {
+        let mut left = self.parse_unary()?;
+
+        while let Some(Token::Operator(operator)) = self.tokens.get(self.position) {
+            let power = match Self::binding_power(operator) {
+                Some(power) if power > min_power => power,
+                _ => break,
+            };
+
+            self.position += 1;
+            let right = self.parse(power)?;
+
+            left = match (left, right) {
+                (Some(left), Some(right)) => Some(Self::apply(operator, left, right)?),
+                _ => None,
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn apply(operator: &str, left: i64, right: i64) -> Result<i64, String> {
+        let shift = || {
+            u32::try_from(right)
+                .ok()
+                .filter(|shift| *shift < i64::BITS)
+                .ok_or_else(|| format!("shift by {} is out of range", right))
+        };
+
+        let value = match operator {
+            "|" => Some(left | right),
+            "^" => Some(left ^ right),
+            "&" => Some(left & right),
+            "<<" => left.checked_shl(shift()?),
+            ">>" => left.checked_shr(shift()?),
+            "+" => left.checked_add(right),
+            "-" => left.checked_sub(right),
+            "*" => left.checked_mul(right),
+            "/" | "%" if right == 0 => return Err("division by zero".to_string()),
+            "/" => left.checked_div(right),
+            _ => left.checked_rem(right),
+        };
+
+        value.ok_or_else(|| format!("{} {} {} overflows", left, operator, right))
+    }
+
+    fn parse_unary(&mut self) -> Result<Option<i64>, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("expression ends unexpectedly")?;
+        self.position += 1;
+
+        let value = match token {
+            Token::Number(value) => Some(value),
+            Token::Symbol(symbol) => self.assembler.lookup(&symbol),
+            Token::Operator("*") => Some(self.assembler.program_counter as i64),
+            Token::Operator("-") => match self.parse_unary()? {
+                Some(value) => Some(
+                    value
+                        .checked_neg()
+                        .ok_or_else(|| format!("-{} overflows", value))?,
+                ),
+                None => None,
+            },
+            Token::Operator("~") => self.parse_unary()?.map(|value| !value & 0xFFFF),
+            Token::Operator("<") => self.parse_unary()?.map(|value| value & 0xFF),
+            Token::Operator(">") => self.parse_unary()?.map(|value| (value >> 8) & 0xFF),
+            Token::Operator("[") => {
+                let value = self.parse(0)?;
+                if self.tokens.get(self.position) != Some(&Token::Operator("]")) {
+                    return Err("missing ']'".to_string());
+                }
+                self.position += 1;
+                value
+            }
+            Token::Operator(operator) => return Err(format!("unexpected '{}'", operator)),
+        };
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, find_opcode};
+    use crate::constants::OPERATION_INFORMATION;
+    use crate::disasm::disassemble;
+    use crate::operation::AddressingModes;
+
+    #[test]
+    fn official_opcodes_round_trip_through_the_disassembler() {
+        let mut bytes = Vec::new();
+        for (opcode, operation) in OPERATION_INFORMATION.iter().enumerate() {
+            let Some(operation) = operation.filter(|operation| operation.is_official) else {
+                continue;
+            };
+
+            // Operands that don't fit the zero page, so the absolute modes
+            // don't depend on the a: prefix
+            bytes.push(opcode as u8);
+            match operation.instruction_size {
+                2 => bytes.push(0x10),
+                3 => bytes.extend_from_slice(&[0x34, 0x12]),
+                _ => {}
+            }
+        }
+        let official = OPERATION_INFORMATION.iter().flatten();
+        assert_eq!(
+            official.filter(|operation| operation.is_official).count(),
+            151
+        );
+
+        let source = disassemble(&bytes, 0x8000);
+        let assembly = assemble(&source).unwrap();
+        assert_eq!(assembly.origin, 0x8000);
+        assert_eq!(assembly.bytes, bytes);
+        assert_eq!(disassemble(&assembly.bytes, 0x8000), source);
+        assert!(source.contains("    LDA ($10),Y\n"), "{}", source);
+        assert!(source.contains("    JMP ($1234)\n"), "{}", source);
+        assert!(source.contains("    BPL $8023\n"), "{}", source);
+    }
+
+    #[test]
+    fn every_opcode_round_trips_as_bytes() {
+        let bytes: Vec<u8> = (0..=255).flat_map(|opcode| [opcode, 0x00, 0x00]).collect();
+        let source = disassemble(&bytes, 0x0200);
+        assert_eq!(assemble(&source).unwrap().bytes, bytes);
+    }
+
+    #[test]
+    fn picks_addressing_modes() {
+        let assembly = assemble(
+            "
+            .org $0200
+                lda $10
+                lda a:$10
+                lda $1234,x
+                ldx $10,y
+                asl
+                asl a
+                jmp ($02FF)
+                sbc #1
+                nop
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            assembly.bytes,
+            [
+                0xA5, 0x10, 0xAD, 0x10, 0x00, 0xBD, 0x34, 0x12, 0xB6, 0x10, 0x0A, 0x0A, 0x6C, 0xFF,
+                0x02, 0xE9, 0x01, 0xEA
+            ]
+        );
+
+        assert_eq!(find_opcode("SBC", AddressingModes::Immediate), Some(0xE9));
+        assert_eq!(find_opcode("SLO", AddressingModes::ZeroPage), Some(0x07));
+        assert_eq!(assemble("isb $10").unwrap().bytes, [0xE7, 0x10]);
+    }
+
+    #[test]
+    fn resolves_labels() {
+        let assembly = assemble(
+            "
+            .org $C000
+            start:
+                ldx #0
+            @loop:
+                inx
+                bne @loop
+                jsr later
+                jmp start
+            later:
+            @loop:
+                dey
+                bne @loop
+                rts
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(assembly.labels["start"], 0xC000);
+        assert_eq!(assembly.labels["later"], 0xC00B);
+        assert_eq!(assembly.labels["start@loop"], 0xC002);
+        assert_eq!(assembly.labels["later@loop"], 0xC00B);
+        assert_eq!(
+            assembly.bytes,
+            [
+                0xA2, 0x00, 0xE8, 0xD0, 0xFD, 0x20, 0x0B, 0xC0, 0x4C, 0x00, 0xC0, 0x88, 0xD0, 0xFD,
+                0x60
+            ]
+        );
+
+        let error = assemble("start:\nstart:").err().unwrap();
+        assert!(error.contains("more than once"), "{}", error);
+        let error = assemble("bne nowhere").err().unwrap();
+        assert!(error.contains("undefined symbol"), "{}", error);
+        let error = assemble(".org $0200\nbne $0300").err().unwrap();
+        assert!(error.contains("out of range"), "{}", error);
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let assembly = assemble(
+            "
+            BASE = $0300
+            COUNT = 2 + 3 * 4
+            .org $0200
+                .byte COUNT, [2 + 3] * 4, %1010, 'A', <BASE, >BASE, 7 % 3, ~0 & $0F
+                .word *, BASE + 1 << 2, -1, $10 | $01 ^ $03
+                .byte \"hi\"
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            assembly.bytes,
+            [
+                14, 20, 10, 0x41, 0x00, 0x03, 1, 0x0F, 0x08, 0x02, 0x04, 0x0C, 0xFF, 0xFF, 0x12,
+                0x00, b'h', b'i'
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_overflow_instead_of_panicking() {
+        for expression in [
+            "$7FFFFFFFFFFFFFFF + 1",
+            "-$7FFFFFFFFFFFFFFF - 2",
+            "$7FFFFFFFFFFFFFFF * 2",
+            "[-$7FFFFFFFFFFFFFFF - 1] / -1",
+            "[-$7FFFFFFFFFFFFFFF - 1] % -1",
+            "-[-$7FFFFFFFFFFFFFFF - 1]",
+            "1 << 64",
+            "1 >> -1",
+        ] {
+            let error = assemble(&format!(".word {}", expression)).err().unwrap();
+            assert!(
+                error.contains("overflows") || error.contains("out of range"),
+                "{}: {}",
+                expression,
+                error
+            );
+        }
+
+        let error = assemble(".byte 1 / 0").err().unwrap();
+        assert!(error.contains("division by zero"), "{}", error);
+    }
+}
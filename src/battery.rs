@@ -0,0 +1,200 @@
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+// Cartridge memory that keeps its contents with the power off. Usually that
+// is battery backed PRG RAM, mappers with a serial EEPROM instead (Bandai
+// FCG, for one) provide the EEPROM contents.
+pub trait SaveMemory {
+    // Everything that goes in the .sav file, empty when there is nothing
+    fn save_data(&self) -> &[u8];
+
+    fn load_save_data(&mut self, data: &[u8]) -> Result<(), String>;
+
+    // Changes whenever the save data might have, so unchanged data isn't
+    // written out again
+    fn save_revision(&self) -> u64;
+}
+
+// Where the save for a ROM lives, `game.nes` saves to `game.sav`
+pub fn save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+// Keeps a .sav file in step with a cartridge's save memory
+pub struct BatterySave {
+    path: PathBuf,
+    // Frames between flushes of changed data
+    flush_interval: u64,
+    next_flush_frame: u64,
+    // The revision last loaded or written, None before either
+    flushed_revision: Option<u64>,
+}
+
+impl BatterySave {
+    pub fn new(path: PathBuf, flush_interval: u64) -> Self {
+        BatterySave {
+            path,
+            flush_interval,
+            next_flush_frame: flush_interval,
+            flushed_revision: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Loads the file into `memory`. Returns false if there is no file yet,
+    // which is how every game starts.
+    pub fn load<S: SaveMemory + ?Sized>(&mut self, memory: &mut S) -> Result<bool, String> {
+        let loaded = match fs::read(&self.path) {
+            Ok(data) => {
+                memory.load_save_data(&data).map_err(|error| {
+                    format!("Could not load {}: {}", self.path.display(), error)
+                })?;
+                true
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => false,
+            Err(error) => return Err(format!("Could not read {}: {}", self.path.display(), error)),
+        };
+
+        self.flushed_revision = Some(memory.save_revision());
+        Ok(loaded)
+    }
+
+    pub fn is_dirty<S: SaveMemory + ?Sized>(&self, memory: &S) -> bool {
+        self.flushed_revision != Some(memory.save_revision())
+    }
+
+    // Writes the save data if it changed since it was last loaded or written.
+    // Returns whether it wrote anything.
+    pub fn flush<S: SaveMemory + ?Sized>(&mut self, memory: &S) -> Result<bool, String> {
+        if !self.is_dirty(memory) || memory.save_data().is_empty() {
+            return Ok(false);
+        }
+
+        write_atomically(&self.path, memory.save_data())?;
+        self.flushed_revision = Some(memory.save_revision());
+        Ok(true)
+    }
+
+    // Call once a frame, flushes at most every `flush_interval` frames so a
+    // crash loses little progress without writing on every frame
+    pub fn flush_periodically<S: SaveMemory + ?Sized>(
+        &mut self,
+        memory: &S,
+        frame: u64,
+    ) -> Result<bool, String> {
+        if frame < self.next_flush_frame {
+            return Ok(false);
+        }
+
+        self.next_flush_frame = frame + self.flush_interval;
+        self.flush(memory)
+    }
+}
+
+// Writes a temporary file next to `path` and renames it over the old one, so
+// a crash or full disk leaves either the old save or the new one, never half
+// of each
+pub fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let write = || {
+        let mut file = File::create(&temporary)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    };
+
+    write().map_err(|error| {
+        let _ = fs::remove_file(&temporary);
+        format!("Could not write {}: {}", path.display(), error)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::{save_path, BatterySave, SaveMemory};
+    use crate::nes::tests::rom;
+    use crate::nes::Nes;
+
+    const SAVER: &str = "
+        .org $8000
+            inc $6000
+            lda #$42
+            sta $7FFF
+        done:
+            jmp done
+        ";
+
+    fn temporary_rom_path(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("nes-battery-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory.join(name)
+    }
+
+    fn console() -> Nes {
+        let mut rom = rom(SAVER);
+        rom.has_battery = true;
+        Nes::new(rom)
+    }
+
+    #[test]
+    fn persists_prg_ram_across_runs() {
+        let rom_path = temporary_rom_path("persists.nes");
+        let path = save_path(&rom_path);
+        assert_eq!(path.extension().unwrap(), "sav");
+        let _ = fs::remove_file(&path);
+
+        for run in 1..=3 {
+            let mut nes = console();
+            let mut battery = BatterySave::new(path.clone(), 60);
+            assert_eq!(battery.load(nes.cpu_mut().bus_mut()), Ok(run > 1));
+            assert!(!battery.is_dirty(nes.cpu().bus()));
+
+            nes.cpu_mut().run_until_trap(1000).unwrap();
+            assert_eq!(nes.cpu().bus().peek(0x6000), run);
+            assert!(battery.is_dirty(nes.cpu().bus()));
+
+            assert_eq!(battery.flush(nes.cpu().bus()), Ok(true));
+            assert_eq!(battery.flush(nes.cpu().bus()), Ok(false));
+        }
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!((data[0], data[0x1FFF]), (3, 0x42));
+        assert!(!Path::new(&format!("{}.tmp", path.display())).exists());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn flushes_periodically_and_checks_the_size() {
+        let path = save_path(&temporary_rom_path("periodic.nes"));
+        let _ = fs::remove_file(&path);
+
+        let mut nes = console();
+        let mut battery = BatterySave::new(path.clone(), 10);
+        battery.load(nes.cpu_mut().bus_mut()).unwrap();
+        nes.cpu_mut().run_until_trap(1000).unwrap();
+
+        assert_eq!(battery.flush_periodically(nes.cpu().bus(), 5), Ok(false));
+        assert_eq!(battery.flush_periodically(nes.cpu().bus(), 10), Ok(true));
+        assert!(path.exists());
+
+        fs::write(&path, [0; 100]).unwrap();
+        let error = battery.load(nes.cpu_mut().bus_mut()).unwrap_err();
+        assert!(error.contains("100 bytes"), "{}", error);
+        assert_eq!(nes.cpu().bus().save_data()[0], 1);
+
+        let _ = fs::remove_file(&path);
+    }
+}
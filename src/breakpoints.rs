@@ -0,0 +1,713 @@
+use crate::bus::{BusAccess, BusAccessKind};
+use crate::cpu::CpuState;
+use crate::cpu_flags::CpuFlags;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BreakpointKind {
+    // Stops before the instruction at this address runs
+    Execute(u16),
+    // Stops after an instruction reads from the inclusive range
+    Read { start: u16, end: u16 },
+    // Stops after an instruction writes to the inclusive range
+    Write { start: u16, end: u16 },
+    // Stops before any instruction with this opcode runs
+    Opcode(u8),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StopReason {
+    Breakpoint {
+        id: usize,
+        program_counter: u16,
+    },
+    Watchpoint {
+        id: usize,
+        access: BusAccess,
+    },
+    // The instruction at this address jumped or branched to itself, only
+    // reported when stopping on traps is turned on
+    Trap(u16),
+    // The instruction made an access the bus policy traps on
+    BadAccess {
+        program_counter: u16,
+        access: BusAccess,
+    },
+}
+
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: BreakpointKind,
+    pub enabled: bool,
+    pub hit_count: u32,
+    condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    pub fn condition(&self) -> Option<&str> {
+        self.condition
+            .as_ref()
+            .map(|condition| condition.source.as_str())
+    }
+
+    fn matches(&self, state: &CpuState) -> bool {
+        match &self.condition {
+            Some(condition) => condition.evaluate(state),
+            None => true,
+        }
+    }
+}
+
+pub struct BreakpointManager {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    // Where the last execute breakpoint stopped, so resuming runs past it
+    // instead of stopping again straight away
+    resume_address: Option<u16>,
+    stop_on_traps: bool,
+}
+
+impl Default for BreakpointManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BreakpointManager {
+    pub fn new() -> Self {
+        BreakpointManager {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            resume_address: None,
+            stop_on_traps: false,
+        }
+    }
+
+    // Test ROMs end in a jump to itself, but games idle in one waiting for
+    // an interrupt, so stopping there is off unless asked for
+    pub fn set_stop_on_traps(&mut self, stop: bool) {
+        self.stop_on_traps = stop;
+    }
+
+    pub fn stops_on_traps(&self) -> bool {
+        self.stop_on_traps
+    }
+
+    // Returns the id used to refer to the breakpoint later
+    pub fn add(&mut self, kind: BreakpointKind) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.breakpoints.push(Breakpoint {
+            id,
+            kind,
+            enabled: true,
+            hit_count: 0,
+            condition: None,
+        });
+
+        id
+    }
+
+    // Like `add`, but the breakpoint only stops when `condition` holds, for
+    // example `A == $40 && X > 3`
+    pub fn add_conditional(
+        &mut self,
+        kind: BreakpointKind,
+        condition: &str,
+    ) -> Result<usize, String> {
+        let condition = Condition::parse(condition)?;
+        let id = self.add(kind);
+
+        if let Some(breakpoint) = self.get_mut(id) {
+            breakpoint.condition = Some(condition);
+        }
+
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let length = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.breakpoints.len() != length
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        match self.get_mut(id) {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.id == id)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.breakpoints
+            .iter_mut()
+            .find(|breakpoint| breakpoint.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    // Memory accesses only need to be recorded while a watchpoint is active
+    pub fn has_watchpoints(&self) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.enabled
+                && matches!(
+                    breakpoint.kind,
+                    BreakpointKind::Read { .. } | BreakpointKind::Write { .. }
+                )
+        })
+    }
+
+    // Checked before the instruction at the program counter runs. The first
+    // check after stopping at a breakpoint ignores that same address.
+    pub fn check_execute(&mut self, state: &CpuState, opcode: u8) -> Option<StopReason> {
+        if self.resume_address.take() == Some(state.program_counter) {
+            return None;
+        }
+
+        let breakpoint = self.breakpoints.iter_mut().find(|breakpoint| {
+            let hit = match breakpoint.kind {
+                BreakpointKind::Execute(address) => address == state.program_counter,
+                BreakpointKind::Opcode(value) => value == opcode,
+                _ => false,
+            };
+
+            breakpoint.enabled && hit && breakpoint.matches(state)
+        })?;
+
+        breakpoint.hit_count += 1;
+        self.resume_address = Some(state.program_counter);
+
+        Some(StopReason::Breakpoint {
+            id: breakpoint.id,
+            program_counter: state.program_counter,
+        })
+    }
+
+    // Checked after an instruction with the bus accesses it made, the
+    // condition sees the registers after the instruction
+    pub fn check_accesses(
+        &mut self,
+        state: &CpuState,
+        accesses: &[BusAccess],
+    ) -> Option<StopReason> {
+        for access in accesses {
+            let breakpoint = self.breakpoints.iter_mut().find(|breakpoint| {
+                let hit = match (breakpoint.kind, access.kind) {
+                    (BreakpointKind::Read { start, end }, BusAccessKind::Read)
+                    | (BreakpointKind::Write { start, end }, BusAccessKind::Write) => {
+                        (start..=end).contains(&access.address)
+                    }
+                    _ => false,
+                };
+
+                breakpoint.enabled && hit && breakpoint.matches(state)
+            });
+
+            if let Some(breakpoint) = breakpoint {
+                breakpoint.hit_count += 1;
+
+                return Some(StopReason::Watchpoint {
+                    id: breakpoint.id,
+                    access: *access,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Register {
+    ProgramCounter,
+    StackPointer,
+    Accumulator,
+    X,
+    Y,
+    Status,
+    Flag(CpuFlags),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Expression {
+    Number(i64),
+    Register(Register),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+}
+
+// A boolean expression over the CPU registers. Registers are A, X, Y, S (or
+// SP), PC and P, the single flags N, V, B, D, I, Z and C are 0 or 1. Numbers
+// use the assembler syntax, `$` for hex and `%` for binary.
+pub struct Condition {
+    source: String,
+    expression: Expression,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = ConditionParser {
+            tokens: &tokens,
+            position: 0,
+        };
+
+        let expression = parser.parse(0)?;
+
+        if let Some(token) = tokens.get(parser.position) {
+            return Err(format!("unexpected {:?} in '{}'", token, source));
+        }
+
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expression,
+        })
+    }
+
+    pub fn evaluate(&self, state: &CpuState) -> bool {
+        evaluate(&self.expression, state) != 0
+    }
+}
+
+fn evaluate(expression: &Expression, state: &CpuState) -> i64 {
+    match expression {
+        Expression::Number(value) => *value,
+        Expression::Register(register) => match register {
+            Register::ProgramCounter => state.program_counter as i64,
+            Register::StackPointer => state.stack_pointer as i64,
+            Register::Accumulator => state.register_accumulator as i64,
+            Register::X => state.register_x as i64,
+            Register::Y => state.register_y as i64,
+            Register::Status => state.register_status.bits() as i64,
+            Register::Flag(flag) => state.register_status.contains(*flag) as i64,
+        },
+        Expression::Unary(operator, operand) => {
+            let value = evaluate(operand, state);
+            match *operator {
+                "!" => (value == 0) as i64,
+                "~" => !value,
+                _ => value.wrapping_neg(),
+            }
+        }
+        Expression::Binary(operator, left, right) => {
+            let left = evaluate(left, state);
+
+            // Short circuit so that the right hand side is not evaluated
+            match *operator {
+                "&&" => return (left != 0 && evaluate(right, state) != 0) as i64,
+                "||" => return (left != 0 || evaluate(right, state) != 0) as i64,
+                _ => {}
+            }
+
+            let right = evaluate(right, state);
+            match *operator {
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                "<=" => (left <= right) as i64,
+                ">" => (left > right) as i64,
+                ">=" => (left >= right) as i64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "+" => left.wrapping_add(right),
+                _ => left.wrapping_sub(right),
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Number(i64),
+    Register(Register),
+    Operator(&'static str),
+}
+
+fn register(name: &str) -> Option<Register> {
+    let register = match name.to_ascii_uppercase().as_str() {
+        "PC" => Register::ProgramCounter,
+        "S" | "SP" => Register::StackPointer,
+        "A" => Register::Accumulator,
+        "X" => Register::X,
+        "Y" => Register::Y,
+        "P" => Register::Status,
+        "N" => Register::Flag(CpuFlags::NEGATIVE),
+        "V" => Register::Flag(CpuFlags::OVERFLOW),
+        "B" => Register::Flag(CpuFlags::BREAK),
+        "D" => Register::Flag(CpuFlags::DECIMAL_MODE),
+        "I" => Register::Flag(CpuFlags::INTERRUPT_DISABLE),
+        "Z" => Register::Flag(CpuFlags::ZERO),
+        "C" => Register::Flag(CpuFlags::CARRY),
+        _ => return None,
+    };
+
+    Some(register)
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let characters: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < characters.len() {
+        let character = characters[index];
+
+        if character.is_whitespace() {
+            index += 1;
+            continue;
+        }
+
+        let radix = match character {
+            '$' => Some(16),
+            '%' => Some(2),
+            _ if character.is_ascii_digit() => Some(10),
+            _ => None,
+        };
+
+        if let Some(radix) = radix {
+            let start = if radix == 10 { index } else { index + 1 };
+            let mut end = start;
+            while end < characters.len() && characters[end].is_digit(radix) {
+                end += 1;
+            }
+            let digits: String = characters[start..end].iter().collect();
+            let value = i64::from_str_radix(&digits, radix)
+                .map_err(|_| format!("malformed number in '{}'", source))?;
+            tokens.push(Token::Number(value));
+            index = end;
+            continue;
+        }
+
+        if character.is_ascii_alphabetic() {
+            let start = index;
+            while index < characters.len() && characters[index].is_ascii_alphabetic() {
+                index += 1;
+            }
+            let name: String = characters[start..index].iter().collect();
+            let register = register(&name).ok_or_else(|| format!("unknown register '{}'", name))?;
+            tokens.push(Token::Register(register));
+            continue;
+        }
+
+        let two: String = characters[index..characters.len().min(index + 2)]
+            .iter()
+            .collect();
+        let operator = match two.as_str() {
+            "&&" => Some("&&"),
+            "||" => Some("||"),
+            "==" => Some("=="),
+            "!=" => Some("!="),
+            "<=" => Some("<="),
+            ">=" => Some(">="),
+            _ => None,
+        };
+
+        if let Some(operator) = operator {
+            tokens.push(Token::Operator(operator));
+            index += 2;
+            continue;
+        }
+
+        let operator = match character {
+            '<' => "<",
+            '>' => ">",
+            '&' => "&",
+            '|' => "|",
+            '^' => "^",
+            '+' => "+",
+            '-' => "-",
+            '!' => "!",
+            '~' => "~",
+            '(' => "(",
+            ')' => ")",
+            _ => return Err(format!("unexpected '{}' in '{}'", character, source)),
+        };
+
+        tokens.push(Token::Operator(operator));
+        index += 1;
+    }
+
+    Ok(tokens)
+}
+
+struct ConditionParser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl ConditionParser<'_> {
+    fn binding_power(operator: &str) -> Option<u8> {
+        match operator {
+            "||" => Some(1),
+            "&&" => Some(2),
+            "==" | "!=" | "<" | "<=" | ">" | ">=" => Some(3),
+            "|" => Some(4),
+            "^" => Some(5),
+            "&" => Some(6),
+            "+" | "-" => Some(7),
+            _ => None,
+        }
+    }
+
+    // Precedence climbing, the same as the assembler's expressions
+    fn parse(&mut self, min_power: u8) -> Result<Expression, String> {
+        let mut left = self.parse_unary()?;
+
+        while let Some(Token::Operator(operator)) = self.tokens.get(self.position) {
+            let power = match Self::binding_power(operator) {
+                Some(power) if power > min_power => power,
+                _ => break,
+            };
+
+            self.position += 1;
+            let right = self.parse(power)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("condition ends unexpectedly")?;
+        self.position += 1;
+
+        let expression = match token {
+            Token::Number(value) => Expression::Number(value),
+            Token::Register(register) => Expression::Register(register),
+            Token::Operator(operator @ ("!" | "~" | "-")) => {
+                Expression::Unary(operator, Box::new(self.parse_unary()?))
+            }
+            Token::Operator("(") => {
+                let expression = self.parse(0)?;
+                if self.tokens.get(self.position) != Some(&Token::Operator(")")) {
+                    return Err("missing ')'".to_string());
+                }
+                self.position += 1;
+                expression
+            }
+            Token::Operator(operator) => return Err(format!("unexpected '{}'", operator)),
+        };
+
+        Ok(expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BreakpointKind, BreakpointManager, Condition, StopReason};
+    use crate::bus::{BusAccess, BusAccessKind};
+    use crate::cpu::tests::cpu_with_program;
+    use crate::cpu::CpuState;
+    use crate::cpu_flags::CpuFlags;
+
+    fn state(register_accumulator: u8, register_x: u8) -> CpuState {
+        CpuState {
+            program_counter: 0xC000,
+            stack_pointer: 0xFD,
+            register_accumulator,
+            register_x,
+            register_y: 0,
+            register_status: CpuFlags::CARRY,
+        }
+    }
+
+    #[test]
+    fn conditions_evaluate_against_registers() {
+        let condition = Condition::parse("A == $40 && X > 3").unwrap();
+        assert!(condition.evaluate(&state(0x40, 4)));
+        assert!(!condition.evaluate(&state(0x40, 3)));
+        assert!(!condition.evaluate(&state(0x41, 4)));
+
+        let condition = Condition::parse("(a & %1100) != 0 || !c").unwrap();
+        assert!(condition.evaluate(&state(0b0100, 0)));
+        assert!(!condition.evaluate(&state(0b0011, 0)));
+
+        assert!(Condition::parse("pc >= $C000 && sp == 253")
+            .unwrap()
+            .evaluate(&state(0, 0)));
+        // Arithmetic wraps rather than overflowing
+        assert!(Condition::parse("-(0-$7FFFFFFFFFFFFFFF-1) < 0")
+            .unwrap()
+            .evaluate(&state(0, 0)));
+
+        assert!(Condition::parse("A == ").is_err());
+        assert!(Condition::parse("Q == 1").is_err());
+        assert!(Condition::parse("(A == 1").is_err());
+    }
+
+    #[test]
+    fn execute_breakpoints_stop_before_the_instruction() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+            loop:
+                inx
+            target:
+                cpx #5
+                bne loop
+            done:
+                jmp done
+            ",
+        );
+
+        let mut breakpoints = BreakpointManager::new();
+        breakpoints.set_stop_on_traps(true);
+        let id = breakpoints
+            .add_conditional(BreakpointKind::Execute(0x0201), "X == 3")
+            .unwrap();
+
+        let reason = cpu.run_with_breakpoints(&mut breakpoints);
+        assert_eq!(
+            reason,
+            StopReason::Breakpoint {
+                id,
+                program_counter: 0x0201
+            }
+        );
+        assert_eq!(cpu.state().register_x, 3);
+
+        breakpoints.set_enabled(id, false);
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Trap(0x0205)
+        );
+        assert_eq!(breakpoints.get(id).unwrap().hit_count, 1);
+    }
+
+    #[test]
+    fn watchpoints_and_opcode_breakpoints() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+                lda #$40
+                sta $0300
+                lda $0310
+                nop
+            done:
+                jmp done
+            ",
+        );
+
+        let mut breakpoints = BreakpointManager::new();
+        breakpoints.set_stop_on_traps(true);
+        let write = breakpoints.add(BreakpointKind::Write {
+            start: 0x0300,
+            end: 0x030F,
+        });
+        let read = breakpoints.add(BreakpointKind::Read {
+            start: 0x0310,
+            end: 0x0310,
+        });
+        let nop = breakpoints.add(BreakpointKind::Opcode(0xEA));
+
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Watchpoint {
+                id: write,
+                access: BusAccess {
+                    address: 0x0300,
+                    value: 0x40,
+                    kind: BusAccessKind::Write
+                }
+            }
+        );
+        assert_eq!(cpu.state().program_counter, 0x0205);
+
+        assert!(matches!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Watchpoint { id, .. } if id == read
+        ));
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Breakpoint {
+                id: nop,
+                program_counter: 0x0208
+            }
+        );
+
+        assert!(breakpoints.remove(nop));
+        assert!(!breakpoints.remove(nop));
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Trap(0x0209)
+        );
+    }
+
+    #[test]
+    fn idle_loops_only_stop_when_asked() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+            idle:
+                jmp idle
+            nmi:
+                inx
+                rti
+            .org $FFFA
+                .word nmi
+            ",
+        );
+        cpu.bus_mut().set_nmi_line(true);
+
+        let mut breakpoints = BreakpointManager::new();
+        let id = breakpoints.add(BreakpointKind::Execute(0x0203));
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Breakpoint {
+                id,
+                program_counter: 0x0203
+            }
+        );
+
+        breakpoints.set_stop_on_traps(true);
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Trap(0x0200)
+        );
+    }
+
+    #[test]
+    fn read_watchpoints_see_reads_of_the_instructions_own_bytes() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+                lda $0201
+            done:
+                jmp done
+            ",
+        );
+
+        let mut breakpoints = BreakpointManager::new();
+        let id = breakpoints.add(BreakpointKind::Read {
+            start: 0x0201,
+            end: 0x0201,
+        });
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Watchpoint {
+                id,
+                access: BusAccess {
+                    address: 0x0201,
+                    value: 0x01,
+                    kind: BusAccessKind::Read
+                }
+            }
+        );
+    }
+}
@@ -1,36 +1,492 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use bitflags::bitflags;
+use log::{error, info, warn};
+
+use crate::battery::SaveMemory;
+use crate::cartridge::Rom;
 use crate::constants::{
-    PPU_REGISTERS, PPU_REGISTERS_MIRRORS_END, RAM_MIRRORS_END, RAM_SIZE, RAM_START,
+    LogTargets, APU_FRAME_COUNTER, APU_REGISTERS, APU_REGISTERS_END, CONTROLLER_1, CONTROLLER_2,
+    CONTROLLER_OPEN_BUS_MASK, FLAT_MEMORY_SIZE, PPU_REGISTERS, PPU_REGISTERS_MIRRORS_END,
+    PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START, RAM_MIRRORS_END, RAM_SIZE, RAM_START,
 };
+use crate::controller::{Buttons, Controller};
+use crate::memory::Memory;
+use crate::rng::Rng;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BusAccessKind {
+    Read,
+    Write,
+    // An opcode fetch. Only observers see these, recordings log them as
+    // reads like the hardware does.
+    Execute,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BusAccess {
+    pub address: u16,
+    pub value: u8,
+    pub kind: BusAccessKind,
+}
+
+impl fmt::Display for BusAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            BusAccessKind::Read => "read",
+            BusAccessKind::Write => "write",
+            BusAccessKind::Execute => "execute",
+        };
+        write!(f, "{} ${:02X} at ${:04X}", kind, self.value, self.address)
+    }
+}
+
+// What happens on a read from an address nothing responds to, or a write to
+// one that ignores it
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum BadAccessPolicy {
+    Ignore,
+    // Warns the first time each address is hit
+    #[default]
+    LogOnce,
+    LogAlways,
+    // Stops `Cpu::run_with_breakpoints` after the instruction
+    Trap,
+    // Makes `Cpu::try_step` fail, and traps like `Trap`
+    Error,
+}
+
+bitflags! {
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct ObservedKinds: u8 {
+        const READ    = 0b001;
+        const WRITE   = 0b010;
+        const EXECUTE = 0b100;
+    }
+}
+
+impl ObservedKinds {
+    fn contains_kind(&self, kind: BusAccessKind) -> bool {
+        match kind {
+            BusAccessKind::Read => self.contains(ObservedKinds::READ),
+            BusAccessKind::Write => self.contains(ObservedKinds::WRITE),
+            BusAccessKind::Execute => self.contains(ObservedKinds::EXECUTE),
+        }
+    }
+}
+
+pub type ObserverCallback = Box<dyn FnMut(&BusAccess) + Send>;
+
+struct Observer {
+    id: usize,
+    start: u16,
+    end: u16,
+    kinds: ObservedKinds,
+    callback: ObserverCallback,
+}
 
 pub struct Bus {
     cpu_ram: [u8; RAM_SIZE as usize],
+    // Only used by the flat test mode, where the whole address space is RAM
+    flat_ram: Option<Vec<u8>>,
+    prg_rom: Vec<u8>,
+    // Empty when the cartridge has none
+    prg_ram: Vec<u8>,
+    // Bumped by every PRG RAM write, for saving battery RAM only when needed
+    prg_ram_revision: u64,
+    recorded_accesses: Option<Vec<BusAccess>>,
+    // The last value driven on the data bus. Reads from addresses nothing
+    // responds to see it again.
+    open_bus: u8,
+    controllers: [Controller; 2],
+    // Driven by the PPU, the APU and mappers once they exist
+    nmi_line: bool,
+    irq_line: bool,
+    bad_access_policy: BadAccessPolicy,
+    logged_bad_accesses: HashSet<u16>,
+    // The first bad access since it was last taken, for the trapping policies
+    bad_access: Option<BusAccess>,
+    // Checked with a single length test, so accesses cost nothing extra while
+    // no observer is registered
+    observers: Vec<Observer>,
+    next_observer_id: usize,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Bus {
     pub fn new() -> Self {
         Bus {
             cpu_ram: [0; RAM_SIZE as usize],
+            flat_ram: None,
+            prg_rom: Vec::new(),
+            prg_ram: Vec::new(),
+            prg_ram_revision: 0,
+            recorded_accesses: None,
+            open_bus: 0,
+            controllers: [Controller::new(); 2],
+            nmi_line: false,
+            irq_line: false,
+            bad_access_policy: BadAccessPolicy::default(),
+            logged_bad_accesses: HashSet::new(),
+            bad_access: None,
+            observers: Vec::new(),
+            next_observer_id: 1,
+        }
+    }
+
+    // Maps the cartridge PRG ROM at $8000, as mapper 0 (NROM) does. A 16 KiB
+    // ROM is mirrored into both halves. PRG RAM is mirrored across $6000-$7FFF.
+    pub fn with_rom(rom: Rom) -> Self {
+        Bus {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; rom.prg_ram_size],
+            ..Bus::new()
+        }
+    }
+
+    // A plain 64 KiB memory map with no mirroring or devices, for running
+    // generic 6502 test programs
+    pub fn new_flat() -> Self {
+        Bus {
+            flat_ram: Some(vec![0; FLAT_MEMORY_SIZE]),
+            ..Bus::new()
+        }
+    }
+
+    // Copies `bytes` into memory from `address` on, as loading a program
+    // does. Nothing is recorded or observed.
+    pub fn load_program(&mut self, address: u16, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.write_mapped(address.wrapping_add(offset as u16), *byte);
+        }
+    }
+
+    // The state after turning the console off and on. Battery backed PRG RAM
+    // keeps its contents, the rest is zeroed or, with a seed, filled with a
+    // repeatable stand in for the garbage real RAM starts with.
+    pub fn power_on(&mut self, keep_prg_ram: bool, seed: Option<u64>) {
+        let mut rng = seed.map(Rng::new);
+        let mut fill = |memory: &mut [u8]| match &mut rng {
+            Some(rng) => rng.fill(memory),
+            None => memory.fill(0),
+        };
+
+        fill(&mut self.cpu_ram);
+        if !keep_prg_ram {
+            fill(&mut self.prg_ram);
+            self.prg_ram_revision += 1;
+        }
+        self.controllers = [Controller::new(); 2];
+        self.open_bus = 0;
+        self.nmi_line = false;
+        self.irq_line = false;
+    }
+
+    // Starts logging every read and write in the order they happen
+    pub fn start_recording(&mut self) {
+        self.recorded_accesses = Some(Vec::new());
+    }
+
+    // Stops logging and returns everything recorded since starting
+    pub fn stop_recording(&mut self) -> Vec<BusAccess> {
+        self.recorded_accesses.take().unwrap_or_default()
+    }
+
+    fn record(&mut self, address: u16, value: u8, kind: BusAccessKind) {
+        if let Some(accesses) = &mut self.recorded_accesses {
+            accesses.push(BusAccess {
+                address,
+                value,
+                kind,
+            });
+        }
+    }
+
+    pub fn set_bad_access_policy(&mut self, policy: BadAccessPolicy) {
+        self.bad_access_policy = policy;
+        self.logged_bad_accesses.clear();
+    }
+
+    pub fn bad_access_policy(&self) -> BadAccessPolicy {
+        self.bad_access_policy
+    }
+
+    pub fn take_bad_access(&mut self) -> Option<BusAccess> {
+        self.bad_access.take()
+    }
+
+    fn report_bad_access(&mut self, address: u16, value: u8, kind: BusAccessKind, what: &str) {
+        let access = BusAccess {
+            address,
+            value,
+            kind,
+        };
+
+        match self.bad_access_policy {
+            BadAccessPolicy::Ignore => {}
+            BadAccessPolicy::LogOnce => {
+                if self.logged_bad_accesses.insert(address) {
+                    warn!(target: LogTargets::BUS, "Bad {}, {}", access, what);
+                }
+            }
+            BadAccessPolicy::LogAlways => {
+                warn!(target: LogTargets::BUS, "Bad {}, {}", access, what);
+            }
+            BadAccessPolicy::Trap | BadAccessPolicy::Error => {
+                if self.bad_access_policy == BadAccessPolicy::Error {
+                    error!(target: LogTargets::BUS, "Bad {}, {}", access, what);
+                }
+                self.bad_access.get_or_insert(access);
+            }
+        }
+    }
+
+    // Accesses to devices the console has but the emulator does not yet. Every
+    // game touches them, so they are logged under the device's target and
+    // never trap.
+    fn report_unemulated(&mut self, target: &str, address: u16, value: u8, kind: BusAccessKind) {
+        let access = BusAccess {
+            address,
+            value,
+            kind,
+        };
+
+        let log = match self.bad_access_policy {
+            BadAccessPolicy::Ignore => false,
+            BadAccessPolicy::LogAlways => true,
+            _ => self.logged_bad_accesses.insert(address),
+        };
+        if log {
+            info!(
+                target: target,
+                "Ignored {}, the {} is not emulated",
+                access,
+                target.to_uppercase()
+            );
+        }
+    }
+
+    // Calls `callback` for every access of one of `kinds` to an address in
+    // `start..=end`, after reads and before writes take effect. Returns an id
+    // for removing it again.
+    pub fn add_observer<F: FnMut(&BusAccess) + Send + 'static>(
+        &mut self,
+        start: u16,
+        end: u16,
+        kinds: ObservedKinds,
+        callback: F,
+    ) -> usize {
+        let id = self.next_observer_id;
+        self.next_observer_id += 1;
+        self.observers.push(Observer {
+            id,
+            start,
+            end,
+            kinds,
+            callback: Box::new(callback),
+        });
+        id
+    }
+
+    pub fn remove_observer(&mut self, id: usize) -> bool {
+        let count = self.observers.len();
+        self.observers.retain(|observer| observer.id != id);
+        self.observers.len() != count
+    }
+
+    fn notify(&mut self, address: u16, value: u8, kind: BusAccessKind) {
+        let access = BusAccess {
+            address,
+            value,
+            kind,
+        };
+
+        for observer in &mut self.observers {
+            if (observer.start..=observer.end).contains(&address)
+                && observer.kinds.contains_kind(kind)
+            {
+                (observer.callback)(&access);
+            }
+        }
+    }
+
+    pub fn mem_read(&mut self, address: u16) -> u8 {
+        let value = self.read_mapped(address);
+        self.record(address, value, BusAccessKind::Read);
+        if !self.observers.is_empty() {
+            self.notify(address, value, BusAccessKind::Read);
+        }
+        value
+    }
+
+    pub fn mem_write(&mut self, address: u16, value: u8) {
+        self.open_bus = value;
+        self.record(address, value, BusAccessKind::Write);
+        if !self.observers.is_empty() {
+            self.notify(address, value, BusAccessKind::Write);
+        }
+        self.write_mapped(address, value);
+    }
+
+    // Reads memory without any side effects, nothing is recorded and device
+    // registers are left alone. Used by debuggers and disassemblers.
+    pub fn peek(&self, address: u16) -> u8 {
+        if let Some(flat_ram) = &self.flat_ram {
+            return flat_ram[address as usize];
+        }
+
+        match address {
+            RAM_START..=RAM_MIRRORS_END => self.cpu_ram[(address & (RAM_SIZE - 1)) as usize],
+            CONTROLLER_1 | CONTROLLER_2 => {
+                let controller = &self.controllers[(address - CONTROLLER_1) as usize];
+                self.open_bus & CONTROLLER_OPEN_BUS_MASK | controller.peek()
+            }
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                self.prg_ram[self.prg_ram_offset(address)]
+            }
+            PRG_ROM_START..=PRG_ROM_END => self.read_prg_rom(address),
+            _ => self.open_bus,
+        }
+    }
+
+    // Writes memory without any side effects, the counterpart of `peek`. Only
+    // RAM can be changed this way, ROM and device registers are refused.
+    pub fn poke(&mut self, address: u16, value: u8) -> Result<(), String> {
+        if let Some(flat_ram) = &mut self.flat_ram {
+            flat_ram[address as usize] = value;
+            return Ok(());
+        }
+
+        match address {
+            RAM_START..=RAM_MIRRORS_END => {
+                self.cpu_ram[(address & (RAM_SIZE - 1)) as usize] = value;
+            }
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                let offset = self.prg_ram_offset(address);
+                self.prg_ram[offset] = value;
+                self.prg_ram_revision += 1;
+            }
+            PRG_ROM_START..=PRG_ROM_END if self.prg_rom_offset(address).is_some() => {
+                return Err(format!(
+                    "${:04X} is in PRG ROM, which is read only",
+                    address
+                ));
+            }
+            _ => return Err(format!("${:04X} is not RAM", address)),
+        }
+
+        Ok(())
+    }
+
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus
+    }
+
+    // Holds `buttons` down on the controller in `port`, 0 or 1
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        self.controllers[port].set_buttons(buttons);
+    }
+
+    pub fn buttons(&self, port: usize) -> Buttons {
+        self.controllers[port].buttons()
+    }
+
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        self.nmi_line = asserted;
+    }
+
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    // The offset into the PRG ROM that is mapped at `address` right now
+    pub fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        if self.flat_ram.is_some() || self.prg_rom.is_empty() || address < PRG_ROM_START {
+            return None;
         }
+
+        Some((address - PRG_ROM_START) as usize % self.prg_rom.len())
     }
 
-    pub fn mem_read(&self, address: u16) -> u8 {
+    // The first CPU address that `offset` into the PRG ROM is mapped at
+    pub fn prg_rom_address(&self, offset: usize) -> Option<u16> {
+        if self.flat_ram.is_some() || offset >= self.prg_rom.len() {
+            return None;
+        }
+
+        Some(PRG_ROM_START + offset as u16)
+    }
+
+    fn prg_ram_offset(&self, address: u16) -> usize {
+        (address - PRG_RAM_START) as usize % self.prg_ram.len()
+    }
+
+    // Without a cartridge nothing drives the bus
+    fn read_prg_rom(&self, address: u16) -> u8 {
+        match self.prg_rom_offset(address) {
+            Some(offset) => self.prg_rom[offset],
+            None => self.open_bus,
+        }
+    }
+
+    fn read_mapped(&mut self, address: u16) -> u8 {
+        let value = self.read_device(address);
+        self.open_bus = value;
+        value
+    }
+
+    fn read_device(&mut self, address: u16) -> u8 {
+        if let Some(flat_ram) = &self.flat_ram {
+            return flat_ram[address as usize];
+        }
+
         match address {
             RAM_START..=RAM_MIRRORS_END => {
                 let mirror_down_addr = address & (RAM_SIZE - 1);
                 let mirror_down_addr = mirror_down_addr as usize;
                 self.cpu_ram[mirror_down_addr]
             }
+            // Until the PPU and APU exist their registers read as open bus
             PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                todo!("Handle PPU Memory");
+                let value = self.open_bus;
+                self.report_unemulated(LogTargets::PPU, address, value, BusAccessKind::Read);
+                value
             }
+            APU_REGISTERS..=APU_REGISTERS_END => {
+                let value = self.open_bus;
+                self.report_unemulated(LogTargets::APU, address, value, BusAccessKind::Read);
+                value
+            }
+            // Controllers only drive bit 0, the rest is open bus
+            CONTROLLER_1 | CONTROLLER_2 => {
+                let controller = &mut self.controllers[(address - CONTROLLER_1) as usize];
+                self.open_bus & CONTROLLER_OPEN_BUS_MASK | controller.read()
+            }
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                self.prg_ram[self.prg_ram_offset(address)]
+            }
+            PRG_ROM_START..=PRG_ROM_END => self.read_prg_rom(address),
             _ => {
-                println!("Invalid Memory access at {}", address);
-                0
+                let value = self.open_bus;
+                self.report_bad_access(address, value, BusAccessKind::Read, "nothing is mapped");
+                value
             }
         }
     }
 
-    pub fn mem_write(&mut self, address: u16, value: u8) {
+    fn write_mapped(&mut self, address: u16, value: u8) {
+        if let Some(flat_ram) = &mut self.flat_ram {
+            flat_ram[address as usize] = value;
+            return;
+        }
+
         match address {
             RAM_START..=RAM_MIRRORS_END => {
                 let mirror_down_addr = address & (RAM_SIZE - 1);
@@ -38,11 +494,344 @@ impl Bus {
                 self.cpu_ram[mirror_down_addr] = value;
             }
             PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                todo!("Handle PPU Memory");
+                self.report_unemulated(LogTargets::PPU, address, value, BusAccessKind::Write);
+            }
+            APU_REGISTERS..=APU_REGISTERS_END | APU_FRAME_COUNTER => {
+                self.report_unemulated(LogTargets::APU, address, value, BusAccessKind::Write);
+            }
+            // The strobe goes to both ports
+            CONTROLLER_1 => {
+                for controller in &mut self.controllers {
+                    controller.write(value);
+                }
+            }
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                let offset = self.prg_ram_offset(address);
+                self.prg_ram[offset] = value;
+                self.prg_ram_revision += 1;
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                self.report_bad_access(
+                    address,
+                    value,
+                    BusAccessKind::Write,
+                    "PRG ROM is read only",
+                );
             }
             _ => {
-                println!("Invalid Memory access at {}", address);
+                self.report_bad_access(address, value, BusAccessKind::Write, "nothing is mapped");
             }
         }
     }
 }
+
+impl Memory for Bus {
+    fn read(&mut self, address: u16) -> u8 {
+        self.mem_read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.mem_write(address, value)
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        Bus::peek(self, address)
+    }
+
+    // Observers see opcode fetches as executes rather than reads
+    fn fetch_opcode(&mut self, address: u16) -> u8 {
+        let value = self.read_mapped(address);
+        self.record(address, value, BusAccessKind::Read);
+        if !self.observers.is_empty() {
+            self.notify(address, value, BusAccessKind::Execute);
+        }
+        value
+    }
+
+    // Nothing else on the bus is clocked yet
+    fn tick(&mut self, _cycles: u8) {}
+
+    fn nmi_line(&self) -> bool {
+        self.nmi_line
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_line
+    }
+}
+
+// PRG RAM is all an NROM cartridge has to keep
+impl SaveMemory for Bus {
+    fn save_data(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != self.prg_ram.len() {
+            return Err(format!(
+                "Save file is {} bytes, the cartridge has {} bytes of save RAM",
+                data.len(),
+                self.prg_ram.len()
+            ));
+        }
+
+        self.prg_ram.copy_from_slice(data);
+        self.prg_ram_revision += 1;
+        Ok(())
+    }
+
+    fn save_revision(&self) -> u64 {
+        self.prg_ram_revision
+    }
+}
+
+// RAM, the controllers and the bus latches. The cartridge ROM comes from the ROM file, and
+// observers, recordings and the bad access policy belong to the session.
+impl Savestate for Bus {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.cpu_ram);
+        writer.write_bool(self.flat_ram.is_some());
+        if let Some(flat_ram) = &self.flat_ram {
+            writer.write_bytes(flat_ram);
+        }
+        writer.write_bytes(&self.prg_ram);
+        for controller in &self.controllers {
+            let (strobe, shift) = controller.latch();
+            writer.write_u8(controller.buttons().bits());
+            writer.write_bool(strobe);
+            writer.write_u8(shift);
+        }
+        writer.write_u8(self.open_bus);
+        writer.write_bool(self.nmi_line);
+        writer.write_bool(self.irq_line);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.cpu_ram)?;
+
+        let flat = reader.read_bool()?;
+        match &mut self.flat_ram {
+            Some(flat_ram) if flat => reader.read_into(flat_ram)?,
+            None if !flat => {}
+            _ => return Err("Save state is for a different memory map".to_string()),
+        }
+        reader.read_into(&mut self.prg_ram)?;
+        self.prg_ram_revision += 1;
+        for controller in &mut self.controllers {
+            controller.set_buttons(Buttons::from_bits_retain(reader.read_u8()?));
+            let strobe = reader.read_bool()?;
+            controller.set_latch(strobe, reader.read_u8()?);
+        }
+
+        self.open_bus = reader.read_u8()?;
+        self.nmi_line = reader.read_bool()?;
+        self.irq_line = reader.read_bool()?;
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::BadAccessPolicy;
+    use super::{Bus, BusAccess, BusAccessKind, ObservedKinds};
+    use crate::asm::assemble;
+    use crate::battery::SaveMemory;
+    use crate::breakpoints::{BreakpointManager, StopReason};
+    use crate::cartridge::{Mirroring, Rom};
+    use crate::constants::PRG_ROM_PAGE_SIZE;
+    use crate::cpu::tests::cpu_with_program;
+    use crate::cpu::{Cpu, CpuState};
+    use crate::nes::tests::rom;
+
+    #[test]
+    fn observers_see_matching_accesses() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+                lda #$42
+                sta $10
+                lda $10
+                sta $20
+            ",
+        );
+        let bus = cpu.bus_mut();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let log = Arc::clone(&seen);
+        bus.add_observer(0x0010, 0x001F, ObservedKinds::all(), move |access| {
+            log.lock().unwrap().push(*access)
+        });
+        let log = Arc::clone(&seen);
+        let executes = bus.add_observer(0x0200, 0x02FF, ObservedKinds::EXECUTE, move |access| {
+            log.lock().unwrap().push(*access)
+        });
+
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert!(cpu.bus_mut().remove_observer(executes));
+        assert!(!cpu.bus_mut().remove_observer(executes));
+        cpu.step();
+
+        let access = |address, value, kind| BusAccess {
+            address,
+            value,
+            kind,
+        };
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                access(0x0200, 0xA9, BusAccessKind::Execute),
+                access(0x0202, 0x85, BusAccessKind::Execute),
+                access(0x0010, 0x42, BusAccessKind::Write),
+                access(0x0204, 0xA5, BusAccessKind::Execute),
+                access(0x0010, 0x42, BusAccessKind::Read),
+            ]
+        );
+    }
+
+    #[test]
+    fn unmapped_reads_return_the_last_bus_value() {
+        let mut cpu = Cpu::new(Bus::with_rom(rom("
+            .org $8000
+            start:
+                lda $5000
+                sta $00
+                lda $4016
+                sta $01
+                lda #$00
+                sta $02
+                lda $4017
+                sta $03
+            done:
+                jmp done
+            ")));
+        cpu.reset();
+        cpu.run_until_trap(1000).unwrap();
+
+        // The high byte of the operand is the last thing on the bus, $50 for
+        // `lda $5000` and $40 for `lda $4016`
+        let bus = cpu.bus();
+        assert_eq!(bus.peek(0x0000), 0x50);
+        assert_eq!(bus.peek(0x0001), 0x40);
+        assert_eq!(bus.peek(0x0003), 0x40);
+
+        // The trap's `jmp done` last fetched the $80 of its target
+        assert_eq!(bus.open_bus(), 0x80);
+        assert_eq!(bus.peek(0x5000), 0x80);
+    }
+
+    #[test]
+    fn pokes_only_change_ram() {
+        let mut bus = Bus::with_rom(rom(".org $8000\n nop"));
+        bus.start_recording();
+
+        bus.poke(0x0801, 0x12).unwrap();
+        assert_eq!(bus.peek(0x0001), 0x12);
+        let revision = bus.save_revision();
+        bus.poke(0x6000, 0x34).unwrap();
+        assert_eq!(bus.peek(0x6000), 0x34);
+        assert_ne!(bus.save_revision(), revision);
+
+        assert!(bus.poke(0x8000, 0x56).unwrap_err().contains("read only"));
+        assert_eq!(bus.peek(0x8000), 0xEA);
+        assert!(bus.poke(0x4016, 0x01).is_err());
+        assert!(bus.poke(0x5000, 0x78).is_err());
+
+        assert_eq!(bus.stop_recording(), []);
+        assert_eq!(bus.open_bus(), 0);
+    }
+
+    const ROM_WRITER: &str = "
+        .org $0200
+            lda #$12
+            sta $8000
+            lda $5000
+        done:
+            jmp done
+        ";
+
+    // Runs `source` from RAM on a cartridge bus, so stray accesses find
+    // nothing mapped
+    fn cartridge_cpu(source: &str, policy: BadAccessPolicy) -> Cpu {
+        let assembly = assemble(source).unwrap();
+
+        let mut bus = Bus::with_rom(Rom {
+            prg_rom: vec![0; PRG_ROM_PAGE_SIZE],
+            chr_rom: Vec::new(),
+            mapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+            has_battery: false,
+            prg_ram_size: 0,
+        });
+        bus.load_program(assembly.origin, &assembly.bytes);
+        bus.set_bad_access_policy(policy);
+
+        let mut cpu = Cpu::new(bus);
+        cpu.set_state(CpuState {
+            program_counter: 0x0200,
+            ..cpu.state()
+        });
+        cpu
+    }
+
+    #[test]
+    fn bad_access_policies() {
+        let mut cpu = cartridge_cpu(ROM_WRITER, BadAccessPolicy::Ignore);
+        assert_eq!(cpu.run_until_trap(1000), Ok(0x0208));
+        assert_eq!(cpu.bus_mut().take_bad_access(), None);
+
+        let mut cpu = cartridge_cpu(ROM_WRITER, BadAccessPolicy::Trap);
+        cpu.try_step().unwrap();
+        assert_eq!(cpu.try_step(), Ok(4));
+        let mut breakpoints = BreakpointManager::new();
+        breakpoints.set_stop_on_traps(true);
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::BadAccess {
+                program_counter: 0x0205,
+                access: BusAccess {
+                    address: 0x5000,
+                    value: 0x50,
+                    kind: BusAccessKind::Read
+                }
+            }
+        );
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Trap(0x0208)
+        );
+
+        // The PPU and APU are not emulated, but games use them all the time, so
+        // their registers read as open bus and never trap
+        let mut cpu = cartridge_cpu(
+            "
+            .org $0200
+                lda #$80
+                sta $3FF8
+                sta $4000
+                sta $4017
+                lda $2002
+                lda $4015
+            done:
+                jmp done
+            ",
+            BadAccessPolicy::Trap,
+        );
+        assert_eq!(
+            cpu.run_with_breakpoints(&mut breakpoints),
+            StopReason::Trap(0x0211)
+        );
+        assert_eq!(cpu.state().register_accumulator, 0x40);
+
+        let mut cpu = cartridge_cpu(ROM_WRITER, BadAccessPolicy::Error);
+        cpu.try_step().unwrap();
+        assert_eq!(
+            cpu.try_step(),
+            Err("Bad write $12 at $8000 by $0202".to_string())
+        );
+    }
+}
@@ -0,0 +1,293 @@
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FrameKind {
+    Subroutine,
+    Interrupt,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Frame {
+    pub kind: FrameKind,
+    // The JSR, or the instruction that was interrupted
+    pub call_site: u16,
+    // Where the subroutine or interrupt handler starts
+    pub target: u16,
+    pub return_address: u16,
+    // The stack pointer before the return address was pushed
+    pub stack_pointer: u8,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BacktraceEntry {
+    // 0 is the innermost frame
+    pub depth: usize,
+    // The current program counter for the innermost frame, the return address
+    // for the others
+    pub address: u16,
+    // The subroutine or handler the address is in, unknown for the outermost
+    pub function: Option<u16>,
+    // Set when this frame was interrupted rather than making a call
+    pub interrupted: bool,
+}
+
+// The logical call chain, kept alongside the hardware stack. Frames are
+// dropped once the stack pointer climbs back to where they were called
+// from, not only on RTS and RTI, so code that pulls its return address and
+// jumps elsewhere or uses RTS as a jump table keeps the chain in sync.
+#[derive(Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, frame: Frame) {
+        self.frames.push(frame);
+    }
+
+    // Called whenever the stack pointer moves up
+    pub fn unwind(&mut self, stack_pointer: u8) {
+        while let Some(frame) = self.frames.last() {
+            if stack_pointer < frame.stack_pointer {
+                break;
+            }
+            self.frames.pop();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // Outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    // Innermost first, starting at `program_counter`
+    pub fn backtrace(&self, program_counter: u16) -> Vec<BacktraceEntry> {
+        let mut entries = Vec::with_capacity(self.frames.len() + 1);
+        let mut address = program_counter;
+        let mut interrupted = false;
+
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            entries.push(BacktraceEntry {
+                depth,
+                address,
+                function: Some(frame.target),
+                interrupted,
+            });
+
+            address = frame.return_address;
+            interrupted = frame.kind == FrameKind::Interrupt;
+        }
+
+        entries.push(BacktraceEntry {
+            depth: self.frames.len(),
+            address,
+            function: None,
+            interrupted,
+        });
+
+        entries
+    }
+
+    // One line per frame, for the debugger and crash dumps. `label` names
+    // addresses that have a symbol.
+    pub fn format_backtrace<L: Fn(u16) -> Option<String>>(
+        &self,
+        program_counter: u16,
+        label: L,
+    ) -> String {
+        let name = |address: u16| label(address).unwrap_or_else(|| format!("${:04X}", address));
+
+        let lines: Vec<String> = self
+            .backtrace(program_counter)
+            .iter()
+            .map(|entry| {
+                let mut line = format!("#{:<2} ${:04X}", entry.depth, entry.address);
+
+                if let Some(function) = entry.function {
+                    line.push_str(&format!(" in {}", name(function)));
+                }
+                if entry.interrupted {
+                    line.push_str(" <interrupted>");
+                }
+
+                line
+            })
+            .collect();
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+    use crate::call_stack::{BacktraceEntry, FrameKind};
+    use crate::constants::IRQ_INTERRUPT_VECTOR_ADDRESS;
+    use crate::cpu::tests::cpu_with_program;
+    use crate::cpu::Cpu;
+
+    fn cpu(source: &str) -> Cpu {
+        let handler = assemble(source).unwrap().labels["handler"];
+        let mut cpu = cpu_with_program(source);
+        cpu.bus_mut()
+            .load_program(IRQ_INTERRUPT_VECTOR_ADDRESS, &handler.to_le_bytes());
+        cpu
+    }
+
+    fn run_to(cpu: &mut Cpu, address: u16) {
+        while cpu.state().program_counter != address {
+            cpu.step();
+        }
+    }
+
+    #[test]
+    fn follows_calls_interrupts_and_returns() {
+        let mut cpu = cpu("
+            .org $0200
+                jsr outer
+            done:
+                jmp done
+            outer:
+                jsr inner
+                rts
+            inner:
+                brk
+                .byte 0
+            after:
+                rts
+            handler:
+                nop
+                rti
+            ");
+
+        run_to(&mut cpu, 0x020D);
+        let call_stack = cpu.call_stack();
+        assert_eq!(call_stack.depth(), 3);
+        assert_eq!(call_stack.frames()[2].kind, FrameKind::Interrupt);
+        assert_eq!(call_stack.frames()[2].return_address, 0x020C);
+        assert_eq!(
+            call_stack.backtrace(0x020D),
+            vec![
+                BacktraceEntry {
+                    depth: 0,
+                    address: 0x020D,
+                    function: Some(0x020D),
+                    interrupted: false
+                },
+                BacktraceEntry {
+                    depth: 1,
+                    address: 0x020C,
+                    function: Some(0x020A),
+                    interrupted: true
+                },
+                BacktraceEntry {
+                    depth: 2,
+                    address: 0x0209,
+                    function: Some(0x0206),
+                    interrupted: false
+                },
+                BacktraceEntry {
+                    depth: 3,
+                    address: 0x0203,
+                    function: None,
+                    interrupted: false
+                },
+            ]
+        );
+        assert_eq!(
+            call_stack.format_backtrace(0x020D, |address| (address == 0x0206)
+                .then(|| "outer".to_string())),
+            "#0  $020D in $020D\n#1  $020C in $020A <interrupted>\n#2  $0209 in outer\n#3  $0203"
+        );
+
+        run_to(&mut cpu, 0x020C);
+        assert_eq!(cpu.call_stack().depth(), 2);
+        run_to(&mut cpu, 0x0203);
+        assert_eq!(cpu.call_stack().depth(), 0);
+    }
+
+    #[test]
+    fn tolerates_stack_tricks() {
+        let mut cpu = cpu("
+            .org $0200
+                jsr outer
+            done:
+                jmp done
+            outer:
+                jsr unwind
+                brk
+            unwind:
+                pla
+                pla
+                lda #>[target - 1]
+                pha
+                lda #<[target - 1]
+                pha
+                rts
+            target:
+                jsr leaf
+                rts
+            leaf:
+                rts
+            handler:
+                rti
+            ");
+
+        run_to(&mut cpu, 0x020A);
+        assert_eq!(cpu.call_stack().depth(), 2);
+
+        // Pulling the return address leaves `outer` as the current frame
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.call_stack().depth(), 1);
+
+        // The RTS jumps to `target` without returning from anything
+        let target = 0x0213;
+        run_to(&mut cpu, target);
+        assert_eq!(cpu.call_stack().depth(), 1);
+        assert_eq!(cpu.call_stack().frames()[0].target, 0x0206);
+
+        cpu.step();
+        assert_eq!(cpu.call_stack().depth(), 2);
+
+        run_to(&mut cpu, 0x0203);
+        assert_eq!(cpu.call_stack().depth(), 0);
+    }
+    #[test]
+    fn unofficial_stack_pointer_writes_unwind() {
+        let mut cpu = cpu("
+            .org $0200
+                jsr outer
+            done:
+                jmp done
+            outer:
+                jsr inner
+            inner:
+                lda #$FF
+                ldx #$FF
+                ldy #0
+                tas $0300,y
+                nop
+            handler:
+                rti
+            ");
+
+        run_to(&mut cpu, 0x0209);
+        assert_eq!(cpu.call_stack().depth(), 2);
+
+        // S = A & X = $FF, which empties the stack
+        run_to(&mut cpu, 0x0212);
+        assert_eq!(cpu.state().stack_pointer, 0xFF);
+        assert_eq!(cpu.call_stack().depth(), 0);
+    }
+}
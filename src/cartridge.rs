@@ -0,0 +1,81 @@
+use log::debug;
+
+use crate::constants::{
+    LogTargets, CHR_ROM_PAGE_SIZE, INES_HEADER_SIZE, INES_TAG, INES_TRAINER_SIZE,
+    PRG_RAM_PAGE_SIZE, PRG_ROM_PAGE_SIZE,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+}
+
+// An iNES file, see:
+// https://www.nesdev.org/wiki/INES
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub has_battery: bool,
+    // Work RAM at $6000, kept by the battery if there is one
+    pub prg_ram_size: usize,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if raw.len() < INES_HEADER_SIZE || raw[0..4] != INES_TAG {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+
+        let ines_version = (raw[7] >> 2) & 0b11;
+        if ines_version != 0 {
+            return Err("NES2.0 format is not supported".to_string());
+        }
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let has_battery = raw[6] & 0b10 != 0;
+        let has_trainer = raw[6] & 0b100 != 0;
+
+        // Zero means 8 KiB, for compatibility with older files
+        let prg_ram_size = raw[8].max(1) as usize * PRG_RAM_PAGE_SIZE;
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        let prg_rom_start = INES_HEADER_SIZE + if has_trainer { INES_TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err("File is shorter than its header says".to_string());
+        }
+
+        debug!(
+            target: LogTargets::MAPPER,
+            "Mapper {}, {} KiB PRG ROM, {} KiB CHR ROM, {:?} mirroring",
+            mapper,
+            prg_rom_size / 1024,
+            chr_rom_size / 1024,
+            screen_mirroring
+        );
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..chr_rom_start + chr_rom_size].to_vec(),
+            mapper,
+            screen_mirroring,
+            has_battery,
+            prg_ram_size,
+        })
+    }
+}
@@ -0,0 +1,206 @@
+use crate::bus::BusAccessKind;
+use crate::constants::{OPERATION_INFORMATION, PRG_ROM_START};
+use crate::cpu::{Cpu, StepInfo};
+use crate::operation::AddressingModes;
+
+// Flag bits of the FCEUX .cdl format, see:
+// https://fceux.com/web/help/CodeDataLogger.html
+//
+// Each PRG ROM byte is xPdcAADC
+#[allow(non_snake_case)]
+pub mod CdlFlags {
+    pub const CODE: u8 = 0b0000_0001;
+    pub const DATA: u8 = 0b0000_0010;
+    // Which 8 KiB window ($8000, $A000, $C000 or $E000) the byte was last
+    // accessed through
+    pub const BANK_MASK: u8 = 0b0000_1100;
+    pub const INDIRECT_CODE: u8 = 0b0001_0000;
+    pub const INDIRECT_DATA: u8 = 0b0010_0000;
+    pub const PCM_DATA: u8 = 0b0100_0000;
+}
+
+// Records how every PRG ROM byte was used. The file is the PRG flags followed
+// by the CHR flags, so it can be shared with FCEUX and the tools that read its
+// logs. Without a PPU nothing here touches CHR ROM, so the CHR flags are
+// written back as they were loaded, or zeroed for a new log.
+//
+// Data reads are taken from the bus accesses of each step, so this is meant
+// for the instruction execution mode. In cycle stepped mode the dummy reads
+// would be logged as data as well.
+pub struct CodeDataLogger {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+}
+
+impl CodeDataLogger {
+    pub fn new(prg_rom_size: usize, chr_rom_size: usize) -> Self {
+        CodeDataLogger {
+            prg_rom: vec![0; prg_rom_size],
+            chr_rom: vec![0; chr_rom_size],
+        }
+    }
+
+    // Loads a log written earlier, the sizes have to match the cartridge
+    pub fn from_bytes(
+        raw: &[u8],
+        prg_rom_size: usize,
+        chr_rom_size: usize,
+    ) -> Result<Self, String> {
+        if raw.len() != prg_rom_size + chr_rom_size {
+            return Err(format!(
+                "CDL file is {} bytes, expected {} for this ROM",
+                raw.len(),
+                prg_rom_size + chr_rom_size
+            ));
+        }
+
+        Ok(CodeDataLogger {
+            prg_rom: raw[..prg_rom_size].to_vec(),
+            chr_rom: raw[prg_rom_size..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.prg_rom.clone();
+        bytes.extend_from_slice(&self.chr_rom);
+        bytes
+    }
+
+    pub fn prg_flags(&self, offset: usize) -> u8 {
+        self.prg_rom.get(offset).copied().unwrap_or(0)
+    }
+
+    // Returns how many PRG bytes were logged as code and as data
+    pub fn prg_coverage(&self) -> (usize, usize) {
+        let count = |flag| {
+            self.prg_rom
+                .iter()
+                .filter(|flags| *flags & flag != 0)
+                .count()
+        };
+        (count(CdlFlags::CODE), count(CdlFlags::DATA))
+    }
+
+    fn mark_prg(&mut self, cpu: &Cpu, address: u16, flags: u8) {
+        let Some(offset) = cpu.bus().prg_rom_offset(address) else {
+            return;
+        };
+
+        if let Some(byte) = self.prg_rom.get_mut(offset) {
+            let bank = (((address - PRG_ROM_START) >> 13) as u8) << 2;
+            *byte = (*byte & !CdlFlags::BANK_MASK) | bank | flags;
+        }
+    }
+
+    // Records one step, called from a hook added with `Cpu::add_step_hook`.
+    // The bytes the CPU fetched as part of the instruction are code, anything
+    // else it read from PRG ROM is data.
+    pub fn record_step(&mut self, cpu: &Cpu, step: &StepInfo) {
+        let fetched = |index: usize| step.fetches & (1 << index) != 0;
+
+        // Interrupts fetch nothing, their vector reads are data
+        let mode = match step.accesses.first() {
+            Some(opcode) if fetched(0) => OPERATION_INFORMATION[opcode.value as usize]
+                .map(|operation| operation.instruction_addressing_mode),
+            _ => None,
+        };
+        let data = match mode {
+            Some(AddressingModes::IndexedIndirect | AddressingModes::IndirectIndexed) => {
+                CdlFlags::DATA | CdlFlags::INDIRECT_DATA
+            }
+            _ => CdlFlags::DATA,
+        };
+
+        for (index, access) in step.accesses.iter().enumerate() {
+            if fetched(index) {
+                self.mark_prg(cpu, access.address, CdlFlags::CODE);
+            } else if access.kind == BusAccessKind::Read {
+                self.mark_prg(cpu, access.address, data);
+            }
+        }
+
+        if mode == Some(AddressingModes::Indirect) {
+            let target = cpu.state().program_counter;
+            self.mark_prg(cpu, target, CdlFlags::INDIRECT_CODE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{CdlFlags, CodeDataLogger};
+    use crate::bus::Bus;
+    use crate::constants::{CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+    use crate::cpu::Cpu;
+    use crate::nes::tests::rom;
+
+    #[test]
+    fn logs_code_data_and_indirect_accesses() {
+        let source = "
+            pointer = $10
+
+            .org $8000
+            start:
+                lda table
+                ldy #0
+                lda (pointer),y
+                jmp (vector)
+            target:
+                jmp target
+            table:
+                .byte $01, $02
+            vector:
+                .word target
+            ";
+
+        // A 16 KiB NROM image is mirrored, so the pointer reaches the table
+        // through the $C000 bank
+        let mut cpu = Cpu::new(Bus::with_rom(rom(source)));
+        cpu.reset();
+        cpu.bus_mut().mem_write(0x0010, 0x0E);
+        cpu.bus_mut().mem_write(0x0011, 0xC0);
+
+        let logger = Arc::new(Mutex::new(CodeDataLogger::new(
+            PRG_ROM_PAGE_SIZE,
+            CHR_ROM_PAGE_SIZE,
+        )));
+        let hook = Arc::clone(&logger);
+        cpu.add_step_hook(move |cpu, step| hook.lock().unwrap().record_step(cpu, step));
+        for _ in 0..5 {
+            cpu.step();
+        }
+        let logger = logger.lock().unwrap();
+
+        let mirror = 0b10 << 2;
+        let target = 0x000A;
+        let table = 0x000D;
+        let vector = 0x000F;
+        assert_eq!(logger.prg_flags(0), CdlFlags::CODE);
+        assert_eq!(
+            logger.prg_flags(target),
+            CdlFlags::CODE | CdlFlags::INDIRECT_CODE
+        );
+        assert_eq!(logger.prg_flags(table), CdlFlags::DATA);
+        assert_eq!(
+            logger.prg_flags(table + 1),
+            CdlFlags::DATA | CdlFlags::INDIRECT_DATA | mirror
+        );
+        assert_eq!(logger.prg_flags(vector), CdlFlags::DATA);
+        assert_eq!(logger.prg_flags(vector + 2), 0);
+        assert_eq!(logger.prg_coverage(), (13, 4));
+
+        let mut bytes = logger.to_bytes();
+        assert_eq!(bytes.len(), PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE);
+        assert_eq!(bytes[PRG_ROM_PAGE_SIZE..], [0; CHR_ROM_PAGE_SIZE]);
+
+        // CHR flags from FCEUX survive a round trip
+        bytes[PRG_ROM_PAGE_SIZE + 0x10] = 0b01;
+
+        let loaded =
+            CodeDataLogger::from_bytes(&bytes, PRG_ROM_PAGE_SIZE, CHR_ROM_PAGE_SIZE).unwrap();
+        assert_eq!(loaded.to_bytes(), bytes);
+        assert!(CodeDataLogger::from_bytes(&bytes, PRG_ROM_PAGE_SIZE, 0).is_err());
+    }
+}
@@ -1,8 +1,6 @@
-use std::vec;
-
-use crate::operation::{AddressingModes, Operation};
-use lazy_static::lazy_static;
+use crate::operation::{AddressingModes, Mnemonic, Operation};
 
+#[allow(non_snake_case)]
 pub mod BitMasks {
     pub const ZERO: u8 = 0b00000001;
     pub const FIRST: u8 = 0b00000010;
@@ -14,6 +12,17 @@ pub mod BitMasks {
     pub const SEVENTH: u8 = 0b10000000;
 }
 
+// `log` targets, so each subsystem can be filtered on its own, for example
+// RUST_LOG=bus=warn,cpu=debug
+#[allow(non_snake_case)]
+pub mod LogTargets {
+    pub const CPU: &str = "cpu";
+    pub const BUS: &str = "bus";
+    pub const PPU: &str = "ppu";
+    pub const APU: &str = "apu";
+    pub const MAPPER: &str = "mapper";
+}
+
 pub const RESET_STACK_ADDRESS: u8 = 0xFD;
 pub const STATUS_REGISTER_INITIAL: u8 = 0b00100100;
 pub const STACK_START: u16 = 0x100;
@@ -22,383 +31,1191 @@ pub const RAM_MIRRORS_END: u16 = 0x1FFF;
 pub const PPU_REGISTERS: u16 = 0x2000;
 pub const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 pub const RAM_SIZE: u16 = 2048;
+pub const FLAT_MEMORY_SIZE: usize = 0x10000;
+// $4014 is OAM DMA, the rest of the range belongs to the APU
+pub const APU_REGISTERS: u16 = 0x4000;
+pub const APU_REGISTERS_END: u16 = 0x4015;
+pub const CONTROLLER_1: u16 = 0x4016;
+pub const CONTROLLER_2: u16 = 0x4017;
+// Writes to $4017 go to the APU frame counter, reads to the second controller
+pub const APU_FRAME_COUNTER: u16 = 0x4017;
+// Controller reads only drive the low bits, the rest is open bus
+pub const CONTROLLER_OPEN_BUS_MASK: u8 = 0b1110_0000;
+pub const PRG_RAM_START: u16 = 0x6000;
+pub const PRG_RAM_END: u16 = 0x7FFF;
+pub const PRG_ROM_START: u16 = 0x8000;
+pub const PRG_ROM_END: u16 = 0xFFFF;
+pub const PRG_ROM_PAGE_SIZE: usize = 16384;
+pub const CHR_ROM_PAGE_SIZE: usize = 8192;
+pub const PRG_RAM_PAGE_SIZE: usize = 8192;
+pub const INES_HEADER_SIZE: usize = 16;
+pub const INES_TRAINER_SIZE: usize = 512;
+pub const INES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+// NTSC timing, a frame is 262 lines of 341 dots and the PPU runs three dots
+// per CPU cycle
+pub const PPU_DOTS_PER_FRAME: u64 = 341 * 262;
+pub const PPU_DOTS_PER_CPU_CYCLE: u64 = 3;
+pub const SAVESTATE_MAGIC: [u8; 4] = *b"NESS";
+pub const SAVESTATE_VERSION: u16 = 3;
+pub const SAVESTATE_HEADER_SIZE: usize = 16;
+pub const NMI_INTERRUPT_VECTOR_ADDRESS: u16 = 0xFFFA;
 pub const IRQ_INTERRUPT_VECTOR_ADDRESS: u16 = 0xFFFE;
 pub const U16_HIGH_BYTE_MASK: u16 = 0xFF00;
 pub const U16_LOW_BYTE_MASK: u16 = 0x00FF;
 pub const RESET_PROGRAM_COUNTER_ADDRESS: u16 = 0xFFFC;
 
-lazy_static! {
-    pub static ref OPERATION_INFORMATION: Vec<Option<Operation>> = {
-        let mut vector = Vec::with_capacity(256);
+// Indexed by opcode, built at compile time
+pub static OPERATION_INFORMATION: [Option<Operation>; 256] = {
+    use Mnemonic::*;
+
+    let mut table = [None; 256];
+
+    table[0x00] = Some(Operation::new(Brk, 7, 1, 0, AddressingModes::Implicit));
+    table[0x01] = Some(Operation::new(
+        Ora,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x02] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x03] = Some(Operation::unofficial(
+        Slo,
+        8,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x04] = Some(Operation::unofficial(
+        Nop,
+        3,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0x05] = Some(Operation::new(Ora, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0x06] = Some(Operation::new(Asl, 5, 2, 0, AddressingModes::ZeroPage));
+    table[0x07] = Some(Operation::unofficial(
+        Slo,
+        5,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0x08] = Some(Operation::new(Php, 3, 1, 0, AddressingModes::Implicit));
+    table[0x09] = Some(Operation::new(Ora, 2, 2, 0, AddressingModes::Immediate));
+    table[0x0A] = Some(Operation::new(Asl, 2, 1, 0, AddressingModes::Accumulator));
+    table[0x0B] = Some(Operation::unofficial(
+        Anc,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0x0C] = Some(Operation::unofficial(
+        Nop,
+        4,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
+    table[0x0D] = Some(Operation::new(Ora, 4, 3, 0, AddressingModes::Absolute));
+    table[0x0E] = Some(Operation::new(Asl, 6, 3, 0, AddressingModes::Absolute));
+    table[0x0F] = Some(Operation::unofficial(
+        Slo,
+        6,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
+
+    table[0x10] = Some(Operation::new(Bpl, 2, 2, 1, AddressingModes::Relative));
+    table[0x11] = Some(Operation::new(
+        Ora,
+        5,
+        2,
+        1,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x12] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x13] = Some(Operation::unofficial(
+        Slo,
+        8,
+        2,
+        0,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x14] = Some(Operation::unofficial(
+        Nop,
+        4,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0x15] = Some(Operation::new(Ora, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0x16] = Some(Operation::new(Asl, 6, 2, 0, AddressingModes::ZeroPageX));
+    table[0x17] = Some(Operation::unofficial(
+        Slo,
+        6,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0x18] = Some(Operation::new(Clc, 2, 1, 0, AddressingModes::Implicit));
+    table[0x19] = Some(Operation::new(Ora, 4, 3, 1, AddressingModes::AbsoluteY));
+    table[0x1A] = Some(Operation::unofficial(
+        Nop,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x1B] = Some(Operation::unofficial(
+        Slo,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0x1C] = Some(Operation::unofficial(
+        Nop,
+        4,
+        3,
+        1,
+        AddressingModes::AbsoluteX,
+    ));
+    table[0x1D] = Some(Operation::new(Ora, 4, 3, 1, AddressingModes::AbsoluteX));
+    table[0x1E] = Some(Operation::new(Asl, 7, 3, 0, AddressingModes::AbsoluteX));
+    table[0x1F] = Some(Operation::unofficial(
+        Slo,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteX,
+    ));
+
+    table[0x20] = Some(Operation::new(Jsr, 6, 3, 0, AddressingModes::Absolute));
+    table[0x21] = Some(Operation::new(
+        And,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x22] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x23] = Some(Operation::unofficial(
+        Rla,
+        8,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x24] = Some(Operation::new(Bit, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0x25] = Some(Operation::new(And, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0x26] = Some(Operation::new(Rol, 5, 2, 0, AddressingModes::ZeroPage));
+    table[0x27] = Some(Operation::unofficial(
+        Rla,
+        5,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0x28] = Some(Operation::new(Plp, 4, 1, 0, AddressingModes::Implicit));
+    table[0x29] = Some(Operation::new(And, 2, 2, 0, AddressingModes::Immediate));
+    table[0x2A] = Some(Operation::new(Rol, 2, 1, 0, AddressingModes::Accumulator));
+    table[0x2B] = Some(Operation::unofficial(
+        Anc,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0x2C] = Some(Operation::new(Bit, 4, 3, 0, AddressingModes::Absolute));
+    table[0x2D] = Some(Operation::new(And, 4, 3, 0, AddressingModes::Absolute));
+    table[0x2E] = Some(Operation::new(Rol, 6, 3, 0, AddressingModes::Absolute));
+    table[0x2F] = Some(Operation::unofficial(
+        Rla,
+        6,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
+
+    table[0x30] = Some(Operation::new(Bmi, 2, 2, 1, AddressingModes::Relative));
+    table[0x31] = Some(Operation::new(
+        And,
+        5,
+        2,
+        1,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x32] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x33] = Some(Operation::unofficial(
+        Rla,
+        8,
+        2,
+        0,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x34] = Some(Operation::unofficial(
+        Nop,
+        4,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0x35] = Some(Operation::new(And, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0x36] = Some(Operation::new(Rol, 6, 2, 0, AddressingModes::ZeroPageX));
+    table[0x37] = Some(Operation::unofficial(
+        Rla,
+        6,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0x38] = Some(Operation::new(Sec, 2, 1, 0, AddressingModes::Implicit));
+    table[0x39] = Some(Operation::new(And, 4, 3, 1, AddressingModes::AbsoluteY));
+    table[0x3A] = Some(Operation::unofficial(
+        Nop,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x3B] = Some(Operation::unofficial(
+        Rla,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0x3C] = Some(Operation::unofficial(
+        Nop,
+        4,
+        3,
+        1,
+        AddressingModes::AbsoluteX,
+    ));
+    table[0x3D] = Some(Operation::new(And, 4, 3, 1, AddressingModes::AbsoluteX));
+    table[0x3E] = Some(Operation::new(Rol, 7, 3, 0, AddressingModes::AbsoluteX));
+    table[0x3F] = Some(Operation::unofficial(
+        Rla,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteX,
+    ));
+
+    table[0x40] = Some(Operation::new(Rti, 6, 1, 0, AddressingModes::Implicit));
+    table[0x41] = Some(Operation::new(
+        Eor,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x42] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x43] = Some(Operation::unofficial(
+        Sre,
+        8,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x44] = Some(Operation::unofficial(
+        Nop,
+        3,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0x45] = Some(Operation::new(Eor, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0x46] = Some(Operation::new(Lsr, 5, 2, 0, AddressingModes::ZeroPage));
+    table[0x47] = Some(Operation::unofficial(
+        Sre,
+        5,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0x48] = Some(Operation::new(Pha, 3, 1, 0, AddressingModes::Implicit));
+    table[0x49] = Some(Operation::new(Eor, 2, 2, 0, AddressingModes::Immediate));
+    table[0x4A] = Some(Operation::new(Lsr, 2, 1, 0, AddressingModes::Accumulator));
+    table[0x4B] = Some(Operation::unofficial(
+        Alr,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0x4C] = Some(Operation::new(Jmp, 3, 3, 0, AddressingModes::Absolute));
+    table[0x4D] = Some(Operation::new(Eor, 4, 3, 0, AddressingModes::Absolute));
+    table[0x4E] = Some(Operation::new(Lsr, 6, 3, 0, AddressingModes::Absolute));
+    table[0x4F] = Some(Operation::unofficial(
+        Sre,
+        6,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
+
+    table[0x50] = Some(Operation::new(Bvc, 2, 2, 1, AddressingModes::Relative));
+    table[0x51] = Some(Operation::new(
+        Eor,
+        5,
+        2,
+        1,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x52] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x53] = Some(Operation::unofficial(
+        Sre,
+        8,
+        2,
+        0,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x54] = Some(Operation::unofficial(
+        Nop,
+        4,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0x55] = Some(Operation::new(Eor, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0x56] = Some(Operation::new(Lsr, 6, 2, 0, AddressingModes::ZeroPageX));
+    table[0x57] = Some(Operation::unofficial(
+        Sre,
+        6,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0x58] = Some(Operation::new(Cli, 2, 1, 0, AddressingModes::Implicit));
+    table[0x59] = Some(Operation::new(Eor, 4, 3, 1, AddressingModes::AbsoluteY));
+    table[0x5A] = Some(Operation::unofficial(
+        Nop,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x5B] = Some(Operation::unofficial(
+        Sre,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0x5C] = Some(Operation::unofficial(
+        Nop,
+        4,
+        3,
+        1,
+        AddressingModes::AbsoluteX,
+    ));
+    table[0x5D] = Some(Operation::new(Eor, 4, 3, 1, AddressingModes::AbsoluteX));
+    table[0x5E] = Some(Operation::new(Lsr, 7, 3, 0, AddressingModes::AbsoluteX));
+    table[0x5F] = Some(Operation::unofficial(
+        Sre,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteX,
+    ));
+
+    table[0x60] = Some(Operation::new(Rts, 6, 1, 0, AddressingModes::Implicit));
+    table[0x61] = Some(Operation::new(
+        Adc,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x62] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x63] = Some(Operation::unofficial(
+        Rra,
+        8,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x64] = Some(Operation::unofficial(
+        Nop,
+        3,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0x65] = Some(Operation::new(Adc, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0x66] = Some(Operation::new(Ror, 5, 2, 0, AddressingModes::ZeroPage));
+    table[0x67] = Some(Operation::unofficial(
+        Rra,
+        5,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0x68] = Some(Operation::new(Pla, 4, 1, 0, AddressingModes::Implicit));
+    table[0x69] = Some(Operation::new(Adc, 2, 2, 0, AddressingModes::Immediate));
+    table[0x6A] = Some(Operation::new(Ror, 2, 1, 0, AddressingModes::Accumulator));
+    table[0x6B] = Some(Operation::unofficial(
+        Arr,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0x6C] = Some(Operation::new(Jmp, 5, 3, 0, AddressingModes::Indirect));
+    table[0x6D] = Some(Operation::new(Adc, 4, 3, 0, AddressingModes::Absolute));
+    table[0x6E] = Some(Operation::new(Ror, 6, 3, 0, AddressingModes::Absolute));
+    table[0x6F] = Some(Operation::unofficial(
+        Rra,
+        6,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
+
+    table[0x70] = Some(Operation::new(Bvs, 2, 2, 1, AddressingModes::Relative));
+    table[0x71] = Some(Operation::new(
+        Adc,
+        5,
+        2,
+        1,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x72] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x73] = Some(Operation::unofficial(
+        Rra,
+        8,
+        2,
+        0,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x74] = Some(Operation::unofficial(
+        Nop,
+        4,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0x75] = Some(Operation::new(Adc, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0x76] = Some(Operation::new(Ror, 6, 2, 0, AddressingModes::ZeroPageX));
+    table[0x77] = Some(Operation::unofficial(
+        Rra,
+        6,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0x78] = Some(Operation::new(Sei, 2, 1, 0, AddressingModes::Implicit));
+    table[0x79] = Some(Operation::new(Adc, 4, 3, 1, AddressingModes::AbsoluteY));
+    table[0x7A] = Some(Operation::unofficial(
+        Nop,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x7B] = Some(Operation::unofficial(
+        Rra,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0x7C] = Some(Operation::unofficial(
+        Nop,
+        4,
+        3,
+        1,
+        AddressingModes::AbsoluteX,
+    ));
+    table[0x7D] = Some(Operation::new(Adc, 4, 3, 1, AddressingModes::AbsoluteX));
+    table[0x7E] = Some(Operation::new(Ror, 7, 3, 0, AddressingModes::AbsoluteX));
+    table[0x7F] = Some(Operation::unofficial(
+        Rra,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteX,
+    ));
+
+    table[0x80] = Some(Operation::unofficial(
+        Nop,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0x81] = Some(Operation::new(
+        Sta,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x82] = Some(Operation::unofficial(
+        Nop,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0x83] = Some(Operation::unofficial(
+        Sax,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0x84] = Some(Operation::new(Sty, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0x85] = Some(Operation::new(Sta, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0x86] = Some(Operation::new(Stx, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0x87] = Some(Operation::unofficial(
+        Sax,
+        3,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0x88] = Some(Operation::new(Dey, 2, 1, 0, AddressingModes::Implicit));
+    table[0x89] = Some(Operation::unofficial(
+        Nop,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0x8A] = Some(Operation::new(Txa, 2, 1, 0, AddressingModes::Implicit));
+    table[0x8B] = Some(Operation::unofficial(
+        Ane,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0x8C] = Some(Operation::new(Sty, 4, 3, 0, AddressingModes::Absolute));
+    table[0x8D] = Some(Operation::new(Sta, 4, 3, 0, AddressingModes::Absolute));
+    table[0x8E] = Some(Operation::new(Stx, 4, 3, 0, AddressingModes::Absolute));
+    table[0x8F] = Some(Operation::unofficial(
+        Sax,
+        4,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
 
-        vector[0x00] = Some(Operation::new("BRK", 7, 1, 0, AddressingModes::Implicit));
-        vector[0x01] = Some(Operation::new(
-            "ORA",
-            6,
-            2,
-            0,
-            AddressingModes::IndexedIndirect,
-        ));
-        vector[0x02] = None;
-        vector[0x03] = None;
-        vector[0x04] = None;
-        vector[0x05] = Some(Operation::new("ORA", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0x06] = Some(Operation::new("ASL", 5, 2, 0, AddressingModes::ZeroPage));
-        vector[0x07] = None;
-        vector[0x08] = Some(Operation::new("PHP", 3, 1, 0, AddressingModes::Implicit));
-        vector[0x09] = Some(Operation::new("ORA", 2, 2, 0, AddressingModes::Immediate));
-        vector[0x0A] = Some(Operation::new("ASL", 2, 1, 0, AddressingModes::Accumulator));
-        vector[0x0B] = None;
-        vector[0x0C] = None;
-        vector[0x0D] = Some(Operation::new("ORA", 4, 3, 0, AddressingModes::Absolute));
-        vector[0x0E] = Some(Operation::new("ASL", 6, 3, 0, AddressingModes::Absolute));
-        vector[0x0F] = None;
+    table[0x90] = Some(Operation::new(Bcc, 2, 2, 1, AddressingModes::Relative));
+    table[0x91] = Some(Operation::new(
+        Sta,
+        6,
+        2,
+        0,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x92] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0x93] = Some(Operation::unofficial(
+        Sha,
+        6,
+        2,
+        0,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0x94] = Some(Operation::new(Sty, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0x95] = Some(Operation::new(Sta, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0x96] = Some(Operation::new(Stx, 4, 2, 0, AddressingModes::ZeroPageY));
+    table[0x97] = Some(Operation::unofficial(
+        Sax,
+        4,
+        2,
+        0,
+        AddressingModes::ZeroPageY,
+    ));
+    table[0x98] = Some(Operation::new(Tya, 2, 1, 0, AddressingModes::Implicit));
+    table[0x99] = Some(Operation::new(Sta, 5, 3, 0, AddressingModes::AbsoluteY));
+    table[0x9A] = Some(Operation::new(Txs, 2, 1, 0, AddressingModes::Implicit));
+    table[0x9B] = Some(Operation::unofficial(
+        Tas,
+        5,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0x9C] = Some(Operation::unofficial(
+        Shy,
+        5,
+        3,
+        0,
+        AddressingModes::AbsoluteX,
+    ));
+    table[0x9D] = Some(Operation::new(Sta, 5, 3, 0, AddressingModes::AbsoluteX));
+    table[0x9E] = Some(Operation::unofficial(
+        Shx,
+        5,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0x9F] = Some(Operation::unofficial(
+        Sha,
+        5,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
 
-        vector[0x10] = Some(Operation::new("BPL", 2, 2, 1, AddressingModes::Relative));
-        vector[0x11] = Some(Operation::new(
-            "ORA",
-            5,
-            2,
-            1,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0x12] = None;
-        vector[0x13] = None;
-        vector[0x14] = None;
-        vector[0x15] = Some(Operation::new("ORA", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x16] = Some(Operation::new("ASL", 6, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x17] = None;
-        vector[0x18] = Some(Operation::new("CLC", 2, 1, 0, AddressingModes::Implicit));
-        vector[0x19] = Some(Operation::new("ORA", 4, 3, 1, AddressingModes::AbsoluteY));
-        vector[0x1A] = None;
-        vector[0x1B] = None;
-        vector[0x1C] = None;
-        vector[0x1D] = Some(Operation::new("ORA", 4, 3, 1, AddressingModes::AbsoluteX));
-        vector[0x1E] = Some(Operation::new("ASL", 7, 3, 0, AddressingModes::AbsoluteX));
-        vector[0x1F] = None;
+    table[0xA0] = Some(Operation::new(Ldy, 2, 2, 0, AddressingModes::Immediate));
+    table[0xA1] = Some(Operation::new(
+        Lda,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0xA2] = Some(Operation::new(Ldx, 2, 2, 0, AddressingModes::Immediate));
+    table[0xA3] = Some(Operation::unofficial(
+        Lax,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0xA4] = Some(Operation::new(Ldy, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0xA5] = Some(Operation::new(Lda, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0xA6] = Some(Operation::new(Ldx, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0xA7] = Some(Operation::unofficial(
+        Lax,
+        3,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0xA8] = Some(Operation::new(Tay, 2, 1, 0, AddressingModes::Implicit));
+    table[0xA9] = Some(Operation::new(Lda, 2, 2, 0, AddressingModes::Immediate));
+    table[0xAA] = Some(Operation::new(Tax, 2, 1, 0, AddressingModes::Implicit));
+    table[0xAB] = Some(Operation::unofficial(
+        Lxa,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0xAC] = Some(Operation::new(Ldy, 4, 3, 0, AddressingModes::Absolute));
+    table[0xAD] = Some(Operation::new(Lda, 4, 3, 0, AddressingModes::Absolute));
+    table[0xAE] = Some(Operation::new(Ldx, 4, 3, 0, AddressingModes::Absolute));
+    table[0xAF] = Some(Operation::unofficial(
+        Lax,
+        4,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
 
-        vector[0x20] = Some(Operation::new("JSR", 6, 3, 0, AddressingModes::Absolute));
-        vector[0x21] = Some(Operation::new(
-            "AND",
-            6,
-            2,
-            0,
-            AddressingModes::IndexedIndirect,
-        ));
-        vector[0x22] = None;
-        vector[0x23] = None;
-        vector[0x24] = Some(Operation::new("BIT", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0x25] = Some(Operation::new("AND", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0x26] = Some(Operation::new("ROL", 5, 2, 0, AddressingModes::ZeroPage));
-        vector[0x27] = None;
-        vector[0x28] = Some(Operation::new("PLP", 4, 1, 0, AddressingModes::Implicit));
-        vector[0x29] = Some(Operation::new("AND", 2, 2, 0, AddressingModes::Immediate));
-        vector[0x2A] = Some(Operation::new("ROL", 2, 1, 0, AddressingModes::Accumulator));
-        vector[0x2B] = None;
-        vector[0x2C] = Some(Operation::new("BIT", 3, 4, 0, AddressingModes::Absolute));
-        vector[0x2D] = Some(Operation::new("AND", 4, 3, 0, AddressingModes::Absolute));
-        vector[0x2E] = Some(Operation::new("ROL", 3, 6, 0, AddressingModes::Absolute));
-        vector[0x2F] = None;
+    table[0xB0] = Some(Operation::new(Bcs, 2, 2, 1, AddressingModes::Relative));
+    table[0xB1] = Some(Operation::new(
+        Lda,
+        5,
+        2,
+        1,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0xB2] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0xB3] = Some(Operation::unofficial(
+        Lax,
+        5,
+        2,
+        1,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0xB4] = Some(Operation::new(Ldy, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0xB5] = Some(Operation::new(Lda, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0xB6] = Some(Operation::new(Ldx, 4, 2, 0, AddressingModes::ZeroPageY));
+    table[0xB7] = Some(Operation::unofficial(
+        Lax,
+        4,
+        2,
+        0,
+        AddressingModes::ZeroPageY,
+    ));
+    table[0xB8] = Some(Operation::new(Clv, 2, 1, 0, AddressingModes::Implicit));
+    table[0xB9] = Some(Operation::new(Lda, 4, 3, 1, AddressingModes::AbsoluteY));
+    table[0xBA] = Some(Operation::new(Tsx, 2, 1, 0, AddressingModes::Implicit));
+    table[0xBB] = Some(Operation::unofficial(
+        Las,
+        4,
+        3,
+        1,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0xBC] = Some(Operation::new(Ldy, 4, 3, 1, AddressingModes::AbsoluteX));
+    table[0xBD] = Some(Operation::new(Lda, 4, 3, 1, AddressingModes::AbsoluteX));
+    table[0xBE] = Some(Operation::new(Ldx, 4, 3, 1, AddressingModes::AbsoluteY));
+    table[0xBF] = Some(Operation::unofficial(
+        Lax,
+        4,
+        3,
+        1,
+        AddressingModes::AbsoluteY,
+    ));
 
-        vector[0x30] = Some(Operation::new("BMI", 2, 2, 2, AddressingModes::Relative));
-        vector[0x31] = Some(Operation::new(
-            "AND",
-            5,
-            2,
-            1,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0x32] = None;
-        vector[0x33] = None;
-        vector[0x34] = None;
-        vector[0x35] = Some(Operation::new("AND", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x36] = Some(Operation::new("ROL", 2, 6, 0, AddressingModes::ZeroPageX));
-        vector[0x37] = None;
-        vector[0x38] = Some(Operation::new("SEC", 2, 1, 0, AddressingModes::Implicit));
-        vector[0x39] = Some(Operation::new("AND", 4, 3, 1, AddressingModes::AbsoluteY));
-        vector[0x3A] = None;
-        vector[0x3B] = None;
-        vector[0x3C] = None;
-        vector[0x3D] = Some(Operation::new("AND", 4, 3, 1, AddressingModes::AbsoluteX));
-        vector[0x3E] = Some(Operation::new("ROL", 7, 3, 0, AddressingModes::AbsoluteX));
-        vector[0x3F] = None;
+    table[0xC0] = Some(Operation::new(Cpy, 2, 2, 0, AddressingModes::Immediate));
+    table[0xC1] = Some(Operation::new(
+        Cmp,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0xC2] = Some(Operation::unofficial(
+        Nop,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0xC3] = Some(Operation::unofficial(
+        Dcp,
+        8,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0xC4] = Some(Operation::new(Cpy, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0xC5] = Some(Operation::new(Cmp, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0xC6] = Some(Operation::new(Dec, 5, 2, 0, AddressingModes::ZeroPage));
+    table[0xC7] = Some(Operation::unofficial(
+        Dcp,
+        5,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0xC8] = Some(Operation::new(Iny, 2, 1, 0, AddressingModes::Implicit));
+    table[0xC9] = Some(Operation::new(Cmp, 2, 2, 0, AddressingModes::Immediate));
+    table[0xCA] = Some(Operation::new(Dex, 2, 1, 0, AddressingModes::Implicit));
+    table[0xCB] = Some(Operation::unofficial(
+        Sbx,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0xCC] = Some(Operation::new(Cpy, 4, 3, 0, AddressingModes::Absolute));
+    table[0xCD] = Some(Operation::new(Cmp, 4, 3, 0, AddressingModes::Absolute));
+    table[0xCE] = Some(Operation::new(Dec, 6, 3, 0, AddressingModes::Absolute));
+    table[0xCF] = Some(Operation::unofficial(
+        Dcp,
+        6,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
 
-        vector[0x40] = Some(Operation::new("RTI", 6, 1, 0, AddressingModes::Implicit));
-        vector[0x41] = Some(Operation::new(
-            "EOR",
-            6,
-            2,
-            0,
-            AddressingModes::IndexedIndirect,
-        ));
-        vector[0x42] = None;
-        vector[0x43] = None;
-        vector[0x44] = None;
-        vector[0x45] = Some(Operation::new("EOR", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0x46] = Some(Operation::new("LSR", 5, 2, 0, AddressingModes::ZeroPage));
-        vector[0x47] = None;
-        vector[0x48] = Some(Operation::new("PHA", 3, 1, 0, AddressingModes::Implicit));
-        vector[0x49] = Some(Operation::new("EOR", 2, 2, 0, AddressingModes::Immediate));
-        vector[0x4A] = Some(Operation::new("LSR", 2, 1, 0, AddressingModes::Accumulator));
-        vector[0x4B] = None;
-        vector[0x4C] = Some(Operation::new("JMP", 3, 3, 0, AddressingModes::Absolute));
-        vector[0x4D] = Some(Operation::new("EOR", 4, 3, 0, AddressingModes::Absolute));
-        vector[0x4E] = Some(Operation::new("LSR", 6, 3, 0, AddressingModes::Absolute));
-        vector[0x4F] = None;
+    table[0xD0] = Some(Operation::new(Bne, 2, 2, 1, AddressingModes::Relative));
+    table[0xD1] = Some(Operation::new(
+        Cmp,
+        5,
+        2,
+        1,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0xD2] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0xD3] = Some(Operation::unofficial(
+        Dcp,
+        8,
+        2,
+        0,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0xD4] = Some(Operation::unofficial(
+        Nop,
+        4,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0xD5] = Some(Operation::new(Cmp, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0xD6] = Some(Operation::new(Dec, 6, 2, 0, AddressingModes::ZeroPageX));
+    table[0xD7] = Some(Operation::unofficial(
+        Dcp,
+        6,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0xD8] = Some(Operation::new(Cld, 2, 1, 0, AddressingModes::Implicit));
+    table[0xD9] = Some(Operation::new(Cmp, 4, 3, 1, AddressingModes::AbsoluteY));
+    table[0xDA] = Some(Operation::unofficial(
+        Nop,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0xDB] = Some(Operation::unofficial(
+        Dcp,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0xDC] = Some(Operation::unofficial(
+        Nop,
+        4,
+        3,
+        1,
+        AddressingModes::AbsoluteX,
+    ));
+    table[0xDD] = Some(Operation::new(Cmp, 4, 3, 1, AddressingModes::AbsoluteX));
+    table[0xDE] = Some(Operation::new(Dec, 7, 3, 0, AddressingModes::AbsoluteX));
+    table[0xDF] = Some(Operation::unofficial(
+        Dcp,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteX,
+    ));
 
-        vector[0x50] = Some(Operation::new("BVC", 2, 2, 1, AddressingModes::Relative));
-        vector[0x51] = Some(Operation::new(
-            "EOR",
-            5,
-            2,
-            1,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0x52] = None;
-        vector[0x53] = None;
-        vector[0x54] = None;
-        vector[0x55] = Some(Operation::new("EOR", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x56] = Some(Operation::new("LSR", 6, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x57] = None;
-        vector[0x58] = Some(Operation::new("CLI", 2, 1, 0, AddressingModes::Implicit));
-        vector[0x59] = Some(Operation::new("EOR", 4, 3, 1, AddressingModes::AbsoluteY));
-        vector[0x5A] = None;
-        vector[0x5B] = None;
-        vector[0x5C] = None;
-        vector[0x5D] = Some(Operation::new("EOR", 4, 3, 1, AddressingModes::AbsoluteX));
-        vector[0x5E] = Some(Operation::new("LSR", 7, 3, 0, AddressingModes::AbsoluteX));
-        vector[0x5F] = None;
+    table[0xE0] = Some(Operation::new(Cpx, 2, 2, 0, AddressingModes::Immediate));
+    table[0xE1] = Some(Operation::new(
+        Sbc,
+        6,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0xE2] = Some(Operation::unofficial(
+        Nop,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0xE3] = Some(Operation::unofficial(
+        Isc,
+        8,
+        2,
+        0,
+        AddressingModes::IndexedIndirect,
+    ));
+    table[0xE4] = Some(Operation::new(Cpx, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0xE5] = Some(Operation::new(Sbc, 3, 2, 0, AddressingModes::ZeroPage));
+    table[0xE6] = Some(Operation::new(Inc, 5, 2, 0, AddressingModes::ZeroPage));
+    table[0xE7] = Some(Operation::unofficial(
+        Isc,
+        5,
+        2,
+        0,
+        AddressingModes::ZeroPage,
+    ));
+    table[0xE8] = Some(Operation::new(Inx, 2, 1, 0, AddressingModes::Implicit));
+    table[0xE9] = Some(Operation::new(Sbc, 2, 2, 0, AddressingModes::Immediate));
+    table[0xEA] = Some(Operation::new(Nop, 2, 1, 0, AddressingModes::Implicit));
+    table[0xEB] = Some(Operation::unofficial(
+        Sbc,
+        2,
+        2,
+        0,
+        AddressingModes::Immediate,
+    ));
+    table[0xEC] = Some(Operation::new(Cpx, 4, 3, 0, AddressingModes::Absolute));
+    table[0xED] = Some(Operation::new(Sbc, 4, 3, 0, AddressingModes::Absolute));
+    table[0xEE] = Some(Operation::new(Inc, 6, 3, 0, AddressingModes::Absolute));
+    table[0xEF] = Some(Operation::unofficial(
+        Isc,
+        6,
+        3,
+        0,
+        AddressingModes::Absolute,
+    ));
 
-        vector[0x60] = Some(Operation::new("RTS", 6, 1, 0, AddressingModes::Implicit));
-        vector[0x61] = Some(Operation::new(
-            "ADC",
-            6,
-            2,
-            0,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0x62] = None;
-        vector[0x63] = None;
-        vector[0x64] = None;
-        vector[0x65] = Some(Operation::new("ADC", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0x66] = Some(Operation::new("ROR", 5, 2, 0, AddressingModes::ZeroPage));
-        vector[0x67] = None;
-        vector[0x68] = Some(Operation::new("PLA", 4, 1, 0, AddressingModes::Implicit));
-        vector[0x69] = Some(Operation::new("ADC", 2, 2, 0, AddressingModes::Immediate));
-        vector[0x6A] = Some(Operation::new("ROR", 2, 1, 0, AddressingModes::Accumulator));
-        vector[0x6B] = None;
-        vector[0x6C] = Some(Operation::new("JMP", 5, 3, 0, AddressingModes::Indirect));
-        vector[0x6D] = Some(Operation::new("ADC", 4, 3, 0, AddressingModes::Absolute));
-        vector[0x6E] = Some(Operation::new("ROR", 6, 3, 0, AddressingModes::Absolute));
-        vector[0x6F] = None;
+    table[0xF0] = Some(Operation::new(Beq, 2, 2, 1, AddressingModes::Relative));
+    table[0xF1] = Some(Operation::new(
+        Sbc,
+        5,
+        2,
+        1,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0xF2] = Some(Operation::unofficial(
+        Jam,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0xF3] = Some(Operation::unofficial(
+        Isc,
+        8,
+        2,
+        0,
+        AddressingModes::IndirectIndexed,
+    ));
+    table[0xF4] = Some(Operation::unofficial(
+        Nop,
+        4,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0xF5] = Some(Operation::new(Sbc, 4, 2, 0, AddressingModes::ZeroPageX));
+    table[0xF6] = Some(Operation::new(Inc, 6, 2, 0, AddressingModes::ZeroPageX));
+    table[0xF7] = Some(Operation::unofficial(
+        Isc,
+        6,
+        2,
+        0,
+        AddressingModes::ZeroPageX,
+    ));
+    table[0xF8] = Some(Operation::new(Sed, 2, 1, 0, AddressingModes::Implicit));
+    table[0xF9] = Some(Operation::new(Sbc, 4, 3, 1, AddressingModes::AbsoluteY));
+    table[0xFA] = Some(Operation::unofficial(
+        Nop,
+        2,
+        1,
+        0,
+        AddressingModes::Implicit,
+    ));
+    table[0xFB] = Some(Operation::unofficial(
+        Isc,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteY,
+    ));
+    table[0xFC] = Some(Operation::unofficial(
+        Nop,
+        4,
+        3,
+        1,
+        AddressingModes::AbsoluteX,
+    ));
+    table[0xFD] = Some(Operation::new(Sbc, 4, 3, 1, AddressingModes::AbsoluteX));
+    table[0xFE] = Some(Operation::new(Inc, 7, 3, 0, AddressingModes::AbsoluteX));
+    table[0xFF] = Some(Operation::unofficial(
+        Isc,
+        7,
+        3,
+        0,
+        AddressingModes::AbsoluteX,
+    ));
 
-        vector[0x70] = Some(Operation::new("BVS", 2, 2, 1, AddressingModes::Relative));
-        vector[0x71] = Some(Operation::new(
-            "ADC",
-            5,
-            2,
-            1,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0x72] = None;
-        vector[0x73] = None;
-        vector[0x74] = None;
-        vector[0x75] = Some(Operation::new("ADC", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x76] = Some(Operation::new("ROR", 6, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x77] = None;
-        vector[0x78] = Some(Operation::new("SEI", 2, 1, 0, AddressingModes::Implicit));
-        vector[0x79] = Some(Operation::new("ADC", 4, 3, 1, AddressingModes::AbsoluteY));
-        vector[0x7A] = None;
-        vector[0x7B] = None;
-        vector[0x7C] = None;
-        vector[0x7D] = Some(Operation::new("ADC", 4, 3, 1, AddressingModes::AbsoluteX));
-        vector[0x7E] = Some(Operation::new("ROR", 7, 3, 0, AddressingModes::AbsoluteX));
-        vector[0x7F] = None;
+    table
+};
 
-        vector[0x80] = None;
-        vector[0x81] = Some(Operation::new(
-            "STA",
-            6,
-            2,
-            0,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0x82] = None;
-        vector[0x83] = None;
-        vector[0x84] = Some(Operation::new("STY", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0x85] = Some(Operation::new("STA", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0x86] = Some(Operation::new("STX", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0x87] = None;
-        vector[0x88] = Some(Operation::new("DEY", 2, 1, 0, AddressingModes::Implicit));
-        vector[0x89] = None;
-        vector[0x8A] = Some(Operation::new("TXA", 2, 1, 0, AddressingModes::Implicit));
-        vector[0x8B] = None;
-        vector[0x8C] = Some(Operation::new("STY", 4, 3, 0, AddressingModes::Absolute));
-        vector[0x8D] = Some(Operation::new("STA", 4, 3, 0, AddressingModes::Absolute));
-        vector[0x8E] = Some(Operation::new("STX", 4, 3, 0, AddressingModes::Absolute));
-        vector[0x8F] = None;
+#[cfg(test)]
+mod tests {
+    use super::OPERATION_INFORMATION;
+    use crate::operation::AddressingModes;
 
-        vector[0x90] = Some(Operation::new("BCC", 2, 2, 1, AddressingModes::Relative));
-        vector[0x91] = Some(Operation::new(
-            "STA",
-            6,
-            2,
-            0,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0x92] = None;
-        vector[0x93] = None;
-        vector[0x94] = Some(Operation::new("STY", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x95] = Some(Operation::new("STA", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0x96] = Some(Operation::new("STX", 4, 2, 0, AddressingModes::ZeroPageY));
-        vector[0x97] = None;
-        vector[0x98] = Some(Operation::new("TYA", 2, 1, 0, AddressingModes::Implicit));
-        vector[0x99] = Some(Operation::new("STA", 5, 3, 0, AddressingModes::AbsoluteY));
-        vector[0x9A] = Some(Operation::new("TXS", 2, 1, 0, AddressingModes::Implicit));
-        vector[0x9B] = None;
-        vector[0x9C] = None;
-        vector[0x9D] = Some(Operation::new("STA", 5, 3, 0, AddressingModes::AbsoluteX));
-        vector[0x9E] = None;
-        vector[0x9F] = None;
+    // Reference opcode matrix, see: https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+    // Entries read "<mnemonic> <mode> <cycles>". A leading `*` marks an
+    // unofficial opcode and a trailing `+` one extra cycle on a page cross.
+    #[rustfmt::skip]
+    const REFERENCE_MATRIX: [&str; 256] = [
+        // 0x
+        "BRK imp 7", "ORA izx 6", "*JAM imp 2", "*SLO izx 8",
+        "*NOP zp 3", "ORA zp 3", "ASL zp 5", "*SLO zp 5",
+        "PHP imp 3", "ORA imm 2", "ASL acc 2", "*ANC imm 2",
+        "*NOP abs 4", "ORA abs 4", "ASL abs 6", "*SLO abs 6",
+        // 1x
+        "BPL rel 2+", "ORA izy 5+", "*JAM imp 2", "*SLO izy 8",
+        "*NOP zpx 4", "ORA zpx 4", "ASL zpx 6", "*SLO zpx 6",
+        "CLC imp 2", "ORA absy 4+", "*NOP imp 2", "*SLO absy 7",
+        "*NOP absx 4+", "ORA absx 4+", "ASL absx 7", "*SLO absx 7",
+        // 2x
+        "JSR abs 6", "AND izx 6", "*JAM imp 2", "*RLA izx 8",
+        "BIT zp 3", "AND zp 3", "ROL zp 5", "*RLA zp 5",
+        "PLP imp 4", "AND imm 2", "ROL acc 2", "*ANC imm 2",
+        "BIT abs 4", "AND abs 4", "ROL abs 6", "*RLA abs 6",
+        // 3x
+        "BMI rel 2+", "AND izy 5+", "*JAM imp 2", "*RLA izy 8",
+        "*NOP zpx 4", "AND zpx 4", "ROL zpx 6", "*RLA zpx 6",
+        "SEC imp 2", "AND absy 4+", "*NOP imp 2", "*RLA absy 7",
+        "*NOP absx 4+", "AND absx 4+", "ROL absx 7", "*RLA absx 7",
+        // 4x
+        "RTI imp 6", "EOR izx 6", "*JAM imp 2", "*SRE izx 8",
+        "*NOP zp 3", "EOR zp 3", "LSR zp 5", "*SRE zp 5",
+        "PHA imp 3", "EOR imm 2", "LSR acc 2", "*ALR imm 2",
+        "JMP abs 3", "EOR abs 4", "LSR abs 6", "*SRE abs 6",
+        // 5x
+        "BVC rel 2+", "EOR izy 5+", "*JAM imp 2", "*SRE izy 8",
+        "*NOP zpx 4", "EOR zpx 4", "LSR zpx 6", "*SRE zpx 6",
+        "CLI imp 2", "EOR absy 4+", "*NOP imp 2", "*SRE absy 7",
+        "*NOP absx 4+", "EOR absx 4+", "LSR absx 7", "*SRE absx 7",
+        // 6x
+        "RTS imp 6", "ADC izx 6", "*JAM imp 2", "*RRA izx 8",
+        "*NOP zp 3", "ADC zp 3", "ROR zp 5", "*RRA zp 5",
+        "PLA imp 4", "ADC imm 2", "ROR acc 2", "*ARR imm 2",
+        "JMP ind 5", "ADC abs 4", "ROR abs 6", "*RRA abs 6",
+        // 7x
+        "BVS rel 2+", "ADC izy 5+", "*JAM imp 2", "*RRA izy 8",
+        "*NOP zpx 4", "ADC zpx 4", "ROR zpx 6", "*RRA zpx 6",
+        "SEI imp 2", "ADC absy 4+", "*NOP imp 2", "*RRA absy 7",
+        "*NOP absx 4+", "ADC absx 4+", "ROR absx 7", "*RRA absx 7",
+        // 8x
+        "*NOP imm 2", "STA izx 6", "*NOP imm 2", "*SAX izx 6",
+        "STY zp 3", "STA zp 3", "STX zp 3", "*SAX zp 3",
+        "DEY imp 2", "*NOP imm 2", "TXA imp 2", "*ANE imm 2",
+        "STY abs 4", "STA abs 4", "STX abs 4", "*SAX abs 4",
+        // 9x
+        "BCC rel 2+", "STA izy 6", "*JAM imp 2", "*SHA izy 6",
+        "STY zpx 4", "STA zpx 4", "STX zpy 4", "*SAX zpy 4",
+        "TYA imp 2", "STA absy 5", "TXS imp 2", "*TAS absy 5",
+        "*SHY absx 5", "STA absx 5", "*SHX absy 5", "*SHA absy 5",
+        // Ax
+        "LDY imm 2", "LDA izx 6", "LDX imm 2", "*LAX izx 6",
+        "LDY zp 3", "LDA zp 3", "LDX zp 3", "*LAX zp 3",
+        "TAY imp 2", "LDA imm 2", "TAX imp 2", "*LXA imm 2",
+        "LDY abs 4", "LDA abs 4", "LDX abs 4", "*LAX abs 4",
+        // Bx
+        "BCS rel 2+", "LDA izy 5+", "*JAM imp 2", "*LAX izy 5+",
+        "LDY zpx 4", "LDA zpx 4", "LDX zpy 4", "*LAX zpy 4",
+        "CLV imp 2", "LDA absy 4+", "TSX imp 2", "*LAS absy 4+",
+        "LDY absx 4+", "LDA absx 4+", "LDX absy 4+", "*LAX absy 4+",
+        // Cx
+        "CPY imm 2", "CMP izx 6", "*NOP imm 2", "*DCP izx 8",
+        "CPY zp 3", "CMP zp 3", "DEC zp 5", "*DCP zp 5",
+        "INY imp 2", "CMP imm 2", "DEX imp 2", "*SBX imm 2",
+        "CPY abs 4", "CMP abs 4", "DEC abs 6", "*DCP abs 6",
+        // Dx
+        "BNE rel 2+", "CMP izy 5+", "*JAM imp 2", "*DCP izy 8",
+        "*NOP zpx 4", "CMP zpx 4", "DEC zpx 6", "*DCP zpx 6",
+        "CLD imp 2", "CMP absy 4+", "*NOP imp 2", "*DCP absy 7",
+        "*NOP absx 4+", "CMP absx 4+", "DEC absx 7", "*DCP absx 7",
+        // Ex
+        "CPX imm 2", "SBC izx 6", "*NOP imm 2", "*ISC izx 8",
+        "CPX zp 3", "SBC zp 3", "INC zp 5", "*ISC zp 5",
+        "INX imp 2", "SBC imm 2", "NOP imp 2", "*SBC imm 2",
+        "CPX abs 4", "SBC abs 4", "INC abs 6", "*ISC abs 6",
+        // Fx
+        "BEQ rel 2+", "SBC izy 5+", "*JAM imp 2", "*ISC izy 8",
+        "*NOP zpx 4", "SBC zpx 4", "INC zpx 6", "*ISC zpx 6",
+        "SED imp 2", "SBC absy 4+", "*NOP imp 2", "*ISC absy 7",
+        "*NOP absx 4+", "SBC absx 4+", "INC absx 7", "*ISC absx 7",
+    ];
 
-        vector[0xA0] = Some(Operation::new("LDY", 2, 2, 0, AddressingModes::Immediate));
-        vector[0xA1] = Some(Operation::new(
-            "LDA",
-            6,
-            2,
-            0,
-            AddressingModes::IndexedIndirect,
-        ));
-        vector[0xA2] = Some(Operation::new("LDX", 2, 2, 0, AddressingModes::Immediate));
-        vector[0xA3] = None;
-        vector[0xA4] = Some(Operation::new("LDY", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0xA5] = Some(Operation::new("LDA", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0xA6] = Some(Operation::new("LDX", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0xA7] = None;
-        vector[0xA8] = Some(Operation::new("TAY", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xA9] = Some(Operation::new("LDA", 2, 2, 0, AddressingModes::Immediate));
-        vector[0xAA] = Some(Operation::new("TAX", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xAB] = None;
-        vector[0xAC] = Some(Operation::new("LDY", 4, 3, 0, AddressingModes::Absolute));
-        vector[0xAD] = Some(Operation::new("LDA", 4, 3, 0, AddressingModes::Absolute));
-        vector[0xAE] = Some(Operation::new("LDX", 4, 3, 0, AddressingModes::Absolute));
-        vector[0xAF] = None;
+    fn parse_mode(mode: &str) -> (AddressingModes, u8) {
+        match mode {
+            "imp" => (AddressingModes::Implicit, 1),
+            "acc" => (AddressingModes::Accumulator, 1),
+            "imm" => (AddressingModes::Immediate, 2),
+            "zp" => (AddressingModes::ZeroPage, 2),
+            "zpx" => (AddressingModes::ZeroPageX, 2),
+            "zpy" => (AddressingModes::ZeroPageY, 2),
+            "rel" => (AddressingModes::Relative, 2),
+            "abs" => (AddressingModes::Absolute, 3),
+            "absx" => (AddressingModes::AbsoluteX, 3),
+            "absy" => (AddressingModes::AbsoluteY, 3),
+            "ind" => (AddressingModes::Indirect, 3),
+            "izx" => (AddressingModes::IndexedIndirect, 2),
+            "izy" => (AddressingModes::IndirectIndexed, 2),
+            _ => panic!("Unknown mode {} in the reference matrix", mode),
+        }
+    }
 
-        vector[0xB0] = Some(Operation::new("BCS", 2, 2, 1, AddressingModes::Relative));
-        vector[0xB1] = Some(Operation::new(
-            "LDA",
-            5,
-            2,
-            1,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0xB2] = None;
-        vector[0xB3] = None;
-        vector[0xB4] = Some(Operation::new("LDY", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0xB5] = Some(Operation::new("LDA", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0xB6] = Some(Operation::new("LDX", 4, 2, 0, AddressingModes::ZeroPageY));
-        vector[0xB7] = None;
-        vector[0xB8] = Some(Operation::new("CLV", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xB9] = Some(Operation::new("LDA", 4, 3, 1, AddressingModes::AbsoluteY));
-        vector[0xBA] = Some(Operation::new("TSX", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xBB] = None;
-        vector[0xBC] = Some(Operation::new("LDY", 4, 3, 1, AddressingModes::AbsoluteX));
-        vector[0xBD] = Some(Operation::new("LDA", 4, 3, 1, AddressingModes::AbsoluteX));
-        vector[0xBE] = Some(Operation::new("LDX", 4, 3, 1, AddressingModes::AbsoluteY));
-        vector[0xBF] = None;
+    #[test]
+    fn operation_information_matches_reference_matrix() {
+        for (opcode, reference) in REFERENCE_MATRIX.iter().enumerate() {
+            let operation = match OPERATION_INFORMATION[opcode] {
+                Some(operation) => operation,
+                None => panic!("Opcode {:#04X} is missing", opcode),
+            };
 
-        vector[0xC0] = Some(Operation::new("CPY", 2, 2, 0, AddressingModes::Immediate));
-        vector[0xC1] = Some(Operation::new(
-            "CMP",
-            6,
-            2,
-            0,
-            AddressingModes::IndexedIndirect,
-        ));
-        vector[0xC2] = None;
-        vector[0xC3] = None;
-        vector[0xC4] = Some(Operation::new("CPY", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0xC5] = Some(Operation::new("CMP", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0xC6] = Some(Operation::new("DEC", 5, 2, 0, AddressingModes::ZeroPage));
-        vector[0xC7] = None;
-        vector[0xC8] = Some(Operation::new("INY", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xC9] = Some(Operation::new("CMP", 2, 2, 0, AddressingModes::Immediate));
-        vector[0xCA] = Some(Operation::new("DEX", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xCB] = None;
-        vector[0xCC] = Some(Operation::new("CPY", 4, 3, 0, AddressingModes::Absolute));
-        vector[0xCD] = Some(Operation::new("CMP", 4, 3, 0, AddressingModes::Absolute));
-        vector[0xCE] = Some(Operation::new("DEC", 6, 3, 0, AddressingModes::Absolute));
-        vector[0xCF] = None;
+            let parts: Vec<&str> = reference.split(' ').collect();
 
-        vector[0xD0] = Some(Operation::new("BNE", 2, 2, 1, AddressingModes::Relative));
-        vector[0xD1] = Some(Operation::new(
-            "CMP",
-            5,
-            2,
-            0,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0xD2] = None;
-        vector[0xD3] = None;
-        vector[0xD4] = None;
-        vector[0xD5] = Some(Operation::new("CMP", 4, 2, 0, AddressingModes::ZeroPageX));
-        vector[0xD6] = Some(Operation::new("DEC", 6, 2, 0, AddressingModes::ZeroPageX));
-        vector[0xD7] = None;
-        vector[0xD8] = Some(Operation::new("CLD", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xD9] = Some(Operation::new("CMP", 4, 3, 1, AddressingModes::AbsoluteY));
-        vector[0xDA] = None;
-        vector[0xDB] = None;
-        vector[0xDC] = None;
-        vector[0xDD] = Some(Operation::new("CMP", 4, 3, 1, AddressingModes::AbsoluteX));
-        vector[0xDE] = Some(Operation::new("DEC", 7, 3, 0, AddressingModes::AbsoluteX));
-        vector[0xDF] = None;
+            let (name, is_official) = match parts[0].strip_prefix('*') {
+                Some(name) => (name, false),
+                None => (parts[0], true),
+            };
 
-        vector[0xE0] = Some(Operation::new("CPX", 2, 2, 0, AddressingModes::Immediate));
-        vector[0xE1] = Some(Operation::new(
-            "SBC",
-            6,
-            2,
-            0,
-            AddressingModes::IndexedIndirect,
-        ));
-        vector[0xE2] = None;
-        vector[0xE3] = None;
-        vector[0xE4] = Some(Operation::new("CPX", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0xE5] = Some(Operation::new("SBC", 3, 2, 0, AddressingModes::ZeroPage));
-        vector[0xE6] = Some(Operation::new("INC", 5, 2, 0, AddressingModes::ZeroPage));
-        vector[0xE7] = None;
-        vector[0xE8] = Some(Operation::new("INX", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xE9] = Some(Operation::new("SBC", 2, 2, 0, AddressingModes::Immediate));
-        vector[0xEA] = Some(Operation::new("NOP", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xEB] = None;
-        vector[0xEC] = Some(Operation::new("CPX", 4, 3, 0, AddressingModes::Absolute));
-        vector[0xED] = Some(Operation::new("SBC", 4, 3, 0, AddressingModes::Absolute));
-        vector[0xEE] = Some(Operation::new("INC", 6, 3, 0, AddressingModes::Absolute));
-        vector[0xEF] = None;
+            let (mode, size) = parse_mode(parts[1]);
 
-        vector[0xF0] = Some(Operation::new("BEQ", 2, 2, 1, AddressingModes::Relative));
-        vector[0xF1] = Some(Operation::new(
-            "SBC",
-            5,
-            2,
-            1,
-            AddressingModes::IndirectIndexed,
-        ));
-        vector[0xF2] = None;
-        vector[0xF3] = None;
-        vector[0xF4] = None;
-        vector[0xF5] = Some(Operation::new("SBC", 4, 2, 0, AddressingModes::ZeroPage));
-        vector[0xF6] = Some(Operation::new("INC", 6, 2, 0, AddressingModes::ZeroPageX));
-        vector[0xF7] = None;
-        vector[0xF8] = Some(Operation::new("SED", 2, 1, 0, AddressingModes::Implicit));
-        vector[0xF9] = Some(Operation::new("SBC", 4, 3, 1, AddressingModes::AbsoluteY));
-        vector[0xFA] = None;
-        vector[0xFB] = None;
-        vector[0xFC] = None;
-        vector[0xFD] = Some(Operation::new("SBC", 4, 3, 1, AddressingModes::AbsoluteX));
-        vector[0xFE] = Some(Operation::new("INC", 7, 3, 0, AddressingModes::AbsoluteX));
-        vector[0xFF] = None;
+            let (cycles, page_cycles) = match parts[2].strip_suffix('+') {
+                Some(cycles) => (cycles, 1),
+                None => (parts[2], 0),
+            };
+            let cycles: u8 = cycles.parse().unwrap();
 
-        vector
-    };
+            let context = format!("opcode {:#04X} ({})", opcode, reference);
+            assert_eq!(operation.name, name, "{}", context);
+            assert_eq!(operation.is_official, is_official, "{}", context);
+            assert_eq!(operation.instruction_addressing_mode, mode, "{}", context);
+            assert_eq!(operation.instruction_size, size, "{}", context);
+            assert_eq!(operation.instruction_cycles, cycles, "{}", context);
+            assert_eq!(
+                operation.instruction_page_cycles, page_cycles,
+                "{}",
+                context
+            );
+        }
+    }
 }
@@ -0,0 +1,98 @@
+use bitflags::bitflags;
+
+bitflags! {
+    // In the order the controller shifts them out
+    #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+    pub struct Buttons: u8 {
+        const A      = 0b0000_0001;
+        const B      = 0b0000_0010;
+        const SELECT = 0b0000_0100;
+        const START  = 0b0000_1000;
+        const UP     = 0b0001_0000;
+        const DOWN   = 0b0010_0000;
+        const LEFT   = 0b0100_0000;
+        const RIGHT  = 0b1000_0000;
+    }
+}
+
+// A standard controller, see:
+// https://www.nesdev.org/wiki/Standard_controller
+//
+// Writing 1 then 0 to $4016 latches the buttons, each read of $4016 or $4017
+// then returns the next one in bit 0.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Controller {
+    buttons: Buttons,
+    // While set the shift register keeps reloading, so reads return A
+    strobe: bool,
+    shift: u8,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons.bits();
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe {
+            // Official controllers return 1 once all eight are read
+            self.shift = self.shift >> 1 | 0x80;
+        }
+        bit
+    }
+
+    // The bit the next read returns
+    pub fn peek(&self) -> u8 {
+        self.shift & 1
+    }
+
+    // The latch state, for save states
+    pub fn latch(&self) -> (bool, u8) {
+        (self.strobe, self.shift)
+    }
+
+    pub fn set_latch(&mut self, strobe: bool, shift: u8) {
+        self.strobe = strobe;
+        self.shift = shift;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Buttons, Controller};
+
+    #[test]
+    fn shifts_out_the_latched_buttons() {
+        let mut controller = Controller::new();
+        controller.set_buttons(Buttons::A | Buttons::START | Buttons::RIGHT);
+
+        controller.write(1);
+        assert_eq!(controller.read(), 1);
+        assert_eq!(controller.read(), 1);
+        controller.write(0);
+
+        // Pressing buttons after the latch changes nothing until the next one
+        controller.set_buttons(Buttons::empty());
+        let bits: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+        assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+}
@@ -1,37 +1,133 @@
-use crate::bus::Bus;
+use std::fmt;
+
+use log::debug;
+
+use crate::breakpoints::{BreakpointManager, StopReason};
+use crate::bus::{BadAccessPolicy, Bus, BusAccess, BusAccessKind};
+use crate::call_stack::{CallStack, Frame, FrameKind};
 use crate::constants::{
-    BitMasks, IRQ_INTERRUPT_VECTOR_ADDRESS, RESET_PROGRAM_COUNTER_ADDRESS, RESET_STACK_ADDRESS,
-    STACK_START, STATUS_REGISTER_INITIAL, U16_HIGH_BYTE_MASK, U16_LOW_BYTE_MASK,
+    BitMasks, IRQ_INTERRUPT_VECTOR_ADDRESS, NMI_INTERRUPT_VECTOR_ADDRESS,
+    RESET_PROGRAM_COUNTER_ADDRESS, RESET_STACK_ADDRESS, STACK_START, STATUS_REGISTER_INITIAL,
+    U16_HIGH_BYTE_MASK, U16_LOW_BYTE_MASK,
 };
+use crate::constants::{LogTargets, OPERATION_INFORMATION};
 use crate::cpu_flags::CpuFlags;
+use crate::memory::Memory;
+use crate::operation::{AddressingModes, Mnemonic, Operation};
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExecutionMode {
+    // Each instruction runs in one go, cycles come from the opcode table
+    Instruction,
+    // Each cycle is a bus access, dummy reads and writes included
+    CycleStepped,
+}
 
-pub struct Cpu {
+// A copy of the programmer visible registers, for tests and debuggers
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CpuState {
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+    pub register_accumulator: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub register_status: CpuFlags,
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{}",
+            self.program_counter,
+            self.register_accumulator,
+            self.register_x,
+            self.register_y,
+            self.stack_pointer,
+            self.register_status
+        )
+    }
+}
+
+// What one step did, for the hooks added with `add_step_hook`
+pub struct StepInfo<'a> {
+    // Where the instruction started, or the instruction an interrupt cut in
+    // before
+    pub program_counter: u16,
+    pub cycles: u8,
+    // The call stack before the step, outermost first
+    pub frames: &'a [Frame],
+    // Every bus access of the step in order, opcode fetches as reads
+    pub accesses: &'a [BusAccess],
+    // Which of `accesses` fetched instruction bytes, as `instruction_fetches`
+    pub fetches: u16,
+}
+
+pub type StepHook<M> = Box<dyn FnMut(&Cpu<M>, &StepInfo) + Send>;
+
+pub struct Cpu<M: Memory = Bus> {
     program_counter: u16,
     stack_pointer: u8,
     register_accumulator: u8,
     register_x: u8,
     register_y: u8,
     register_status: CpuFlags,
-    bus: Bus,
+    bus: M,
+    execution_mode: ExecutionMode,
+    // Bus accesses made by the current instruction
+    bus_cycles: u8,
+    // Bit n is set when access n of the current instruction fetched one of
+    // its own bytes
+    instruction_fetches: u16,
+    // The NES CPU has the decimal flag but no BCD arithmetic
+    decimal_mode_supported: bool,
+    call_stack: CallStack,
+    // The NMI level at the last sample, to detect edges
+    nmi_line: bool,
+    // An NMI edge was seen and the NMI has not been taken yet
+    nmi_pending: bool,
+    // The result of the last interrupt poll, the next step runs the interrupt
+    // sequence instead of an instruction
+    interrupt_pending: bool,
+    // Taken branches that stay on the page don't poll in their last cycle
+    skip_interrupt_poll: bool,
+    // The buffers below are only filled while there are hooks, so steps
+    // cost nothing extra without them
+    step_hooks: Vec<(usize, StepHook<M>)>,
+    next_step_hook_id: usize,
+    step_frames: Vec<Frame>,
+    step_accesses: Vec<BusAccess>,
 }
 
 // Implement Basic Functions for CPU
-impl Cpu {
-    pub fn new(bus: Bus) -> Self {
+impl<M: Memory> Cpu<M> {
+    pub fn new(bus: M) -> Self {
         let register = CpuFlags::from_bits(STATUS_REGISTER_INITIAL);
 
         match register {
-            Some(status) => {
-                return Cpu {
-                    program_counter: 0,
-                    stack_pointer: RESET_STACK_ADDRESS,
-                    register_accumulator: 0,
-                    register_x: 0,
-                    register_y: 0,
-                    register_status: status,
-                    bus: bus,
-                };
-            }
+            Some(status) => Cpu {
+                program_counter: 0,
+                stack_pointer: RESET_STACK_ADDRESS,
+                register_accumulator: 0,
+                register_x: 0,
+                register_y: 0,
+                register_status: status,
+                bus,
+                execution_mode: ExecutionMode::Instruction,
+                bus_cycles: 0,
+                instruction_fetches: 0,
+                decimal_mode_supported: false,
+                call_stack: CallStack::new(),
+                nmi_line: false,
+                nmi_pending: false,
+                interrupt_pending: false,
+                skip_interrupt_poll: false,
+                step_hooks: Vec::new(),
+                next_step_hook_id: 0,
+                step_frames: Vec::new(),
+                step_accesses: Vec::new(),
+            },
             None => panic!("Could not create CPU flags!"),
         }
     }
@@ -55,14 +151,700 @@ impl Cpu {
 
         let reset_pc = self.mem_read_u16(RESET_PROGRAM_COUNTER_ADDRESS);
         self.program_counter = reset_pc;
+        self.call_stack.clear();
+        self.nmi_pending = false;
+        self.interrupt_pending = false;
+        debug!(target: LogTargets::CPU, "Reset to ${:04X}", reset_pc);
+    }
+
+    pub fn set_execution_mode(&mut self, mode: ExecutionMode) {
+        self.execution_mode = mode;
+    }
+
+    // Turns on BCD arithmetic for ADC/SBC, as on a stock 6502
+    pub fn set_decimal_mode_supported(&mut self, supported: bool) {
+        self.decimal_mode_supported = supported;
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            register_accumulator: self.register_accumulator,
+            register_x: self.register_x,
+            register_y: self.register_y,
+            register_status: self.register_status,
+        }
+    }
+
+    // Also drops a polled interrupt and the current instruction's cycle
+    // count, which belong to the state being replaced
+    pub fn set_state(&mut self, state: CpuState) {
+        self.program_counter = state.program_counter;
+        self.stack_pointer = state.stack_pointer;
+        self.register_accumulator = state.register_accumulator;
+        self.register_x = state.register_x;
+        self.register_y = state.register_y;
+        self.register_status = state.register_status;
+        self.nmi_pending = false;
+        self.interrupt_pending = false;
+        self.skip_interrupt_poll = false;
+        self.bus_cycles = 0;
+        self.instruction_fetches = 0;
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    // Which bus accesses of the last step fetched the opcode or operand
+    // bytes, bit n for the nth access. Everything else was a data access,
+    // even at an address inside the instruction.
+    pub fn instruction_fetches(&self) -> u16 {
+        self.instruction_fetches
+    }
+
+    // Calls `hook` after every step, interrupts included. Returns an id for
+    // removing it again.
+    pub fn add_step_hook<F: FnMut(&Cpu<M>, &StepInfo) + Send + 'static>(
+        &mut self,
+        hook: F,
+    ) -> usize {
+        let id = self.next_step_hook_id;
+        self.next_step_hook_id += 1;
+        self.step_hooks.push((id, Box::new(hook)));
+        id
+    }
+
+    pub fn remove_step_hook(&mut self, id: usize) -> bool {
+        let count = self.step_hooks.len();
+        self.step_hooks.retain(|(hook_id, _)| *hook_id != id);
+        self.step_hooks.len() != count
+    }
+
+    pub fn bus(&self) -> &M {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut M {
+        &mut self.bus
+    }
+}
+
+// The registers and interrupt state. The execution mode and decimal support
+// are configuration and stay as they are.
+impl<M: Memory> Savestate for Cpu<M> {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.program_counter);
+        writer.write_u8(self.stack_pointer);
+        writer.write_u8(self.register_accumulator);
+        writer.write_u8(self.register_x);
+        writer.write_u8(self.register_y);
+        writer.write_u8(self.register_status.bits());
+        writer.write_bool(self.nmi_line);
+        writer.write_bool(self.nmi_pending);
+        writer.write_bool(self.interrupt_pending);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.program_counter = reader.read_u16()?;
+        self.stack_pointer = reader.read_u8()?;
+        self.register_accumulator = reader.read_u8()?;
+        self.register_x = reader.read_u8()?;
+        self.register_y = reader.read_u8()?;
+        self.register_status = CpuFlags::from_bits_truncate(reader.read_u8()?);
+        self.nmi_line = reader.read_bool()?;
+        self.nmi_pending = reader.read_bool()?;
+        self.interrupt_pending = reader.read_bool()?;
+        self.skip_interrupt_poll = false;
+
+        // The frames belong to a different history
+        self.call_stack.clear();
+        reader.finish()
+    }
+}
+
+// Instruction execution
+impl<M: Memory> Cpu<M> {
+    // Executes a single instruction, or the interrupt sequence when the last
+    // instruction polled one, and returns the number of cycles it took
+    pub fn step(&mut self) -> u8 {
+        self.bus_cycles = 0;
+        self.instruction_fetches = 0;
+
+        let program_counter = self.program_counter;
+        let hooked = !self.step_hooks.is_empty();
+        if hooked {
+            self.step_frames.clear();
+            self.step_frames.extend_from_slice(self.call_stack.frames());
+            self.step_accesses.clear();
+        }
+
+        let cycles = if self.interrupt_pending {
+            self.interrupt()
+        } else {
+            match self.execution_mode {
+                ExecutionMode::Instruction => self.step_instruction(),
+                ExecutionMode::CycleStepped => self.step_cycles(),
+            }
+        };
+
+        self.bus.tick(cycles);
+
+        if hooked {
+            self.run_step_hooks(program_counter, cycles);
+        }
+        cycles
+    }
+
+    fn run_step_hooks(&mut self, program_counter: u16, cycles: u8) {
+        // Taken out for the call, so the hooks can see the whole CPU
+        let mut hooks = std::mem::take(&mut self.step_hooks);
+        let frames = std::mem::take(&mut self.step_frames);
+        let accesses = std::mem::take(&mut self.step_accesses);
+
+        let step = StepInfo {
+            program_counter,
+            cycles,
+            frames: &frames,
+            accesses: &accesses,
+            fetches: self.instruction_fetches,
+        };
+        for (_, hook) in &mut hooks {
+            hook(self, &step);
+        }
+
+        self.step_hooks = hooks;
+        self.step_frames = frames;
+        self.step_accesses = accesses;
+    }
+
+    // Runs until an instruction jumps or branches to itself, which is how
+    // test ROMs signal that they are done. Returns the address of the trap,
+    // or fails once `max_instructions` ran without one, so a CPU bug that
+    // misses the trap fails instead of hanging.
+    pub fn run_until_trap(&mut self, max_instructions: u64) -> Result<u16, String> {
+        for _ in 0..max_instructions {
+            let program_counter = self.program_counter;
+            self.step();
+
+            if self.program_counter == program_counter {
+                return Ok(program_counter);
+            }
+        }
+
+        Err(format!(
+            "No trap after {} instructions, PC is ${:04X}",
+            max_instructions, self.program_counter
+        ))
+    }
+
+    fn step_instruction(&mut self) -> u8 {
+        let opcode = self.fetch_opcode();
+
+        let operation = match OPERATION_INFORMATION[opcode as usize] {
+            Some(operation) => operation,
+            None => panic!("Unknown opcode {:#04X}", opcode),
+        };
+
+        let (address, page_crossed) =
+            self.get_operand_address(operation.instruction_addressing_mode);
+        self.program_counter = self
+            .program_counter
+            .wrapping_add(operation.instruction_size as u16 - 1);
+
+        let mut cycles = operation.instruction_cycles;
+
+        if page_crossed {
+            cycles += operation.instruction_page_cycles;
+        }
+
+        // CLI, SEI and PLP change the flag after the poll, so their effect on
+        // interrupts is one instruction late
+        let interrupt_disable = self.register_status.contains(CpuFlags::INTERRUPT_DISABLE);
+        cycles += self.execute(operation, address);
+
+        match operation.mnemonic {
+            Mnemonic::Brk => {}
+            Mnemonic::Cli | Mnemonic::Sei | Mnemonic::Plp => {
+                self.poll_interrupts(interrupt_disable)
+            }
+            _ => self.poll_interrupts(self.register_status.contains(CpuFlags::INTERRUPT_DISABLE)),
+        }
+
+        cycles
+    }
+
+    // Returns any cycles spent on top of the table, which only branches do
+    fn execute(&mut self, operation: Operation, address: u16) -> u8 {
+        let accumulator_mode =
+            operation.instruction_addressing_mode == AddressingModes::Accumulator;
+
+        match operation.mnemonic {
+            Mnemonic::Adc => self.adc(address),
+            Mnemonic::And => self.and(address),
+            Mnemonic::Asl if accumulator_mode => self.asl_accumulator(),
+            Mnemonic::Asl => self.asl(address),
+            Mnemonic::Bcc => return self.bcc(address),
+            Mnemonic::Bcs => return self.bcs(address),
+            Mnemonic::Beq => return self.beq(address),
+            Mnemonic::Bit => self.bit(address),
+            Mnemonic::Bmi => return self.bmi(address),
+            Mnemonic::Bne => return self.bne(address),
+            Mnemonic::Bpl => return self.bpl(address),
+            Mnemonic::Brk => self.brk(),
+            Mnemonic::Bvc => return self.bvc(address),
+            Mnemonic::Bvs => return self.bvs(address),
+            Mnemonic::Clc => self.clear_carry_flag(),
+            Mnemonic::Cld => self.clear_decimal_mode_flag(),
+            Mnemonic::Cli => self.clear_interrupt_disable_flag(),
+            Mnemonic::Clv => self.clear_overflow_flag(),
+            Mnemonic::Cmp => self.cmp(address),
+            Mnemonic::Cpx => self.cpx(address),
+            Mnemonic::Cpy => self.cpy(address),
+            Mnemonic::Dec => self.dec(address),
+            Mnemonic::Dex => self.dex(),
+            Mnemonic::Dey => self.dey(),
+            Mnemonic::Eor => self.eor(address),
+            Mnemonic::Inc => self.inc(address),
+            Mnemonic::Inx => self.inx(),
+            Mnemonic::Iny => self.iny(),
+            Mnemonic::Jmp => self.jmp(address),
+            Mnemonic::Jsr => self.jsr(address),
+            Mnemonic::Lda => self.lda(address),
+            Mnemonic::Ldx => self.ldx(address),
+            Mnemonic::Ldy => self.ldy(address),
+            Mnemonic::Lsr if accumulator_mode => self.lsr_accumulator(),
+            Mnemonic::Lsr => self.lsr(address),
+            Mnemonic::Nop => self.nop(operation.instruction_addressing_mode, address),
+            Mnemonic::Ora => self.ora(address),
+            Mnemonic::Pha => self.pha(),
+            Mnemonic::Php => self.php(),
+            Mnemonic::Pla => self.pla(),
+            Mnemonic::Plp => self.plp(),
+            Mnemonic::Rol if accumulator_mode => self.rol_accumulator(),
+            Mnemonic::Rol => self.rol(address),
+            Mnemonic::Ror if accumulator_mode => self.ror_accumulator(),
+            Mnemonic::Ror => self.ror(address),
+            Mnemonic::Rti => self.rti(),
+            Mnemonic::Rts => self.rts(),
+            Mnemonic::Sbc => self.sbc(address),
+            Mnemonic::Sec => self.set_carry_flag(),
+            Mnemonic::Sed => self.set_decimal_mode_flag(),
+            Mnemonic::Sei => self.set_interrupt_disable_flag(),
+            Mnemonic::Sta => self.sta(address),
+            Mnemonic::Stx => self.stx(address),
+            Mnemonic::Sty => self.sty(address),
+            Mnemonic::Tax => self.tax(),
+            Mnemonic::Tay => self.tay(),
+            Mnemonic::Tsx => self.tsx(),
+            Mnemonic::Txa => self.txa(),
+            Mnemonic::Txs => self.txs(),
+            Mnemonic::Tya => self.tya(),
+
+            Mnemonic::Alr => self.alr(address),
+            Mnemonic::Anc => self.anc(address),
+            Mnemonic::Ane => self.ane(address),
+            Mnemonic::Arr => self.arr(address),
+            Mnemonic::Dcp => self.dcp(address),
+            Mnemonic::Isc => self.isc(address),
+            Mnemonic::Jam => self.jam(),
+            Mnemonic::Las => self.las(address),
+            Mnemonic::Lax => self.lax(address),
+            Mnemonic::Lxa => self.lxa(address),
+            Mnemonic::Rla => self.rla(address),
+            Mnemonic::Rra => self.rra(address),
+            Mnemonic::Sax => self.sax(address),
+            Mnemonic::Sbx => self.sbx(address),
+            Mnemonic::Sha => self.sha(address),
+            Mnemonic::Shx => self.shx(address),
+            Mnemonic::Shy => self.shy(address),
+            Mnemonic::Slo => self.slo(address),
+            Mnemonic::Sre => self.sre(address),
+            Mnemonic::Tas => self.tas(address),
+        }
+
+        0
+    }
+}
+
+// Running with the debugging features of the NES bus
+impl Cpu<Bus> {
+    // Like `step`, but fails when the instruction made an access the bus
+    // policy treats as an error
+    pub fn try_step(&mut self) -> Result<u8, String> {
+        let program_counter = self.program_counter;
+        self.bus.take_bad_access();
+        let cycles = self.step();
+
+        if self.bus.bad_access_policy() == BadAccessPolicy::Error {
+            if let Some(access) = self.bus.take_bad_access() {
+                return Err(format!("Bad {} by ${:04X}", access, program_counter));
+            }
+        }
+
+        Ok(cycles)
+    }
+
+    // Runs until a breakpoint or watchpoint fires, the bus traps on a bad
+    // access, or the program traps when the breakpoints stop on traps.
+    // Calling this again after a breakpoint continues past it.
+    pub fn run_with_breakpoints(&mut self, breakpoints: &mut BreakpointManager) -> StopReason {
+        loop {
+            if let Some(reason) = self.run_with_breakpoints_for(breakpoints, u64::MAX) {
+                return reason;
+            }
+        }
+    }
+
+    // Like `run_with_breakpoints`, but gives up after `max_instructions` so
+    // the caller can check for other reasons to stop in between
+    pub fn run_with_breakpoints_for(
+        &mut self,
+        breakpoints: &mut BreakpointManager,
+        max_instructions: u64,
+    ) -> Option<StopReason> {
+        for _ in 0..max_instructions {
+            let program_counter = self.program_counter;
+            let opcode = self.bus.peek(program_counter);
+
+            if let Some(reason) = breakpoints.check_execute(&self.state(), opcode) {
+                return Some(reason);
+            }
+
+            let watching = breakpoints.has_watchpoints();
+            if watching {
+                self.bus.start_recording();
+            }
+
+            self.bus.take_bad_access();
+            self.step();
+
+            if watching {
+                // Fetching the instruction bytes is not a data read
+                let fetches = self.instruction_fetches;
+                let accesses: Vec<_> = self
+                    .bus
+                    .stop_recording()
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(index, _)| fetches & (1 << index) == 0)
+                    .map(|(_, access)| access)
+                    .collect();
+
+                if let Some(reason) = breakpoints.check_accesses(&self.state(), &accesses) {
+                    return Some(reason);
+                }
+            }
+
+            if let Some(access) = self.bus.take_bad_access() {
+                return Some(StopReason::BadAccess {
+                    program_counter,
+                    access,
+                });
+            }
+
+            if breakpoints.stops_on_traps() && self.program_counter == program_counter {
+                return Some(StopReason::Trap(program_counter));
+            }
+        }
+
+        None
+    }
+}
+
+// Interrupts, see:
+// https://www.nesdev.org/wiki/CPU_interrupts
+//
+// The lines are polled at the end of each instruction, using their state at
+// the end of the second to last cycle. In cycle stepped mode they are sampled
+// before every bus access, which also gives NMI hijacking of BRK and IRQ and
+// the delay after taken branches. In instruction mode only the CLI, SEI and
+// PLP delay is modelled.
+impl<M: Memory> Cpu<M> {
+    fn poll_interrupts(&mut self, interrupt_disable: bool) {
+        let nmi = self.bus.nmi_line();
+        if nmi && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = nmi;
+
+        self.interrupt_pending = self.nmi_pending || (self.bus.irq_line() && !interrupt_disable);
+    }
+
+    // Called before each bus access, the last sample of an instruction is
+    // the one that counts
+    fn sample_interrupts(&mut self) {
+        if self.execution_mode != ExecutionMode::CycleStepped {
+            return;
+        }
+
+        if self.skip_interrupt_poll {
+            // The edge detector still runs every cycle
+            let interrupt_pending = self.interrupt_pending;
+            self.poll_interrupts(true);
+            self.interrupt_pending = interrupt_pending;
+        } else {
+            self.poll_interrupts(self.register_status.contains(CpuFlags::INTERRUPT_DISABLE));
+        }
+    }
+
+    // Chosen after the status is pushed, so an NMI that arrives during a BRK
+    // or IRQ sequence takes it over
+    fn interrupt_vector(&mut self) -> u16 {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            NMI_INTERRUPT_VECTOR_ADDRESS
+        } else {
+            IRQ_INTERRUPT_VECTOR_ADDRESS
+        }
+    }
+
+    // The BRK sequence with the opcode fetch replaced, the status is pushed
+    // with B clear
+    fn interrupt(&mut self) -> u8 {
+        let return_address = self.program_counter;
+        let stack_pointer = self.stack_pointer;
+
+        if self.execution_mode == ExecutionMode::CycleStepped {
+            self.mem_read(return_address);
+            self.mem_read(return_address);
+        }
+
+        self.stack_push_u16(return_address);
+
+        let mut flags = self.register_status;
+        flags.remove(CpuFlags::BREAK);
+        flags.insert(CpuFlags::UNUSED);
+        self.stack_push(flags.bits());
+
+        self.set_interrupt_disable_flag();
+
+        let vector = self.interrupt_vector();
+        self.program_counter = self.mem_read_u16(vector);
+        self.enter_frame(
+            FrameKind::Interrupt,
+            return_address,
+            return_address,
+            stack_pointer,
+        );
+
+        // The first instruction of the handler always runs
+        self.interrupt_pending = false;
+
+        7
+    }
+}
+
+// Cycle stepped execution, every cycle of an instruction is an explicit bus
+// access made in the same order as the hardware, see:
+// https://www.nesdev.org/6502_cpu.txt
+impl<M: Memory> Cpu<M> {
+    fn step_cycles(&mut self) -> u8 {
+        let opcode = self.fetch_opcode();
+
+        let operation = match OPERATION_INFORMATION[opcode as usize] {
+            Some(operation) => operation,
+            None => panic!("Unknown opcode {:#04X}", opcode),
+        };
+
+        match operation.mnemonic {
+            Mnemonic::Brk => {
+                self.mem_read(self.program_counter);
+                self.brk();
+            }
+            Mnemonic::Jsr => {
+                let lo = self.fetch() as u16;
+                let stack_pointer = self.stack_pointer;
+                self.mem_read(STACK_START + stack_pointer as u16);
+                self.stack_push_u16(self.program_counter);
+                let hi = self.fetch_read(self.program_counter) as u16;
+
+                // The program counter is still on the high byte of the operand
+                let call_site = self.program_counter.wrapping_sub(2);
+                let return_address = self.program_counter.wrapping_add(1);
+                self.program_counter = hi << 8 | lo;
+                self.enter_frame(
+                    FrameKind::Subroutine,
+                    call_site,
+                    return_address,
+                    stack_pointer,
+                );
+            }
+            Mnemonic::Rti => {
+                self.mem_read(self.program_counter);
+                self.mem_read(STACK_START + self.stack_pointer as u16);
+                self.rti();
+            }
+            Mnemonic::Rts => {
+                self.mem_read(self.program_counter);
+                self.mem_read(STACK_START + self.stack_pointer as u16);
+                self.rts();
+                self.mem_read(self.program_counter.wrapping_sub(1));
+            }
+            Mnemonic::Pha | Mnemonic::Php => {
+                self.mem_read(self.program_counter);
+                self.execute(operation, 0);
+            }
+            Mnemonic::Pla | Mnemonic::Plp => {
+                self.mem_read(self.program_counter);
+                self.mem_read(STACK_START + self.stack_pointer as u16);
+                self.execute(operation, 0);
+            }
+            _ if operation.instruction_addressing_mode == AddressingModes::Relative => {
+                let jump = self.fetch() as i8;
+                let next = self.program_counter;
+                let target = next.wrapping_add(jump as u16);
+
+                let extra_cycles = self.execute(operation, target);
+
+                if extra_cycles > 0 {
+                    self.skip_interrupt_poll = extra_cycles == 1;
+                    self.mem_read(next);
+                    self.skip_interrupt_poll = false;
+                }
+
+                if extra_cycles > 1 {
+                    self.mem_read((next & U16_HIGH_BYTE_MASK) | (target & U16_LOW_BYTE_MASK));
+                }
+            }
+            mnemonic => {
+                let address = self.get_operand_address_cycle_stepped(
+                    operation.instruction_addressing_mode,
+                    Self::always_reads_before_indexing(mnemonic),
+                );
+                self.execute(operation, address);
+            }
+        }
+
+        self.bus_cycles
+    }
+
+    fn fetch_opcode(&mut self) -> u8 {
+        self.sample_interrupts();
+        self.mark_next_access_as_fetch();
+        self.bus_cycles += 1;
+        let value = self.bus.fetch_opcode(self.program_counter);
+        self.record_access(self.program_counter, value, BusAccessKind::Read);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        value
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let value = self.fetch_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        value
+    }
+
+    fn fetch_u16(&mut self) -> u16 {
+        let lo = self.fetch() as u16;
+        let hi = self.fetch() as u16;
+        hi << 8 | lo
+    }
+
+    // Writes and read-modify-writes can't skip the read from the unfixed
+    // address, as they must not touch memory before the address is final
+    fn always_reads_before_indexing(mnemonic: Mnemonic) -> bool {
+        matches!(
+            mnemonic,
+            Mnemonic::Sta
+                | Mnemonic::Stx
+                | Mnemonic::Sty
+                | Mnemonic::Sax
+                | Mnemonic::Sha
+                | Mnemonic::Shx
+                | Mnemonic::Shy
+                | Mnemonic::Tas
+                | Mnemonic::Asl
+                | Mnemonic::Lsr
+                | Mnemonic::Rol
+                | Mnemonic::Ror
+                | Mnemonic::Inc
+                | Mnemonic::Dec
+                | Mnemonic::Slo
+                | Mnemonic::Rla
+                | Mnemonic::Sre
+                | Mnemonic::Rra
+                | Mnemonic::Dcp
+                | Mnemonic::Isc
+        )
+    }
+
+    fn get_operand_address_cycle_stepped(
+        &mut self,
+        mode: AddressingModes,
+        always_read_before_indexing: bool,
+    ) -> u16 {
+        match mode {
+            AddressingModes::Implicit | AddressingModes::Accumulator => {
+                self.mem_read(self.program_counter);
+                0
+            }
+            AddressingModes::Immediate | AddressingModes::Relative => {
+                self.mark_next_access_as_fetch();
+                let address = self.program_counter;
+                self.program_counter = self.program_counter.wrapping_add(1);
+                address
+            }
+            AddressingModes::ZeroPage => self.fetch() as u16,
+            AddressingModes::ZeroPageX => {
+                let base = self.fetch();
+                self.mem_read(base as u16);
+                base.wrapping_add(self.register_x) as u16
+            }
+            AddressingModes::ZeroPageY => {
+                let base = self.fetch();
+                self.mem_read(base as u16);
+                base.wrapping_add(self.register_y) as u16
+            }
+            AddressingModes::Absolute => self.fetch_u16(),
+            AddressingModes::AbsoluteX => {
+                let base = self.fetch_u16();
+                self.index_address(base, self.register_x, always_read_before_indexing)
+            }
+            AddressingModes::AbsoluteY => {
+                let base = self.fetch_u16();
+                self.index_address(base, self.register_y, always_read_before_indexing)
+            }
+            AddressingModes::Indirect => {
+                let operand = self.program_counter;
+                self.program_counter = self.program_counter.wrapping_add(2);
+                self.get_address_for_indirect(operand)
+            }
+            AddressingModes::IndexedIndirect => {
+                let pointer = self.fetch();
+                self.mem_read(pointer as u16);
+                self.mem_read_zero_page_u16(pointer.wrapping_add(self.register_x))
+            }
+            AddressingModes::IndirectIndexed => {
+                let pointer = self.fetch();
+                let base = self.mem_read_zero_page_u16(pointer);
+                self.index_address(base, self.register_y, always_read_before_indexing)
+            }
+        }
+    }
+
+    // The index is added to the low byte first, so the extra read lands in
+    // the base page while the high byte gets fixed up
+    fn index_address(&mut self, base: u16, index: u8, always_read: bool) -> u16 {
+        let address = base.wrapping_add(index as u16);
+
+        if always_read || Self::page_crossed(base, address) {
+            self.mem_read((base & U16_HIGH_BYTE_MASK) | (address & U16_LOW_BYTE_MASK));
+        }
+
+        address
     }
 }
 
 // CMP related operations
-impl Cpu {
+impl<M: Memory> Cpu<M> {
     fn compare(&mut self, address: u16, register_value: u8) {
         let value = self.mem_read(address);
+        self.compare_values(register_value, value);
+    }
 
+    fn compare_values(&mut self, register_value: u8, value: u8) {
         if register_value >= value {
             self.register_status.insert(CpuFlags::CARRY);
         } else {
@@ -86,91 +868,195 @@ impl Cpu {
 }
 
 // Branching related operations
-impl Cpu {
-    fn branch_helper(&mut self, condition: bool) {
-        if condition {
-            let jump = self.mem_read(self.program_counter) as i8;
-            let jump_address = self
-                .program_counter
-                .wrapping_add(1)
-                .wrapping_add(jump as u16);
-            self.program_counter = jump_address;
+impl<M: Memory> Cpu<M> {
+    fn branch_helper(&mut self, condition: bool, address: u16) -> u8 {
+        if !condition {
+            return 0;
         }
+
+        let extra_cycles =
+            if self.program_counter & U16_HIGH_BYTE_MASK == address & U16_HIGH_BYTE_MASK {
+                1
+            } else {
+                2
+            };
+
+        self.program_counter = address;
+        extra_cycles
     }
 
-    fn blp(&mut self) {
-        self.branch_helper(!self.register_status.contains(CpuFlags::NEGATIVE));
+    fn bpl(&mut self, address: u16) -> u8 {
+        self.branch_helper(!self.register_status.contains(CpuFlags::NEGATIVE), address)
     }
 
-    fn bcc(&mut self) {
-        self.branch_helper(!self.register_status.contains(CpuFlags::CARRY));
+    fn bcc(&mut self, address: u16) -> u8 {
+        self.branch_helper(!self.register_status.contains(CpuFlags::CARRY), address)
     }
 
-    fn bcs(&mut self) {
-        self.branch_helper(self.register_status.contains(CpuFlags::CARRY));
+    fn bcs(&mut self, address: u16) -> u8 {
+        self.branch_helper(self.register_status.contains(CpuFlags::CARRY), address)
     }
 
-    fn beq(&mut self) {
-        self.branch_helper(self.register_status.contains(CpuFlags::ZERO));
+    fn beq(&mut self, address: u16) -> u8 {
+        self.branch_helper(self.register_status.contains(CpuFlags::ZERO), address)
     }
 
-    fn bmi(&mut self) {
-        self.branch_helper(self.register_status.contains(CpuFlags::NEGATIVE));
+    fn bmi(&mut self, address: u16) -> u8 {
+        self.branch_helper(self.register_status.contains(CpuFlags::NEGATIVE), address)
     }
 
-    fn bne(&mut self) {
-        self.branch_helper(!self.register_status.contains(CpuFlags::ZERO));
+    fn bne(&mut self, address: u16) -> u8 {
+        self.branch_helper(!self.register_status.contains(CpuFlags::ZERO), address)
     }
 
-    fn bvc(&mut self) {
-        self.branch_helper(!self.register_status.contains(CpuFlags::OVERFLOW));
+    fn bvc(&mut self, address: u16) -> u8 {
+        self.branch_helper(!self.register_status.contains(CpuFlags::OVERFLOW), address)
     }
 
-    fn bvs(&mut self) {
-        self.branch_helper(self.register_status.contains(CpuFlags::OVERFLOW));
+    fn bvs(&mut self, address: u16) -> u8 {
+        self.branch_helper(self.register_status.contains(CpuFlags::OVERFLOW), address)
     }
 }
 
 // Implement Memory functions
-impl Cpu {
-    fn mem_read(&self, address: u16) -> u8 {
-        self.bus.mem_read(address)
+impl<M: Memory> Cpu<M> {
+    fn mem_read(&mut self, address: u16) -> u8 {
+        self.sample_interrupts();
+        self.bus_cycles += 1;
+        let value = self.bus.read(address);
+        self.record_access(address, value, BusAccessKind::Read);
+        value
     }
 
     fn mem_write(&mut self, address: u16, value: u8) {
-        self.bus.mem_write(address, value)
+        self.sample_interrupts();
+        self.bus_cycles += 1;
+        self.record_access(address, value, BusAccessKind::Write);
+        self.bus.write(address, value)
+    }
+
+    fn record_access(&mut self, address: u16, value: u8, kind: BusAccessKind) {
+        if !self.step_hooks.is_empty() {
+            self.step_accesses.push(BusAccess {
+                address,
+                value,
+                kind,
+            });
+        }
     }
 
-    fn mem_read_u16(&self, address: u16) -> u16 {
-        let lo = self.mem_read(address) as u16;
-        let hi = self.mem_read(address + 1) as u16;
-        (hi << 8) | (lo as u16)
+    // Read-modify-write instructions write the unmodified value back before
+    // the result, which only the cycle stepped mode reproduces
+    fn read_modify_write(&mut self, address: u16, operation: fn(&mut Self, u8) -> u8) -> u8 {
+        let value = self.mem_read(address);
+
+        if self.execution_mode == ExecutionMode::CycleStepped {
+            self.mem_write(address, value);
+        }
+
+        let value = operation(self, value);
+        self.mem_write(address, value);
+        value
+    }
+
+    // Reads a byte of the instruction itself
+    fn fetch_read(&mut self, address: u16) -> u8 {
+        self.mark_next_access_as_fetch();
+        self.mem_read(address)
+    }
+
+    fn fetch_read_u16(&mut self, address: u16) -> u16 {
+        let lo = self.fetch_read(address) as u16;
+        let hi = self.fetch_read(address.wrapping_add(1)) as u16;
+        (hi << 8) | lo
     }
 
-    fn get_address_for_indirect(&self) -> u16 {
-        let addr = self.mem_read_u16(self.program_counter);
+    fn mark_next_access_as_fetch(&mut self) {
+        self.instruction_fetches |= 1 << self.bus_cycles;
+    }
 
-        let mut address = self.mem_read_u16(addr);
+    fn mem_read_u16(&mut self, address: u16) -> u16 {
+        let lo = self.mem_read(address) as u16;
+        let hi = self.mem_read(address.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
 
-        if addr & U16_LOW_BYTE_MASK == U16_LOW_BYTE_MASK {
-            let lo = self.mem_read(address);
-            let hi = self.mem_read(address & U16_HIGH_BYTE_MASK);
-            address = (hi as u16) << 8 | (lo as u16);
+    // Expects the program counter to point at the first operand byte. The
+    // returned flag is set when indexing crossed into another page.
+    fn get_operand_address(&mut self, mode: AddressingModes) -> (u16, bool) {
+        let operand = self.program_counter;
+
+        match mode {
+            AddressingModes::Implicit | AddressingModes::Accumulator => (0, false),
+            AddressingModes::Immediate => {
+                self.mark_next_access_as_fetch();
+                (operand, false)
+            }
+            AddressingModes::ZeroPage => (self.fetch_read(operand) as u16, false),
+            AddressingModes::ZeroPageX => {
+                let address = self.fetch_read(operand).wrapping_add(self.register_x);
+                (address as u16, false)
+            }
+            AddressingModes::ZeroPageY => {
+                let address = self.fetch_read(operand).wrapping_add(self.register_y);
+                (address as u16, false)
+            }
+            AddressingModes::Relative => {
+                let jump = self.fetch_read(operand) as i8;
+                let address = operand.wrapping_add(1).wrapping_add(jump as u16);
+                (address, false)
+            }
+            AddressingModes::Absolute => (self.fetch_read_u16(operand), false),
+            AddressingModes::AbsoluteX => {
+                let base = self.fetch_read_u16(operand);
+                let address = base.wrapping_add(self.register_x as u16);
+                (address, Self::page_crossed(base, address))
+            }
+            AddressingModes::AbsoluteY => {
+                let base = self.fetch_read_u16(operand);
+                let address = base.wrapping_add(self.register_y as u16);
+                (address, Self::page_crossed(base, address))
+            }
+            AddressingModes::Indirect => (self.get_address_for_indirect(operand), false),
+            AddressingModes::IndexedIndirect => {
+                let pointer = self.fetch_read(operand).wrapping_add(self.register_x);
+                (self.mem_read_zero_page_u16(pointer), false)
+            }
+            AddressingModes::IndirectIndexed => {
+                let pointer = self.fetch_read(operand);
+                let base = self.mem_read_zero_page_u16(pointer);
+                let address = base.wrapping_add(self.register_y as u16);
+                (address, Self::page_crossed(base, address))
+            }
         }
+    }
 
-        address
+    fn page_crossed(base: u16, address: u16) -> bool {
+        base & U16_HIGH_BYTE_MASK != address & U16_HIGH_BYTE_MASK
     }
 
-    fn mem_write_u16(&mut self, address: u16, value: u16) {
-        let hi = (value >> 8) as u8;
-        let lo = (value & 0xFF) as u8;
-        self.mem_write(address, lo);
-        self.mem_write(address + 1, hi)
+    // Pointers stored in the zero page wrap around within it
+    fn mem_read_zero_page_u16(&mut self, pointer: u8) -> u16 {
+        let lo = self.mem_read(pointer as u16) as u16;
+        let hi = self.mem_read(pointer.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    // The pointer's high byte is fetched without carrying into the next
+    // page, so JMP ($10FF) reads $10FF and $1000
+    fn get_address_for_indirect(&mut self, operand: u16) -> u16 {
+        let addr = self.fetch_read_u16(operand);
+
+        let lo = self.mem_read(addr);
+        let hi =
+            self.mem_read((addr & U16_HIGH_BYTE_MASK) | (addr.wrapping_add(1) & U16_LOW_BYTE_MASK));
+
+        (hi as u16) << 8 | (lo as u16)
     }
 }
 
 // Implement Stack functions for Cpu
-impl Cpu {
+impl<M: Memory> Cpu<M> {
     fn stack_push(&mut self, val: u8) {
         let address = STACK_START + self.stack_pointer as u16;
         self.mem_write(address, val);
@@ -186,10 +1072,29 @@ impl Cpu {
 
     fn stack_pop(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.call_stack.unwind(self.stack_pointer);
         let address = STACK_START + self.stack_pointer as u16;
         self.mem_read(address)
     }
 
+    // Records a call or interrupt on the shadow call stack, once the program
+    // counter is at the target
+    fn enter_frame(
+        &mut self,
+        kind: FrameKind,
+        call_site: u16,
+        return_address: u16,
+        stack_pointer: u8,
+    ) {
+        self.call_stack.push(Frame {
+            kind,
+            call_site,
+            target: self.program_counter,
+            return_address,
+            stack_pointer,
+        });
+    }
+
     fn stack_pop_u16(&mut self) -> u16 {
         let lo = self.stack_pop() as u16;
         let hi = self.stack_pop() as u16;
@@ -199,7 +1104,7 @@ impl Cpu {
 }
 
 // Flag related functions
-impl Cpu {
+impl<M: Memory> Cpu<M> {
     fn update_negative_flag(&mut self, value: u8) {
         if value >> 7 == 1 {
             self.register_status.insert(CpuFlags::NEGATIVE);
@@ -251,14 +1156,14 @@ impl Cpu {
 }
 
 // Register related functions
-impl Cpu {
+impl<M: Memory> Cpu<M> {
     fn set_accumulator(&mut self, value: u8) {
         self.register_accumulator = value;
         self.update_zero_and_negative_flags(self.register_accumulator);
     }
 
     fn add_to_accumulator(&mut self, value: u8) {
-        let carry_val = if (self.register_status.contains(CpuFlags::CARRY)) {
+        let carry_val = if self.register_status.contains(CpuFlags::CARRY) {
             1
         } else {
             0
@@ -289,35 +1194,127 @@ impl Cpu {
         // Sets value of accumulator and updates zero and negative flags
         self.set_accumulator(res);
     }
-}
 
-// Operation functions
-impl Cpu {
-    fn asl_accumulator(&mut self) {
-        let mut val = self.register_accumulator;
+    fn subtract_from_accumulator(&mut self, value: u8) {
+        let accumulator = self.register_accumulator;
+        let borrow = !self.register_status.contains(CpuFlags::CARRY) as i16;
+
+        let value_twos_complement = (value as i8).wrapping_neg();
 
-        if val >> 7 == 1 {
-            self.set_carry_flag();
+        // We sub here and then if carry flag is set we can add it back or
+        // not.
+        let value_twos_complement = value_twos_complement.wrapping_sub(1);
+
+        let value_twos_complement = value_twos_complement as u8;
+
+        self.add_to_accumulator(value_twos_complement);
+
+        // In decimal mode the flags still come from the binary subtraction
+        if self.decimal_mode_active() {
+            let mut low = (accumulator & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+            if low < 0 {
+                low = ((low - 0x06) & 0x0F) - 0x10;
+            }
+
+            let mut result = (accumulator & 0xF0) as i16 - (value & 0xF0) as i16 + low;
+            if result < 0 {
+                result -= 0x60;
+            }
+
+            self.register_accumulator = result as u8;
+        }
+    }
+
+    fn decimal_mode_active(&self) -> bool {
+        self.decimal_mode_supported && self.register_status.contains(CpuFlags::DECIMAL_MODE)
+    }
+
+    fn add_with_carry(&mut self, value: u8) {
+        if self.decimal_mode_active() {
+            self.add_to_accumulator_decimal(value);
         } else {
-            self.clear_carry_flag();
+            self.add_to_accumulator(value);
         }
+    }
 
-        val = val << 1;
-        self.set_accumulator(val);
+    // NMOS 6502 BCD addition, see: http://www.6502.org/tutorials/decimal_mode.html
+    // Z comes from the binary sum while N and V are taken before the high
+    // digit gets adjusted.
+    fn add_to_accumulator_decimal(&mut self, value: u8) {
+        let accumulator = self.register_accumulator;
+        let carry = self.register_status.contains(CpuFlags::CARRY) as u16;
+
+        let binary = accumulator as u16 + value as u16 + carry;
+        self.update_zero_flag(binary as u8);
+
+        let mut low = (accumulator & 0x0F) as u16 + (value & 0x0F) as u16 + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+
+        let mut result = (accumulator & 0xF0) as u16 + (value & 0xF0) as u16 + low;
+
+        let signed = (accumulator & 0xF0) as i8 as i16 + (value & 0xF0) as i8 as i16 + low as i16;
+        self.register_status
+            .set(CpuFlags::OVERFLOW, !(-128..=127).contains(&signed));
+        self.update_negative_flag(result as u8);
+
+        if result >= 0xA0 {
+            result += 0x60;
+        }
+
+        self.register_status.set(CpuFlags::CARRY, result >= 0x100);
+        self.register_accumulator = result as u8;
     }
+}
 
-    fn asl(&mut self, address: u16) {
-        let mut val = self.mem_read(address);
+// Shift and rotate helpers, these only update the carry flag
+impl<M: Memory> Cpu<M> {
+    fn shift_left(&mut self, value: u8) -> u8 {
+        self.register_status
+            .set(CpuFlags::CARRY, value & BitMasks::SEVENTH != 0);
+        value << 1
+    }
+
+    fn shift_right(&mut self, value: u8) -> u8 {
+        self.register_status
+            .set(CpuFlags::CARRY, value & BitMasks::ZERO != 0);
+        value >> 1
+    }
 
-        if val >> 7 == 1 {
-            self.set_carry_flag();
+    fn rotate_left(&mut self, value: u8) -> u8 {
+        let current_carry = self.register_status.contains(CpuFlags::CARRY);
+        let value = self.shift_left(value);
+
+        if current_carry {
+            value | BitMasks::ZERO
         } else {
-            self.clear_carry_flag();
+            value
         }
+    }
 
-        val = val << 1;
-        self.mem_write(address, val);
-        self.update_zero_and_negative_flags(val);
+    fn rotate_right(&mut self, value: u8) -> u8 {
+        let current_carry = self.register_status.contains(CpuFlags::CARRY);
+        let value = self.shift_right(value);
+
+        if current_carry {
+            value | BitMasks::SEVENTH
+        } else {
+            value
+        }
+    }
+}
+
+// Operation functions
+impl<M: Memory> Cpu<M> {
+    fn asl_accumulator(&mut self) {
+        let value = self.shift_left(self.register_accumulator);
+        self.set_accumulator(value);
+    }
+
+    fn asl(&mut self, address: u16) {
+        let value = self.read_modify_write(address, Self::shift_left);
+        self.update_zero_and_negative_flags(value);
     }
 
     fn and(&mut self, address: u16) {
@@ -328,7 +1325,7 @@ impl Cpu {
 
     fn adc(&mut self, address: u16) {
         let val = self.mem_read(address);
-        self.add_to_accumulator(val);
+        self.add_with_carry(val);
     }
 
     fn bit(&mut self, address: u16) {
@@ -349,19 +1346,37 @@ impl Cpu {
     }
 
     fn brk(&mut self) {
-        self.stack_push_u16(self.program_counter);
-        self.stack_push(self.register_status.bits());
+        let call_site = self.program_counter.wrapping_sub(1);
+        let stack_pointer = self.stack_pointer;
+
+        // The byte after BRK is skipped, RTI returns past it
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let return_address = self.program_counter;
+        self.stack_push_u16(return_address);
+
+        let mut flags = self.register_status;
+        flags.insert(CpuFlags::BREAK);
+        flags.insert(CpuFlags::UNUSED);
+        self.stack_push(flags.bits());
+
+        self.set_interrupt_disable_flag();
 
-        let new_pc = self.mem_read_u16(IRQ_INTERRUPT_VECTOR_ADDRESS);
+        let vector = self.interrupt_vector();
+        let new_pc = self.mem_read_u16(vector);
         self.program_counter = new_pc;
+        self.enter_frame(
+            FrameKind::Interrupt,
+            call_site,
+            return_address,
+            stack_pointer,
+        );
 
-        self.register_status.insert(CpuFlags::BREAK);
+        // Like the interrupt sequence, BRK doesn't poll for interrupts
+        self.interrupt_pending = false;
     }
 
     fn dec(&mut self, address: u16) {
-        let value = self.mem_read(address);
-        let value = value.wrapping_sub(1);
-        self.mem_write(address, value);
+        let value = self.read_modify_write(address, |_, value| value.wrapping_sub(1));
         self.update_zero_and_negative_flags(value);
     }
 
@@ -382,9 +1397,7 @@ impl Cpu {
     }
 
     fn inc(&mut self, address: u16) {
-        let value = self.mem_read(address);
-        let value = value.wrapping_add(1);
-        self.mem_write(address, value);
+        let value = self.read_modify_write(address, |_, value| value.wrapping_add(1));
         self.update_zero_and_negative_flags(value);
     }
 
@@ -394,24 +1407,28 @@ impl Cpu {
     }
 
     fn iny(&mut self) {
-        self.register_y = self.register_x.wrapping_add(1);
+        self.register_y = self.register_y.wrapping_add(1);
         self.update_zero_and_negative_flags(self.register_y);
     }
 
-    fn jmp_absolute(&mut self) {
-        let pc = self.mem_read_u16(self.program_counter);
-        self.program_counter = pc;
-    }
-
-    fn jmp_indirect(&mut self) {
-        let address = self.get_address_for_indirect();
+    fn jmp(&mut self, address: u16) {
         self.program_counter = address;
     }
 
     fn jsr(&mut self, address: u16) {
-        let temp_pc = self.program_counter - 1;
+        let stack_pointer = self.stack_pointer;
+        let return_address = self.program_counter;
+        let temp_pc = self.program_counter.wrapping_sub(1);
         self.stack_push_u16(temp_pc);
         self.program_counter = address;
+
+        let call_site = return_address.wrapping_sub(3);
+        self.enter_frame(
+            FrameKind::Subroutine,
+            call_site,
+            return_address,
+            stack_pointer,
+        );
     }
 
     fn lda(&mut self, address: u16) {
@@ -432,30 +1449,20 @@ impl Cpu {
     }
 
     fn lsr_accumulator(&mut self) {
-        let accumulator = self.register_accumulator;
-
-        if accumulator & 0b00000001 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-
-        let accumulator = accumulator >> 1;
-        self.set_accumulator(accumulator);
+        let value = self.shift_right(self.register_accumulator);
+        self.set_accumulator(value);
     }
 
     fn lsr(&mut self, address: u16) {
-        let value = self.mem_read(address);
+        let value = self.read_modify_write(address, Self::shift_right);
+        self.update_zero_and_negative_flags(value);
+    }
 
-        if value & 0b00000001 == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
+    // Unofficial NOPs with an operand still read it
+    fn nop(&mut self, mode: AddressingModes, address: u16) {
+        if mode != AddressingModes::Implicit {
+            self.mem_read(address);
         }
-
-        let value = value >> 1;
-        self.mem_write(address, value);
-        self.update_zero_and_negative_flags(value);
     }
 
     fn ora(&mut self, address: u16) {
@@ -469,7 +1476,7 @@ impl Cpu {
 
     // https://www.masswerk.at/6502/6502_instruction_set.html#PHP
     fn php(&mut self) {
-        let mut flags = self.register_status.clone();
+        let mut flags = self.register_status;
         flags.insert(CpuFlags::BREAK);
         flags.insert(CpuFlags::UNUSED);
         self.stack_push(flags.bits());
@@ -498,81 +1505,23 @@ impl Cpu {
     }
 
     fn rol_accumulator(&mut self) {
-        let mut accumulator = self.register_accumulator;
-        let current_carry = self.register_status.contains(CpuFlags::CARRY);
-
-        if accumulator & BitMasks::SEVENTH == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-
-        accumulator = accumulator << 1;
-
-        if current_carry {
-            accumulator = accumulator | BitMasks::ZERO;
-        }
-
-        self.set_accumulator(accumulator);
+        let value = self.rotate_left(self.register_accumulator);
+        self.set_accumulator(value);
     }
 
     fn rol(&mut self, address: u16) {
-        let mut value = self.mem_read(address);
-        let current_carry = self.register_status.contains(CpuFlags::CARRY);
-
-        if value & BitMasks::SEVENTH == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-
-        value = value << 1;
-
-        if current_carry {
-            value = value | BitMasks::ZERO;
-        }
-
-        self.mem_write(address, value);
-        self.update_negative_flag(value);
+        let value = self.read_modify_write(address, Self::rotate_left);
+        self.update_zero_and_negative_flags(value);
     }
 
     fn ror_accumulator(&mut self) {
-        let mut accumulator = self.register_accumulator;
-        let current_carry = self.register_status.contains(CpuFlags::CARRY);
-
-        if accumulator & BitMasks::ZERO == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-
-        accumulator = accumulator >> 1;
-
-        if current_carry {
-            accumulator = accumulator | BitMasks::SEVENTH;
-        }
-
-        self.set_accumulator(accumulator);
+        let value = self.rotate_right(self.register_accumulator);
+        self.set_accumulator(value);
     }
 
     fn ror(&mut self, address: u16) {
-        let mut value = self.mem_read(address);
-        let current_carry = self.register_status.contains(CpuFlags::CARRY);
-
-        if value & BitMasks::ZERO == 1 {
-            self.set_carry_flag();
-        } else {
-            self.clear_carry_flag();
-        }
-
-        value = value >> 1;
-
-        if current_carry {
-            value = value | BitMasks::SEVENTH;
-        }
-
-        self.mem_write(address, value);
-        self.update_negative_flag(value);
+        let value = self.read_modify_write(address, Self::rotate_right);
+        self.update_zero_and_negative_flags(value);
     }
 
     fn rti(&mut self) {
@@ -588,17 +1537,8 @@ impl Cpu {
     }
 
     fn sbc(&mut self, address: u16) {
-        let value = self.mem_read(address) as i8;
-
-        let value_twos_complement = value.wrapping_neg();
-
-        // We sub here and then if carry flag is set we can add it back or
-        // not.
-        let value_twos_complement = value_twos_complement.wrapping_sub(1);
-
-        let value_twos_complement = value_twos_complement as u8;
-
-        self.add_to_accumulator(value_twos_complement);
+        let value = self.mem_read(address);
+        self.subtract_from_accumulator(value);
     }
 
     fn sta(&mut self, address: u16) {
@@ -642,6 +1582,7 @@ impl Cpu {
     fn txs(&mut self) {
         let value = self.register_x;
         self.stack_pointer = value;
+        self.call_stack.unwind(self.stack_pointer);
     }
 
     fn tya(&mut self) {
@@ -649,3 +1590,321 @@ impl Cpu {
         self.set_accumulator(value);
     }
 }
+
+// Unofficial operation functions
+impl<M: Memory> Cpu<M> {
+    fn alr(&mut self, address: u16) {
+        self.and(address);
+        self.lsr_accumulator();
+    }
+
+    fn anc(&mut self, address: u16) {
+        self.and(address);
+        let negative = self.register_status.contains(CpuFlags::NEGATIVE);
+        self.register_status.set(CpuFlags::CARRY, negative);
+    }
+
+    // Unstable on real hardware, 0xEE is the most commonly observed constant
+    fn ane(&mut self, address: u16) {
+        let value = self.mem_read(address);
+        let accumulator = (self.register_accumulator | 0xEE) & self.register_x & value;
+        self.set_accumulator(accumulator);
+    }
+
+    fn arr(&mut self, address: u16) {
+        self.and(address);
+        self.ror_accumulator();
+
+        let accumulator = self.register_accumulator;
+        let bit_six = accumulator & BitMasks::SIXTH != 0;
+        let bit_five = accumulator & BitMasks::FIFTH != 0;
+        self.register_status.set(CpuFlags::CARRY, bit_six);
+        self.register_status
+            .set(CpuFlags::OVERFLOW, bit_six ^ bit_five);
+    }
+
+    fn dcp(&mut self, address: u16) {
+        let value = self.read_modify_write(address, |_, value| value.wrapping_sub(1));
+        self.compare_values(self.register_accumulator, value);
+    }
+
+    fn isc(&mut self, address: u16) {
+        let value = self.read_modify_write(address, |_, value| value.wrapping_add(1));
+        self.subtract_from_accumulator(value);
+    }
+
+    // Locks up the CPU, executing it again keeps the program counter in place
+    fn jam(&mut self) {
+        self.program_counter = self.program_counter.wrapping_sub(1);
+    }
+
+    fn las(&mut self, address: u16) {
+        let value = self.mem_read(address) & self.stack_pointer;
+        self.register_x = value;
+        self.stack_pointer = value;
+        self.call_stack.unwind(self.stack_pointer);
+        self.set_accumulator(value);
+    }
+
+    fn lax(&mut self, address: u16) {
+        self.lda(address);
+        self.register_x = self.register_accumulator;
+    }
+
+    fn lxa(&mut self, address: u16) {
+        let value = self.mem_read(address);
+        let value = (self.register_accumulator | 0xEE) & value;
+        self.register_x = value;
+        self.set_accumulator(value);
+    }
+
+    fn rla(&mut self, address: u16) {
+        let value = self.read_modify_write(address, Self::rotate_left);
+        self.set_accumulator(self.register_accumulator & value);
+    }
+
+    fn rra(&mut self, address: u16) {
+        let value = self.read_modify_write(address, Self::rotate_right);
+        self.add_with_carry(value);
+    }
+
+    fn sax(&mut self, address: u16) {
+        let value = self.register_accumulator & self.register_x;
+        self.mem_write(address, value);
+    }
+
+    fn sbx(&mut self, address: u16) {
+        let value = self.mem_read(address);
+        let register = self.register_accumulator & self.register_x;
+
+        self.register_status.set(CpuFlags::CARRY, register >= value);
+
+        self.register_x = register.wrapping_sub(value);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    // The SH* family ANDs the stored value with the high byte of the base
+    // address plus one. When indexing crosses a page, that value also ends
+    // up as the high byte of the target address.
+    fn store_and_high_byte(&mut self, address: u16, index: u8, value: u8) {
+        let base = address.wrapping_sub(index as u16);
+        let high = ((base >> 8) as u8).wrapping_add(1);
+        let value = value & high;
+
+        let address = if Self::page_crossed(base, address) {
+            ((value as u16) << 8) | (address & U16_LOW_BYTE_MASK)
+        } else {
+            address
+        };
+
+        self.mem_write(address, value);
+    }
+
+    fn sha(&mut self, address: u16) {
+        let value = self.register_accumulator & self.register_x;
+        self.store_and_high_byte(address, self.register_y, value);
+    }
+
+    fn shx(&mut self, address: u16) {
+        self.store_and_high_byte(address, self.register_y, self.register_x);
+    }
+
+    fn shy(&mut self, address: u16) {
+        self.store_and_high_byte(address, self.register_x, self.register_y);
+    }
+
+    fn slo(&mut self, address: u16) {
+        let value = self.read_modify_write(address, Self::shift_left);
+        self.set_accumulator(self.register_accumulator | value);
+    }
+
+    fn sre(&mut self, address: u16) {
+        let value = self.read_modify_write(address, Self::shift_right);
+        self.set_accumulator(self.register_accumulator ^ value);
+    }
+
+    fn tas(&mut self, address: u16) {
+        self.stack_pointer = self.register_accumulator & self.register_x;
+        self.call_stack.unwind(self.stack_pointer);
+        self.store_and_high_byte(address, self.register_y, self.stack_pointer);
+    }
+}
+
+#[cfg(test)]
+mod interrupt_tests;
+#[cfg(test)]
+mod single_step_tests;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Cpu, CpuState, ExecutionMode};
+    use crate::asm::assemble;
+    use crate::bus::{Bus, BusAccessKind};
+    use crate::constants::RESET_PROGRAM_COUNTER_ADDRESS;
+    use crate::cpu_flags::CpuFlags;
+
+    // A CPU on a flat 64 KiB bus, reset to the start of the assembled `source`
+    pub(crate) fn cpu_with_program(source: &str) -> Cpu {
+        let assembly = assemble(source).unwrap();
+
+        let mut bus = Bus::new_flat();
+        bus.load_program(assembly.origin, &assembly.bytes);
+        bus.load_program(
+            RESET_PROGRAM_COUNTER_ADDRESS,
+            &assembly.origin.to_le_bytes(),
+        );
+
+        let mut cpu = Cpu::new(bus);
+        cpu.reset();
+        cpu
+    }
+
+    // Steps one instruction and returns its bus accesses in order
+    fn step_accesses(cpu: &mut Cpu) -> Vec<(u16, BusAccessKind)> {
+        cpu.bus_mut().start_recording();
+        let cycles = cpu.step();
+        let accesses: Vec<_> = cpu
+            .bus_mut()
+            .stop_recording()
+            .iter()
+            .map(|access| (access.address, access.kind))
+            .collect();
+
+        assert_eq!(cycles as usize, accesses.len());
+        accesses
+    }
+
+    #[test]
+    fn makes_dummy_accesses_in_hardware_order() {
+        use BusAccessKind::{Read, Write};
+
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+                ldx #1
+                bne @same
+            @same:
+                beq @same
+                jmp cross
+            .org $02F0
+            cross:
+                bne far
+            .org $0310
+            far:
+                inc $12FF,x
+                lda $12FF,x
+                lda $1200,x
+            ",
+        );
+        cpu.set_execution_mode(ExecutionMode::CycleStepped);
+        cpu.bus_mut().load_program(0x1300, &[0x41]);
+        step_accesses(&mut cpu);
+
+        // Taken on the same page, the extra cycle reads the next opcode
+        assert_eq!(
+            step_accesses(&mut cpu),
+            [(0x0202, Read), (0x0203, Read), (0x0204, Read)]
+        );
+        // Not taken
+        assert_eq!(step_accesses(&mut cpu), [(0x0204, Read), (0x0205, Read)]);
+        step_accesses(&mut cpu);
+        // Taken into the next page, the fix-up cycle reads from the old page
+        assert_eq!(
+            step_accesses(&mut cpu),
+            [
+                (0x02F0, Read),
+                (0x02F1, Read),
+                (0x02F2, Read),
+                (0x0210, Read)
+            ]
+        );
+
+        // Read-modify-write reads before the high byte is fixed up, then
+        // writes the old value back before the new one
+        cpu.bus_mut().start_recording();
+        cpu.step();
+        let accesses = cpu.bus_mut().stop_recording();
+        let accesses: Vec<_> = accesses
+            .iter()
+            .map(|access| (access.address, access.value, access.kind))
+            .collect();
+        assert_eq!(
+            accesses,
+            [
+                (0x0310, 0xFE, Read),
+                (0x0311, 0xFF, Read),
+                (0x0312, 0x12, Read),
+                (0x1200, 0x00, Read),
+                (0x1300, 0x41, Read),
+                (0x1300, 0x41, Write),
+                (0x1300, 0x42, Write),
+            ]
+        );
+
+        // Loads only read the unfixed address when the page is crossed
+        assert_eq!(
+            step_accesses(&mut cpu),
+            [
+                (0x0313, Read),
+                (0x0314, Read),
+                (0x0315, Read),
+                (0x1200, Read),
+                (0x1300, Read)
+            ]
+        );
+        assert_eq!(
+            step_accesses(&mut cpu),
+            [
+                (0x0316, Read),
+                (0x0317, Read),
+                (0x0318, Read),
+                (0x1201, Read)
+            ]
+        );
+    }
+
+    #[test]
+    fn long_runs_count_cycles_per_instruction() {
+        let mut cpu = cpu_with_program(&format!(".org $0200\n{}", "nop\n".repeat(1000)));
+
+        for mode in [ExecutionMode::Instruction, ExecutionMode::CycleStepped] {
+            cpu.set_execution_mode(mode);
+            for _ in 0..500 {
+                assert_eq!(cpu.step(), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn set_state_round_trips_and_drops_pending_interrupts() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+                nop
+                nop
+                nop
+            ",
+        );
+        cpu.bus_mut().set_nmi_line(true);
+        cpu.step();
+        assert!(cpu.interrupt_pending && cpu.nmi_pending);
+
+        let state = CpuState {
+            program_counter: 0x0202,
+            stack_pointer: 0x80,
+            register_accumulator: 0x12,
+            register_x: 0x34,
+            register_y: 0x56,
+            register_status: CpuFlags::CARRY | CpuFlags::INTERRUPT_DISABLE | CpuFlags::UNUSED,
+        };
+        cpu.set_state(state);
+        assert_eq!(cpu.state(), state);
+        assert!(!cpu.interrupt_pending && !cpu.nmi_pending);
+        assert_eq!(cpu.bus_cycles, 0);
+
+        // The NOP runs instead of the interrupt
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.state().program_counter, 0x0203);
+        assert_eq!(cpu.state().stack_pointer, 0x80);
+    }
+}
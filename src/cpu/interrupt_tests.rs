@@ -0,0 +1,281 @@
+use super::{Cpu, CpuState, ExecutionMode};
+use crate::asm::{assemble, Assembly};
+use crate::cpu_flags::CpuFlags;
+use crate::memory::{FlatMemory, Memory};
+
+// Edge cases from blargg's cpu_interrupts_v2 and the nesdev wiki, see:
+// https://www.nesdev.org/wiki/CPU_interrupts
+
+// Asserts the interrupt lines once a number of bus accesses, one per cycle in
+// cycle stepped mode, have completed. Writing to $5000 acknowledges the IRQ.
+struct ScriptedMemory {
+    memory: FlatMemory,
+    accesses: u64,
+    nmi_after: Option<u64>,
+    irq_after: Option<u64>,
+}
+
+impl Memory for ScriptedMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        self.accesses += 1;
+        self.memory.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.accesses += 1;
+        if address == 0x5000 {
+            self.irq_after = None;
+        }
+        self.memory.write(address, value);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory.peek(address)
+    }
+
+    fn nmi_line(&self) -> bool {
+        self.nmi_after.is_some_and(|after| self.accesses >= after)
+    }
+
+    fn irq_line(&self) -> bool {
+        self.irq_after.is_some_and(|after| self.accesses >= after)
+    }
+}
+
+const HANDLERS: &str = "
+    .org $0300
+    nmi:
+        nop
+        rti
+    irq:
+        sta $5000
+        rti
+    ";
+
+fn machine(
+    source: &str,
+    mode: ExecutionMode,
+    interrupt_disable: bool,
+    nmi_after: Option<u64>,
+    irq_after: Option<u64>,
+) -> (Cpu<ScriptedMemory>, Assembly) {
+    let assembly = assemble(source).unwrap();
+    let handlers = assemble(HANDLERS).unwrap();
+
+    let mut memory = FlatMemory::new();
+    memory.load(assembly.origin, &assembly.bytes);
+    memory.load(handlers.origin, &handlers.bytes);
+    memory.load(0xFFFA, &handlers.labels["nmi"].to_le_bytes());
+    memory.load(0xFFFE, &handlers.labels["irq"].to_le_bytes());
+
+    let mut cpu = Cpu::new(ScriptedMemory {
+        memory,
+        accesses: 0,
+        nmi_after,
+        irq_after,
+    });
+    cpu.set_execution_mode(mode);
+
+    let mut status = CpuFlags::from_bits_truncate(0b0010_0000);
+    status.set(CpuFlags::INTERRUPT_DISABLE, interrupt_disable);
+    cpu.set_state(CpuState {
+        program_counter: assembly.origin,
+        register_status: status,
+        ..cpu.state()
+    });
+
+    (cpu, assembly)
+}
+
+const NMI: u16 = 0x0300;
+const IRQ: u16 = 0x0302;
+
+const MODES: [ExecutionMode; 2] = [ExecutionMode::Instruction, ExecutionMode::CycleStepped];
+
+fn pushed_status(cpu: &Cpu<ScriptedMemory>) -> CpuFlags {
+    let address = 0x0100 + cpu.state().stack_pointer as u16 + 1;
+    CpuFlags::from_bits_truncate(cpu.bus().peek(address))
+}
+
+#[test]
+fn cli_and_plp_take_effect_one_instruction_late() {
+    for mode in MODES {
+        for clear in ["cli", "plp"] {
+            let source = format!(
+                "
+                .org $0200
+                    lda #0
+                    pha
+                    {}
+                first:
+                    nop
+                second:
+                    nop
+                ",
+                clear
+            );
+            let (mut cpu, assembly) = machine(&source, mode, true, None, Some(0));
+
+            cpu.step();
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.state().program_counter, assembly.labels["first"]);
+
+            // The IRQ is only seen after the next instruction
+            cpu.step();
+            assert_eq!(cpu.state().program_counter, assembly.labels["second"]);
+            assert_eq!(cpu.step(), 7);
+            assert_eq!(cpu.state().program_counter, IRQ, "{:?} {}", mode, clear);
+        }
+    }
+}
+
+#[test]
+fn sei_still_takes_a_pending_irq() {
+    for mode in MODES {
+        let (mut cpu, assembly) = machine(
+            "
+            .org $0200
+                sei
+            next:
+                nop
+            ",
+            mode,
+            false,
+            None,
+            Some(0),
+        );
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.state().program_counter, IRQ, "{:?}", mode);
+
+        // The handler returns with interrupts disabled
+        let pushed = pushed_status(&cpu);
+        assert!(pushed.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert!(!pushed.contains(CpuFlags::BREAK));
+        assert!(pushed.contains(CpuFlags::UNUSED));
+
+        // The handler acknowledges the IRQ and returns to the next instruction
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.state().program_counter, assembly.labels["next"]);
+    }
+}
+
+#[test]
+fn b_flag_is_only_set_by_brk_and_php() {
+    for mode in MODES {
+        let (mut cpu, _) = machine(
+            "
+            .org $0200
+                php
+                brk
+                .byte 0
+            ",
+            mode,
+            false,
+            None,
+            None,
+        );
+
+        cpu.step();
+        assert!(pushed_status(&cpu).contains(CpuFlags::BREAK));
+        cpu.step();
+        assert_eq!(cpu.state().program_counter, IRQ);
+        assert!(pushed_status(&cpu).contains(CpuFlags::BREAK));
+
+        let (mut cpu, _) = machine(".org $0200\n nop", mode, false, Some(0), None);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.state().program_counter, NMI);
+        assert!(!pushed_status(&cpu).contains(CpuFlags::BREAK));
+    }
+}
+
+#[test]
+fn nmi_hijacks_brk_and_irq() {
+    // BRK reads its opcode and padding byte, pushes PC and the status, then
+    // reads the vector. An NMI seen before the status push takes it over.
+    let brk = "
+        .org $0200
+            brk
+            .byte 0
+        ";
+
+    let (mut cpu, _) = machine(brk, ExecutionMode::CycleStepped, false, Some(4), None);
+    cpu.step();
+    assert_eq!(cpu.state().program_counter, NMI);
+    assert!(pushed_status(&cpu).contains(CpuFlags::BREAK));
+    assert_eq!(cpu.state().stack_pointer, 0xFA);
+
+    // Any later and the BRK runs normally, with the NMI after the first
+    // instruction of the handler
+    let (mut cpu, _) = machine(brk, ExecutionMode::CycleStepped, false, Some(5), None);
+    cpu.step();
+    assert_eq!(cpu.state().program_counter, IRQ);
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.state().program_counter, NMI);
+
+    // The IRQ sequence after NOP starts on the third access
+    let (mut cpu, _) = machine(
+        ".org $0200\n nop",
+        ExecutionMode::CycleStepped,
+        false,
+        Some(6),
+        Some(0),
+    );
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.state().program_counter, NMI);
+    assert!(!pushed_status(&cpu).contains(CpuFlags::BREAK));
+}
+
+#[test]
+fn taken_branches_delay_interrupts() {
+    // The IRQ arrives in time for the last cycle of a three cycle
+    // instruction. LDA polls then and takes it, a taken branch that stays on
+    // the page doesn't and runs one more instruction first.
+    let source = |instruction: &str| {
+        format!(
+            "
+            .org $0200
+                lda #0
+                {}
+            next:
+                nop
+            after:
+                nop
+            ",
+            instruction
+        )
+    };
+
+    let (mut cpu, _) = machine(
+        &source("lda $10"),
+        ExecutionMode::CycleStepped,
+        false,
+        None,
+        Some(4),
+    );
+    cpu.step();
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.state().program_counter, IRQ);
+
+    let (mut cpu, assembly) = machine(
+        &source("beq next"),
+        ExecutionMode::CycleStepped,
+        false,
+        None,
+        Some(4),
+    );
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.state().program_counter, assembly.labels["next"]);
+    cpu.step();
+    assert_eq!(cpu.state().program_counter, assembly.labels["after"]);
+    cpu.step();
+    assert_eq!(cpu.state().program_counter, IRQ);
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use super::{Cpu, CpuState, ExecutionMode};
+use crate::bus::{Bus, BusAccess, BusAccessKind};
+use crate::constants::OPERATION_INFORMATION;
+use crate::cpu_flags::CpuFlags;
+
+// Harness for Tom Harte's single step tests, see:
+// https://github.com/SingleStepTests/65x02 (the nes6502 set)
+//
+// Each <opcode>.json file holds thousands of tests with the CPU and RAM state
+// before and after one instruction, plus every bus access it made. The files
+// are read from tests/single_step, or from the SINGLE_STEP_TESTS directory.
+
+const DEFAULT_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/single_step");
+
+// B and the unused bit are not stored in the status register, they only show
+// up in pushed copies, which are checked through memory
+const IGNORED_FLAGS: CpuFlags = CpuFlags::BREAK.union(CpuFlags::UNUSED);
+
+#[derive(Default)]
+struct OpcodeSummary {
+    passed: u32,
+    failed: u32,
+    first_failure: Option<String>,
+}
+
+fn test_directory() -> PathBuf {
+    match env::var("SINGLE_STEP_TESTS") {
+        Ok(directory) => PathBuf::from(directory),
+        Err(_) => PathBuf::from(DEFAULT_DIRECTORY),
+    }
+}
+
+fn number(value: &Value) -> Result<u64, String> {
+    value
+        .as_u64()
+        .ok_or_else(|| format!("expected a number, found {}", value))
+}
+
+fn field(state: &Value, name: &str) -> Result<u64, String> {
+    number(&state[name]).map_err(|error| format!("{}: {}", name, error))
+}
+
+fn ram_entries(state: &Value) -> Result<Vec<(u16, u8)>, String> {
+    let entries = state["ram"].as_array().ok_or("ram is not an array")?;
+
+    entries
+        .iter()
+        .map(|entry| Ok((number(&entry[0])? as u16, number(&entry[1])? as u8)))
+        .collect()
+}
+
+fn load_state(cpu: &mut Cpu, state: &Value) -> Result<(), String> {
+    cpu.set_state(CpuState {
+        program_counter: field(state, "pc")? as u16,
+        stack_pointer: field(state, "s")? as u8,
+        register_accumulator: field(state, "a")? as u8,
+        register_x: field(state, "x")? as u8,
+        register_y: field(state, "y")? as u8,
+        register_status: CpuFlags::from_bits_truncate(field(state, "p")? as u8),
+    });
+
+    for (address, value) in ram_entries(state)? {
+        cpu.bus.mem_write(address, value);
+    }
+
+    Ok(())
+}
+
+fn compare_state(cpu: &mut Cpu, state: &Value) -> Result<(), String> {
+    let actual = cpu.state();
+
+    let registers = [
+        ("pc", actual.program_counter as u64, field(state, "pc")?),
+        ("s", actual.stack_pointer as u64, field(state, "s")?),
+        ("a", actual.register_accumulator as u64, field(state, "a")?),
+        ("x", actual.register_x as u64, field(state, "x")?),
+        ("y", actual.register_y as u64, field(state, "y")?),
+    ];
+
+    for (name, actual, expected) in registers {
+        if actual != expected {
+            return Err(format!(
+                "{} is {:#X}, expected {:#X}",
+                name, actual, expected
+            ));
+        }
+    }
+
+    let expected_flags = CpuFlags::from_bits_truncate(field(state, "p")? as u8);
+    if cpu.register_status.difference(IGNORED_FLAGS) != expected_flags.difference(IGNORED_FLAGS) {
+        return Err(format!(
+            "p is {:#010b}, expected {:#010b}",
+            cpu.register_status.bits(),
+            expected_flags.bits()
+        ));
+    }
+
+    for (address, expected) in ram_entries(state)? {
+        let actual = cpu.bus.mem_read(address);
+        if actual != expected {
+            return Err(format!(
+                "${:04X} is {:#04X}, expected {:#04X}",
+                address, actual, expected
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn compare_cycles(accesses: &[BusAccess], cycles: &Value) -> Result<(), String> {
+    let cycles = cycles.as_array().ok_or("cycles is not an array")?;
+
+    let expected: Vec<BusAccess> = cycles
+        .iter()
+        .map(|cycle| {
+            let kind = match cycle[2].as_str() {
+                Some("read") => BusAccessKind::Read,
+                Some("write") => BusAccessKind::Write,
+                _ => return Err(format!("unknown cycle kind {}", cycle[2])),
+            };
+
+            Ok(BusAccess {
+                address: number(&cycle[0])? as u16,
+                value: number(&cycle[1])? as u8,
+                kind,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    if accesses != expected.as_slice() {
+        return Err(format!(
+            "bus accesses were {:?}, expected {:?}",
+            accesses, expected
+        ));
+    }
+
+    Ok(())
+}
+
+fn run_test(test: &Value) -> Result<(), String> {
+    let mut cpu = Cpu::new(Bus::new_flat());
+    cpu.set_execution_mode(ExecutionMode::CycleStepped);
+
+    load_state(&mut cpu, &test["initial"])?;
+
+    cpu.bus.start_recording();
+    cpu.step();
+    let accesses = cpu.bus.stop_recording();
+
+    compare_state(&mut cpu, &test["final"])?;
+    compare_cycles(&accesses, &test["cycles"])
+}
+
+// The test data is not checked in, see tests/roms/README.md
+#[test]
+#[ignore = "needs the SingleStepTests JSON files"]
+fn single_step_tests() {
+    let directory = test_directory();
+
+    assert!(
+        directory.is_dir(),
+        "{} was not found, see tests/roms/README.md",
+        directory.display()
+    );
+
+    let mut summaries: BTreeMap<u8, OpcodeSummary> = BTreeMap::new();
+
+    for opcode in 0..=255u8 {
+        let name = OPERATION_INFORMATION[opcode as usize]
+            .map(|operation| operation.name)
+            .unwrap_or("???");
+
+        // JAM locks the CPU up, there is no end state to compare with
+        if name == "JAM" {
+            continue;
+        }
+
+        let path = directory.join(format!("{:02x}.json", opcode));
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+
+        let tests: Vec<Value> = match serde_json::from_str(&contents) {
+            Ok(tests) => tests,
+            Err(error) => panic!("Could not parse {}: {}", path.display(), error),
+        };
+
+        let summary = summaries.entry(opcode).or_default();
+
+        for test in &tests {
+            match run_test(test) {
+                Ok(()) => summary.passed += 1,
+                Err(error) => {
+                    summary.failed += 1;
+                    if summary.first_failure.is_none() {
+                        summary.first_failure = Some(format!("{}: {}", test["name"], error));
+                    }
+                }
+            }
+        }
+    }
+
+    assert!(
+        !summaries.is_empty(),
+        "No test files were found in {}",
+        directory.display()
+    );
+
+    let mut failed_opcodes = 0;
+
+    for (opcode, summary) in &summaries {
+        let name = OPERATION_INFORMATION[*opcode as usize]
+            .map(|operation| operation.name)
+            .unwrap_or("???");
+        let total = summary.passed + summary.failed;
+
+        match &summary.first_failure {
+            None => println!(
+                "{:02X} {} passed {}/{}",
+                opcode, name, summary.passed, total
+            ),
+            Some(failure) => {
+                failed_opcodes += 1;
+                println!(
+                    "{:02X} {} FAILED {}/{}, first failure {}",
+                    opcode, name, summary.failed, total, failure
+                );
+            }
+        }
+    }
+
+    assert_eq!(
+        failed_opcodes,
+        0,
+        "{} of {} opcodes failed",
+        failed_opcodes,
+        summaries.len()
+    );
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::constants::BitMasks;
 use bitflags::bitflags;
 
@@ -16,7 +18,7 @@ bitflags! {
             |+-------- Overflow
             +--------- Negative
      */
-    #[derive(PartialEq, Clone, Copy)]
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
     pub struct CpuFlags: u8 {
         const CARRY             = BitMasks::ZERO;
         const ZERO              = BitMasks::FIRST;
@@ -28,3 +30,46 @@ bitflags! {
         const NEGATIVE          = BitMasks::SEVENTH;
     }
 }
+
+// Written as NV-BDIZC, upper case when a flag is set and lower case when it
+// is clear. The unused bit is always shown as a dash.
+impl fmt::Display for CpuFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [
+            (CpuFlags::NEGATIVE, 'N'),
+            (CpuFlags::OVERFLOW, 'V'),
+            (CpuFlags::UNUSED, '-'),
+            (CpuFlags::BREAK, 'B'),
+            (CpuFlags::DECIMAL_MODE, 'D'),
+            (CpuFlags::INTERRUPT_DISABLE, 'I'),
+            (CpuFlags::ZERO, 'Z'),
+            (CpuFlags::CARRY, 'C'),
+        ];
+
+        for (flag, letter) in flags {
+            let letter = if self.contains(flag) {
+                letter
+            } else {
+                letter.to_ascii_lowercase()
+            };
+            write!(f, "{}", letter)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CpuFlags;
+
+    #[test]
+    fn display_shows_set_flags_in_upper_case() {
+        assert_eq!(CpuFlags::empty().to_string(), "nv-bdizc");
+        assert_eq!(CpuFlags::all().to_string(), "NV-BDIZC");
+        assert_eq!(
+            CpuFlags::from_bits_truncate(0b1010_0101).to_string(),
+            "Nv-bdIzC"
+        );
+    }
+}
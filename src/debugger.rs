@@ -0,0 +1,720 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::breakpoints::{BreakpointKind, BreakpointManager, StopReason};
+use crate::cpu::Cpu;
+use crate::cpu_flags::CpuFlags;
+use crate::disasm::disassemble_instruction_with_labels;
+use crate::symbols::SymbolTable;
+
+const JSR_OPCODE: u8 = 0x20;
+const RTS_OPCODE: u8 = 0x60;
+
+// How many instructions continue, next and finish run between checks for an
+// interrupt
+const INTERRUPT_POLL_INSTRUCTIONS: u64 = 10_000;
+
+const HELP: &str = "\
+step [count]             run one instruction, or count of them (s)
+next                     like step, but runs a JSR until it returns (n)
+finish                   run until the current subroutine returns
+continue                 run until a breakpoint or watchpoint (c)
+regs                     show the registers (r)
+mem <address> [length]   dump memory (m)
+disasm [address] [count] disassemble, from PC by default (d)
+set <register>=<value>   set A, X, Y, S, PC, P or a flag N V B D I Z C
+set <address>=<value>    write a byte to RAM
+break                    list breakpoints and watchpoints
+break <address> [if <condition>]
+watch <start>[-<end>] [read|write|access] [if <condition>]
+delete|enable|disable <id>
+traps on|off             stop when an instruction jumps to itself
+bt                       show the call stack
+quit                     leave the debugger (q)
+Ctrl-C stops continue, next and finish";
+
+// Interactive debugger over a `Cpu`. Memory is inspected with `Bus::peek` and
+// changed with `Bus::poke`, so looking around never disturbs the machine.
+pub struct Debugger {
+    cpu: Cpu,
+    breakpoints: BreakpointManager,
+    symbols: SymbolTable,
+    interrupted: Arc<AtomicBool>,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Self {
+        Debugger {
+            cpu,
+            breakpoints: BreakpointManager::new(),
+            symbols: SymbolTable::new(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Setting the flag, from a Ctrl-C handler say, stops the running command
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupted)
+    }
+
+    // Labels are used in disassembly and accepted anywhere an address is
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    // Reads commands until `quit` or the end of the input. An empty line
+    // repeats the previous command.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, output: &mut W) -> io::Result<()> {
+        let mut lines = input.lines();
+        let mut previous = String::new();
+
+        writeln!(output, "{}", self.current_instruction())?;
+
+        loop {
+            write!(output, "(nes) ")?;
+            output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            let line = if line.trim().is_empty() {
+                previous.clone()
+            } else {
+                line.trim().to_string()
+            };
+
+            if matches!(line.as_str(), "quit" | "q" | "exit") {
+                return Ok(());
+            }
+
+            match self.execute(&line) {
+                Ok(text) if text.is_empty() => {}
+                Ok(text) => writeln!(output, "{}", text)?,
+                Err(error) => writeln!(output, "error: {}", error)?,
+            }
+
+            previous = line;
+        }
+    }
+
+    // Runs one command and returns what it printed
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let (command, arguments) = match line.trim().split_once(char::is_whitespace) {
+            Some((command, arguments)) => (command, arguments.trim()),
+            None => (line.trim(), ""),
+        };
+
+        match command {
+            "" => Ok(String::new()),
+            "step" | "s" => self.step(arguments),
+            "next" | "n" => self.next(),
+            "finish" => self.finish(),
+            "continue" | "c" => Ok(self
+                .run_until_stopped()
+                .map_or_else(|| self.interrupted(), |reason| self.stopped(reason))),
+            "regs" | "r" => Ok(self.cpu.state().to_string()),
+            "mem" | "m" => self.memory(arguments),
+            "disasm" | "d" => self.disassemble(arguments),
+            "set" => self.set(arguments),
+            "break" | "b" => self.add_breakpoint(arguments),
+            "watch" | "w" => self.add_watchpoint(arguments),
+            "delete" => {
+                let id = parse_number(arguments)? as usize;
+                if !self.breakpoints.remove(id) {
+                    return Err(format!("no breakpoint {}", id));
+                }
+                Ok(String::new())
+            }
+            "enable" | "disable" => {
+                let id = parse_number(arguments)? as usize;
+                if !self.breakpoints.set_enabled(id, command == "enable") {
+                    return Err(format!("no breakpoint {}", id));
+                }
+                Ok(String::new())
+            }
+            "traps" => match arguments {
+                "on" | "off" => {
+                    self.breakpoints.set_stop_on_traps(arguments == "on");
+                    Ok(String::new())
+                }
+                _ => Err("traps takes on or off".to_string()),
+            },
+            "bt" => Ok(self.backtrace()),
+            "help" | "h" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command '{}', try help", command)),
+        }
+    }
+
+    fn step(&mut self, arguments: &str) -> Result<String, String> {
+        let count = if arguments.is_empty() {
+            1
+        } else {
+            parse_number(arguments)?
+        };
+
+        for _ in 0..count {
+            self.cpu.step();
+
+            // Stepping onto a breakpoint reports it, and continuing from
+            // there runs past it
+            let opcode = self.cpu.bus().peek(self.cpu.state().program_counter);
+            if let Some(reason) = self.breakpoints.check_execute(&self.cpu.state(), opcode) {
+                return Ok(self.stopped(reason));
+            }
+        }
+
+        Ok(self.current_instruction())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let state = self.cpu.state();
+
+        if self.cpu.bus().peek(state.program_counter) != JSR_OPCODE {
+            return self.step("");
+        }
+
+        // The subroutine has returned once the stack is back where it was
+        let return_address = state.program_counter.wrapping_add(3);
+        let stopped = self.run_to(BreakpointKind::Execute(return_address), state.stack_pointer)?;
+        Ok(stopped.unwrap_or_else(|| self.current_instruction()))
+    }
+
+    fn finish(&mut self) -> Result<String, String> {
+        let stack_pointer = self.cpu.state().stack_pointer;
+        match self.run_to(BreakpointKind::Opcode(RTS_OPCODE), stack_pointer)? {
+            Some(stopped) => Ok(stopped),
+            None => self.step(""),
+        }
+    }
+
+    // Runs with a temporary breakpoint that only fires at the current stack
+    // depth or above, so recursive calls do not stop it early. Returns what
+    // to print if something else stopped it first.
+    fn run_to(
+        &mut self,
+        kind: BreakpointKind,
+        stack_pointer: u8,
+    ) -> Result<Option<String>, String> {
+        let condition = format!("S >= ${:02X}", stack_pointer);
+        let id = self.breakpoints.add_conditional(kind, &condition)?;
+
+        let reason = self.run_until_stopped();
+        self.breakpoints.remove(id);
+
+        match reason {
+            Some(StopReason::Breakpoint { id: stopped, .. }) if stopped == id => Ok(None),
+            Some(reason) => Ok(Some(self.stopped(reason))),
+            None => Ok(Some(self.interrupted())),
+        }
+    }
+
+    // Runs in slices until something stops it, or returns `None` once the
+    // interrupt flag is set. An interrupt from before the run is dropped.
+    fn run_until_stopped(&mut self) -> Option<StopReason> {
+        self.interrupted.store(false, Ordering::Relaxed);
+
+        loop {
+            let stopped = self
+                .cpu
+                .run_with_breakpoints_for(&mut self.breakpoints, INTERRUPT_POLL_INSTRUCTIONS);
+            if stopped.is_some() {
+                return stopped;
+            }
+            if self.interrupted.swap(false, Ordering::Relaxed) {
+                return None;
+            }
+        }
+    }
+
+    fn interrupted(&self) -> String {
+        format!("Interrupted\n{}", self.current_instruction())
+    }
+
+    fn stopped(&self, reason: StopReason) -> String {
+        let message = match reason {
+            StopReason::Breakpoint {
+                id,
+                program_counter,
+            } => format!("Breakpoint {} at ${:04X}", id, program_counter),
+            StopReason::Watchpoint { id, access } => format!("Watchpoint {}: {}", id, access),
+            StopReason::Trap(address) => format!("Trapped at ${:04X}", address),
+            StopReason::BadAccess {
+                program_counter,
+                access,
+            } => format!("Bad {} by ${:04X}", access, program_counter),
+        };
+
+        format!("{}\n{}", message, self.current_instruction())
+    }
+
+    fn current_instruction(&self) -> String {
+        self.format_instruction(self.cpu.state().program_counter).0
+    }
+
+    // Returns the line for the instruction and the address after it
+    fn format_instruction(&self, address: u16) -> (String, u16) {
+        let bus = self.cpu.bus();
+        let instruction = disassemble_instruction_with_labels(
+            address,
+            |address| bus.peek(address),
+            |address| self.symbols.label(bus, address).map(str::to_string),
+        );
+
+        let bytes: Vec<String> = instruction
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let marker = if address == self.cpu.state().program_counter {
+            '>'
+        } else {
+            ' '
+        };
+
+        let mut line = format!(
+            "{} ${:04X}  {:<8}  {}",
+            marker,
+            address,
+            bytes.join(" "),
+            instruction.text
+        );
+
+        if let Some(label) = self.symbols.label(bus, address) {
+            line = format!("{}:\n{}", label, line);
+        }
+
+        (line, address.wrapping_add(instruction.bytes.len() as u16))
+    }
+
+    fn memory(&self, arguments: &str) -> Result<String, String> {
+        let mut arguments = arguments.split_whitespace();
+        let start = self.parse_address(arguments.next().ok_or("mem needs an address")?)?;
+        let length = match arguments.next() {
+            Some(length) => parse_number(length)?,
+            None => 16,
+        };
+
+        let bus = self.cpu.bus();
+        let mut lines = Vec::new();
+
+        for row in (0..length).step_by(16) {
+            let address = start.wrapping_add(row as u16);
+            let bytes: Vec<String> = (row..length.min(row + 16))
+                .map(|offset| format!("{:02X}", bus.peek(start.wrapping_add(offset as u16))))
+                .collect();
+            lines.push(format!("${:04X}  {}", address, bytes.join(" ")));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn disassemble(&self, arguments: &str) -> Result<String, String> {
+        let mut arguments = arguments.split_whitespace();
+        let mut address = match arguments.next() {
+            Some(address) => self.parse_address(address)?,
+            None => self.cpu.state().program_counter,
+        };
+        let count = match arguments.next() {
+            Some(count) => parse_number(count)?,
+            None => 10,
+        };
+
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let (line, next) = self.format_instruction(address);
+            lines.push(line);
+            address = next;
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn set(&mut self, arguments: &str) -> Result<String, String> {
+        let (target, value) = arguments
+            .split_once('=')
+            .ok_or("usage: set <register>=<value>")?;
+        let target = target.trim();
+        let value = parse_number(value.trim())?;
+
+        let mut state = self.cpu.state();
+
+        let flag = match target.to_ascii_uppercase().as_str() {
+            "A" => {
+                state.register_accumulator = value as u8;
+                None
+            }
+            "X" => {
+                state.register_x = value as u8;
+                None
+            }
+            "Y" => {
+                state.register_y = value as u8;
+                None
+            }
+            "S" | "SP" => {
+                state.stack_pointer = value as u8;
+                None
+            }
+            "PC" => {
+                state.program_counter = value as u16;
+                None
+            }
+            "P" => {
+                state.register_status = CpuFlags::from_bits_truncate(value as u8);
+                None
+            }
+            "N" => Some(CpuFlags::NEGATIVE),
+            "V" => Some(CpuFlags::OVERFLOW),
+            "B" => Some(CpuFlags::BREAK),
+            "D" => Some(CpuFlags::DECIMAL_MODE),
+            "I" => Some(CpuFlags::INTERRUPT_DISABLE),
+            "Z" => Some(CpuFlags::ZERO),
+            "C" => Some(CpuFlags::CARRY),
+            _ => {
+                let address = self
+                    .parse_address(target)
+                    .map_err(|_| format!("unknown register '{}'", target))?;
+                self.cpu.bus_mut().poke(address, value as u8)?;
+                return Ok(String::new());
+            }
+        };
+
+        if let Some(flag) = flag {
+            state.register_status.set(flag, value != 0);
+        }
+
+        self.cpu.set_state(state);
+        Ok(self.cpu.state().to_string())
+    }
+
+    fn add_breakpoint(&mut self, arguments: &str) -> Result<String, String> {
+        if arguments.is_empty() {
+            return Ok(self.list_breakpoints());
+        }
+
+        let (address, condition) = split_condition(arguments);
+        let kind = BreakpointKind::Execute(self.parse_address(address)?);
+
+        let id = match condition {
+            Some(condition) => self.breakpoints.add_conditional(kind, condition)?,
+            None => self.breakpoints.add(kind),
+        };
+
+        Ok(format!("Breakpoint {} at {}", id, address))
+    }
+
+    fn add_watchpoint(&mut self, arguments: &str) -> Result<String, String> {
+        let (arguments, condition) = split_condition(arguments);
+        let mut arguments = arguments.split_whitespace();
+
+        let range = arguments.next().ok_or("watch needs an address")?;
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (self.parse_address(start)?, self.parse_address(end)?),
+            None => (self.parse_address(range)?, self.parse_address(range)?),
+        };
+
+        let kinds = match arguments.next().unwrap_or("write") {
+            "read" => vec![BreakpointKind::Read { start, end }],
+            "write" => vec![BreakpointKind::Write { start, end }],
+            "access" => vec![
+                BreakpointKind::Read { start, end },
+                BreakpointKind::Write { start, end },
+            ],
+            kind => return Err(format!("unknown watch kind '{}'", kind)),
+        };
+
+        let mut lines = Vec::new();
+        for kind in kinds {
+            let id = match condition {
+                Some(condition) => self.breakpoints.add_conditional(kind, condition)?,
+                None => self.breakpoints.add(kind),
+            };
+            lines.push(format!("Watchpoint {} on {}", id, range));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn list_breakpoints(&self) -> String {
+        let lines: Vec<String> = self
+            .breakpoints
+            .iter()
+            .map(|breakpoint| {
+                let kind = match breakpoint.kind {
+                    BreakpointKind::Execute(address) => format!("break ${:04X}", address),
+                    BreakpointKind::Read { start, end } => {
+                        format!("watch ${:04X}-${:04X} read", start, end)
+                    }
+                    BreakpointKind::Write { start, end } => {
+                        format!("watch ${:04X}-${:04X} write", start, end)
+                    }
+                    BreakpointKind::Opcode(opcode) => format!("opcode ${:02X}", opcode),
+                };
+                let condition = breakpoint
+                    .condition()
+                    .map(|condition| format!(" if {}", condition))
+                    .unwrap_or_default();
+                let enabled = if breakpoint.enabled {
+                    ""
+                } else {
+                    " (disabled)"
+                };
+
+                format!(
+                    "{:>3}  {}{}, hit {} times{}",
+                    breakpoint.id, kind, condition, breakpoint.hit_count, enabled
+                )
+            })
+            .collect();
+
+        if lines.is_empty() {
+            "No breakpoints".to_string()
+        } else {
+            lines.join("\n")
+        }
+    }
+
+    // Addresses are numbers or labels from the symbol table
+    fn parse_address(&self, text: &str) -> Result<u16, String> {
+        if let Some(address) = self.symbols.address(self.cpu.bus(), text) {
+            return Ok(address);
+        }
+
+        let value = parse_number(text)?;
+        u16::try_from(value).map_err(|_| format!("address '{}' is out of range", text))
+    }
+
+    // The shadow call stack, innermost first
+    fn backtrace(&self) -> String {
+        let bus = self.cpu.bus();
+        let program_counter = self.cpu.state().program_counter;
+
+        self.cpu
+            .call_stack()
+            .format_backtrace(program_counter, |address| {
+                self.symbols.label(bus, address).map(str::to_string)
+            })
+    }
+}
+
+fn split_condition(arguments: &str) -> (&str, Option<&str>) {
+    match arguments.split_once(" if ") {
+        Some((arguments, condition)) => (arguments.trim(), Some(condition.trim())),
+        None => (arguments.trim(), None),
+    }
+}
+
+// Numbers are decimal unless they start with `$` or `0x` for hex, or `%`
+// for binary
+pub fn parse_number(text: &str) -> Result<u64, String> {
+    let (digits, radix) = if let Some(digits) = text.strip_prefix('$') {
+        (digits, 16)
+    } else if let Some(digits) = text.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = text.strip_prefix('%') {
+        (digits, 2)
+    } else {
+        (text, 10)
+    };
+
+    u64::from_str_radix(digits, radix).map_err(|_| format!("malformed number '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::Debugger;
+    use crate::cpu::tests::cpu_with_program;
+    use crate::symbols::{SymbolLocation, SymbolTable};
+
+    fn debugger(source: &str) -> Debugger {
+        Debugger::new(cpu_with_program(source))
+    }
+
+    const PROGRAM: &str = "
+        .org $0200
+            ldx #0
+        loop:
+            jsr increment
+            cpx #3
+            bne loop
+        done:
+            jmp done
+        increment:
+            jsr nested
+            rts
+        nested:
+            inx
+            stx $0300
+            rts
+    ";
+
+    #[test]
+    fn next_and_finish_follow_subroutines() {
+        let mut debugger = debugger(PROGRAM);
+
+        assert_eq!(
+            debugger.execute("step").unwrap(),
+            "> $0202  20 0C 02  JSR $020C"
+        );
+        assert_eq!(
+            debugger.execute("next").unwrap(),
+            "> $0205  E0 03     CPX #$03"
+        );
+        assert_eq!(debugger.cpu().state().register_x, 1);
+
+        debugger.execute("step 2").unwrap();
+        debugger.execute("step").unwrap();
+        assert_eq!(debugger.cpu().state().program_counter, 0x020C);
+
+        debugger.execute("step").unwrap();
+        let backtrace = debugger.execute("bt").unwrap();
+        assert_eq!(
+            backtrace,
+            "#0  $0210 in $0210\n#1  $020F in $020C\n#2  $0205"
+        );
+
+        assert_eq!(
+            debugger.execute("finish").unwrap(),
+            "> $020F  60        RTS"
+        );
+        assert_eq!(
+            debugger.execute("finish").unwrap(),
+            "> $0205  E0 03     CPX #$03"
+        );
+        assert_eq!(debugger.cpu().state().register_x, 2);
+    }
+
+    #[test]
+    fn breakpoints_watchpoints_and_memory() {
+        let mut debugger = debugger(PROGRAM);
+
+        assert_eq!(
+            debugger.execute("break $0205 if X == 2").unwrap(),
+            "Breakpoint 1 at $0205"
+        );
+        assert_eq!(
+            debugger.execute("c").unwrap(),
+            "Breakpoint 1 at $0205\n> $0205  E0 03     CPX #$03"
+        );
+        assert_eq!(debugger.cpu().state().register_x, 2);
+
+        debugger.execute("watch $0300 write if X == 3").unwrap();
+        assert_eq!(
+            debugger.execute("continue").unwrap(),
+            "Watchpoint 2: write $03 at $0300\n> $0214  60        RTS"
+        );
+        assert_eq!(
+            debugger.execute("break").unwrap(),
+            "  1  break $0205 if X == 2, hit 1 times\n  2  watch $0300-$0300 write if X == 3, hit 1 times"
+        );
+
+        assert_eq!(debugger.execute("mem $0300 2").unwrap(), "$0300  03 00");
+        assert_eq!(
+            debugger.execute("disasm $0200 2").unwrap(),
+            "  $0200  A2 00     LDX #$00\n  $0202  20 0C 02  JSR $020C"
+        );
+
+        debugger.execute("set A=$10").unwrap();
+        debugger.execute("set c=1").unwrap();
+        debugger.execute("set $0301=%101").unwrap();
+        assert_eq!(
+            debugger.execute("regs").unwrap(),
+            "PC:0214 A:10 X:03 Y:00 SP:F9 P:nv-bdIzC"
+        );
+        assert_eq!(debugger.execute("m $0301 1").unwrap(), "$0301  05");
+        assert!(debugger.execute("set Q=1").is_err());
+        assert!(debugger.execute("frobnicate").is_err());
+    }
+
+    #[test]
+    fn symbols_name_addresses() {
+        let mut debugger = debugger(PROGRAM);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("increment", SymbolLocation::Cpu(0x020C));
+        symbols.insert("counter", SymbolLocation::Cpu(0x0300));
+        debugger.set_symbols(symbols);
+
+        assert_eq!(
+            debugger.execute("disasm $0202 1").unwrap(),
+            "  $0202  20 0C 02  JSR increment"
+        );
+        assert_eq!(
+            debugger.execute("break increment").unwrap(),
+            "Breakpoint 1 at increment"
+        );
+        assert_eq!(
+            debugger.execute("c").unwrap(),
+            "Breakpoint 1 at $020C\nincrement:\n> $020C  20 10 02  JSR $0210"
+        );
+        assert_eq!(debugger.execute("m counter 1").unwrap(), "$0300  00");
+    }
+
+    #[test]
+    fn traps_stop_only_when_turned_on() {
+        let mut debugger = debugger(
+            "
+            .org $0200
+                inx
+            done:
+                jmp done
+            ",
+        );
+
+        assert!(debugger.execute("traps maybe").is_err());
+        debugger.execute("traps on").unwrap();
+        assert_eq!(
+            debugger.execute("c").unwrap(),
+            "Trapped at $0201\n> $0201  4C 01 02  JMP $0201"
+        );
+    }
+    #[test]
+    fn interrupts_stop_running_commands() {
+        // Well over one slice of instructions before it traps
+        let mut debugger = debugger(
+            "
+            .org $0200
+            outer:
+                inx
+            inner:
+                iny
+                bne inner
+                cpx #64
+                bne outer
+            done:
+                jmp done
+            ",
+        );
+        debugger.execute("traps on").unwrap();
+
+        // An interrupt from before the command is dropped
+        let interrupt = debugger.interrupt_flag();
+        interrupt.store(true, Ordering::Relaxed);
+        assert_eq!(
+            debugger.execute("c").unwrap(),
+            "Trapped at $0208\n> $0208  4C 08 02  JMP $0208"
+        );
+
+        debugger.execute("traps off").unwrap();
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            interrupt.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(
+            debugger.execute("c").unwrap(),
+            "Interrupted\n> $0208  4C 08 02  JMP $0208"
+        );
+        interrupter.join().unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use crate::asm::find_opcode;
+use crate::constants::OPERATION_INFORMATION;
+use crate::operation::AddressingModes;
+
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+// Decodes the instruction at `address`, with `read` supplying the memory
+// contents. The text uses the syntax accepted by the assembler.
+pub fn disassemble_instruction<F: Fn(u16) -> u8>(address: u16, read: F) -> Instruction {
+    disassemble_instruction_with_labels(address, read, |_| None)
+}
+
+// Like `disassemble_instruction`, but operands that `label` has a name for
+// are written as `JSR update_player` instead of `JSR $C3A0`
+pub fn disassemble_instruction_with_labels<F, L>(address: u16, read: F, label: L) -> Instruction
+where
+    F: Fn(u16) -> u8,
+    L: Fn(u16) -> Option<String>,
+{
+    let opcode = read(address);
+
+    let operation = match OPERATION_INFORMATION[opcode as usize] {
+        Some(operation) => operation,
+        None => {
+            return Instruction {
+                address,
+                bytes: vec![opcode],
+                text: format!(".byte ${:02X}", opcode),
+            }
+        }
+    };
+
+    let bytes: Vec<u8> = (0..operation.instruction_size as u16)
+        .map(|offset| read(address.wrapping_add(offset)))
+        .collect();
+
+    let operand = format_operand(
+        operation.instruction_addressing_mode,
+        address,
+        &bytes,
+        &label,
+    );
+
+    let text = if operand.is_empty() {
+        operation.name.to_string()
+    } else {
+        format!("{} {}", operation.name, operand)
+    };
+
+    Instruction {
+        address,
+        bytes,
+        text,
+    }
+}
+
+// Produces assembler source for a block of bytes loaded at `origin`. Opcodes
+// that share a mnemonic and addressing mode with another encoding (the
+// unofficial NOP and SBC copies) are written as `.byte` so that assembling
+// the output gives back the exact same bytes.
+pub fn disassemble(bytes: &[u8], origin: u16) -> String {
+    let mut source = format!(".org ${:04X}\n", origin);
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let address = origin.wrapping_add(offset as u16);
+        let instruction = disassemble_instruction(address, |address| {
+            let index = address.wrapping_sub(origin) as usize;
+            bytes.get(index).copied().unwrap_or(0)
+        });
+
+        let size = instruction.bytes.len();
+
+        let text = if offset + size > bytes.len() {
+            format_bytes(&bytes[offset..])
+        } else if is_canonical(instruction.bytes[0]) {
+            instruction.text
+        } else {
+            format_bytes(&instruction.bytes)
+        };
+
+        source.push_str(&format!("    {}\n", text));
+        offset += size.min(bytes.len() - offset);
+    }
+
+    source
+}
+
+fn is_canonical(opcode: u8) -> bool {
+    match OPERATION_INFORMATION[opcode as usize] {
+        Some(operation) => {
+            find_opcode(operation.name, operation.instruction_addressing_mode) == Some(opcode)
+        }
+        None => false,
+    }
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|byte| format!("${:02X}", byte)).collect();
+    format!(".byte {}", values.join(", "))
+}
+
+fn format_operand<L: Fn(u16) -> Option<String>>(
+    mode: AddressingModes,
+    address: u16,
+    bytes: &[u8],
+    label: &L,
+) -> String {
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or(0)]);
+
+    let zero_page = label(byte as u16).unwrap_or_else(|| format!("${:02X}", byte));
+
+    // Zero page values in an absolute mode need the assembler's `a:` prefix
+    let word_text = label(word).unwrap_or_else(|| format!("${:04X}", word));
+    let absolute = if word <= 0xFF {
+        format!("a:{}", word_text)
+    } else {
+        word_text.clone()
+    };
+
+    match mode {
+        AddressingModes::Implicit => String::new(),
+        AddressingModes::Accumulator => "A".to_string(),
+        AddressingModes::Immediate => format!("#${:02X}", byte),
+        AddressingModes::ZeroPage => zero_page,
+        AddressingModes::ZeroPageX => format!("{},X", zero_page),
+        AddressingModes::ZeroPageY => format!("{},Y", zero_page),
+        AddressingModes::Relative => {
+            let target = address.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            label(target).unwrap_or_else(|| format!("${:04X}", target))
+        }
+        AddressingModes::Absolute => absolute,
+        AddressingModes::AbsoluteX => format!("{},X", absolute),
+        AddressingModes::AbsoluteY => format!("{},Y", absolute),
+        AddressingModes::Indirect => format!("({})", word_text),
+        AddressingModes::IndexedIndirect => format!("({},X)", zero_page),
+        AddressingModes::IndirectIndexed => format!("({}),Y", zero_page),
+    }
+}
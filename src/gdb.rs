@@ -0,0 +1,592 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::breakpoints::{BreakpointKind, BreakpointManager};
+use crate::cpu::Cpu;
+use crate::cpu_flags::CpuFlags;
+
+// The stop reply for everything but an interrupt, signal 5 is SIGTRAP
+const STOPPED: &str = "S05";
+// The stop reply after GDB interrupts a `c`, signal 2 is SIGINT
+const INTERRUPTED: &str = "S02";
+
+// What GDB sends to interrupt a running target (Ctrl-C)
+const INTERRUPT: u8 = 0x03;
+
+// Advertised in qSupported, in hex like everything else in the protocol
+const PACKET_SIZE: usize = 0x1000;
+// Memory replies are two hex digits a byte plus the framing, longer reads get
+// cut short, which GDB copes with
+const MAX_MEMORY_READ: u32 = (PACKET_SIZE as u32 - 4) / 2;
+
+// How many instructions a `c` runs between checks for an interrupt
+const INTERRUPT_POLL_INSTRUCTIONS: u64 = 10_000;
+
+// A connection that can say whether an interrupt is waiting, without blocking
+pub trait Interruptible: Read {
+    fn poll_interrupt(&mut self) -> io::Result<bool>;
+}
+
+impl Interruptible for TcpStream {
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0; 1];
+        let peeked = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+
+        match peeked {
+            Ok(1) if byte[0] == INTERRUPT => {
+                self.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            // GDB hung up, stop so that the session can end
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(false),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+// For scripted sessions, which arrive all at once and are read from the
+// buffer instead
+impl Interruptible for &[u8] {
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+// Checks what is already buffered before asking the connection
+fn poll_interrupt<R: Interruptible>(reader: &mut BufReader<R>) -> io::Result<bool> {
+    match reader.buffer().first() {
+        Some(&INTERRUPT) => {
+            reader.consume(1);
+            Ok(true)
+        }
+        Some(_) => Ok(false),
+        None => reader.get_mut().poll_interrupt(),
+    }
+}
+
+// Server for the GDB remote serial protocol, see:
+// https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+//
+// GDB has no 6502 target, so the register file is our own: A, X, Y, P and S
+// as one byte each, then PC as two bytes, little endian. Register numbers for
+// the `p` and `P` packets follow the same order.
+pub struct GdbStub {
+    cpu: Cpu,
+    breakpoints: BreakpointManager,
+    // Breakpoint ids by address, for the `z0` packet
+    breakpoint_ids: HashMap<u16, usize>,
+}
+
+impl GdbStub {
+    pub fn new(cpu: Cpu) -> Self {
+        GdbStub {
+            cpu,
+            breakpoints: BreakpointManager::new(),
+            breakpoint_ids: HashMap::new(),
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    // Waits for one debugger to connect and serves it until it detaches
+    pub fn listen(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+
+        let reader = stream.try_clone()?;
+        self.serve(reader, stream)
+    }
+
+    // Handles packets until the connection closes or the debugger sends a
+    // detach or kill
+    pub fn serve<R: Interruptible, W: Write>(
+        &mut self,
+        reader: R,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let mut reader = BufReader::new(reader);
+
+        while let Some(packet) = read_packet(&mut reader)? {
+            let Some(packet) = packet else {
+                writer.write_all(b"-")?;
+                continue;
+            };
+            writer.write_all(b"+")?;
+
+            let (reply, done) = match packet.as_str() {
+                "D" => ("OK".to_string(), true),
+                "k" => return Ok(()),
+                _ if packet.starts_with('c') => {
+                    (self.resume_running(&packet[1..], &mut reader)?, false)
+                }
+                _ => (self.handle(&packet), false),
+            };
+
+            write_packet(&mut writer, &reply)?;
+
+            if done {
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> String {
+        let result = match packet.as_bytes().first() {
+            Some(b'?') => Ok(STOPPED.to_string()),
+            Some(b'g') => Ok(self.read_registers()),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b's') => self.resume(&packet[1..]),
+            Some(b'Z') => self.insert_breakpoint(&packet[1..]),
+            Some(b'z') => self.remove_breakpoint(&packet[1..]),
+            Some(b'H') => Ok("OK".to_string()),
+            _ if packet.starts_with("qSupported") => Ok(format!("PacketSize={:x}", PACKET_SIZE)),
+            _ if packet == "qAttached" => Ok("1".to_string()),
+            // Anything else is unsupported, which GDB expects as an empty reply
+            _ => Ok(String::new()),
+        };
+
+        result.unwrap_or_else(|error| error)
+    }
+
+    fn registers(&self) -> [u8; 7] {
+        let state = self.cpu.state();
+        let [pc_lo, pc_hi] = state.program_counter.to_le_bytes();
+
+        [
+            state.register_accumulator,
+            state.register_x,
+            state.register_y,
+            state.register_status.bits(),
+            state.stack_pointer,
+            pc_lo,
+            pc_hi,
+        ]
+    }
+
+    fn set_registers(&mut self, registers: [u8; 7]) {
+        let mut state = self.cpu.state();
+
+        state.register_accumulator = registers[0];
+        state.register_x = registers[1];
+        state.register_y = registers[2];
+        state.register_status = CpuFlags::from_bits_truncate(registers[3]);
+        state.stack_pointer = registers[4];
+        state.program_counter = u16::from_le_bytes([registers[5], registers[6]]);
+
+        self.cpu.set_state(state);
+    }
+
+    fn read_registers(&self) -> String {
+        encode_hex(&self.registers())
+    }
+
+    fn write_registers(&mut self, data: &str) -> Result<String, String> {
+        let registers: [u8; 7] = decode_hex(data)?.try_into().map_err(|_| "E01")?;
+        self.set_registers(registers);
+        Ok("OK".to_string())
+    }
+
+    // Returns the byte range of a register in the register file
+    fn register_range(number: &str) -> Result<std::ops::Range<usize>, String> {
+        match parse_hex(number)? {
+            number @ 0..=4 => Ok(number as usize..number as usize + 1),
+            5 => Ok(5..7),
+            _ => Err("E01".to_string()),
+        }
+    }
+
+    fn read_register(&self, data: &str) -> Result<String, String> {
+        let range = Self::register_range(data)?;
+        Ok(encode_hex(&self.registers()[range]))
+    }
+
+    fn write_register(&mut self, data: &str) -> Result<String, String> {
+        let (number, value) = data.split_once('=').ok_or("E01")?;
+        let range = Self::register_range(number)?;
+        let value = decode_hex(value)?;
+
+        if value.len() != range.len() {
+            return Err("E01".to_string());
+        }
+
+        let mut registers = self.registers();
+        registers[range].copy_from_slice(&value);
+        self.set_registers(registers);
+
+        Ok("OK".to_string())
+    }
+
+    fn read_memory(&self, data: &str) -> Result<String, String> {
+        let (address, length) = data.split_once(',').ok_or("E01")?;
+        let address = parse_address(address)?;
+        let length = parse_hex(length)?.min(MAX_MEMORY_READ);
+
+        let bus = self.cpu.bus();
+        let bytes: Vec<u8> = (0..length)
+            .map(|offset| bus.peek(address.wrapping_add(offset as u16)))
+            .collect();
+
+        Ok(encode_hex(&bytes))
+    }
+
+    fn write_memory(&mut self, data: &str) -> Result<String, String> {
+        let (range, bytes) = data.split_once(':').ok_or("E01")?;
+        let (address, length) = range.split_once(',').ok_or("E01")?;
+        let address = parse_address(address)?;
+        let bytes = decode_hex(bytes)?;
+
+        if bytes.len() as u32 != parse_hex(length)? {
+            return Err("E01".to_string());
+        }
+
+        // Only RAM takes the bytes. ROM and device registers get an error
+        // rather than a reply claiming the write stuck.
+        for (offset, byte) in bytes.into_iter().enumerate() {
+            self.cpu
+                .bus_mut()
+                .poke(address.wrapping_add(offset as u16), byte)
+                .map_err(|_| "E01")?;
+        }
+
+        Ok("OK".to_string())
+    }
+
+    // `s` and `c` can carry an address to resume from
+    fn resume_from(&mut self, data: &str) -> Result<(), String> {
+        if !data.is_empty() {
+            let mut state = self.cpu.state();
+            state.program_counter = parse_address(data)?;
+            self.cpu.set_state(state);
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, data: &str) -> Result<String, String> {
+        self.resume_from(data)?;
+        self.cpu.step();
+        Ok(STOPPED.to_string())
+    }
+
+    // Runs in slices, looking for an interrupt from GDB in between
+    fn resume_running<R: Interruptible>(
+        &mut self,
+        data: &str,
+        reader: &mut BufReader<R>,
+    ) -> io::Result<String> {
+        if let Err(error) = self.resume_from(data) {
+            return Ok(error);
+        }
+
+        loop {
+            let stopped = self
+                .cpu
+                .run_with_breakpoints_for(&mut self.breakpoints, INTERRUPT_POLL_INSTRUCTIONS);
+            if stopped.is_some() {
+                return Ok(STOPPED.to_string());
+            }
+            if poll_interrupt(reader)? {
+                return Ok(INTERRUPTED.to_string());
+            }
+        }
+    }
+
+    fn insert_breakpoint(&mut self, data: &str) -> Result<String, String> {
+        let Some(address) = software_breakpoint_address(data)? else {
+            return Ok(String::new());
+        };
+
+        if !self.breakpoint_ids.contains_key(&address) {
+            let id = self.breakpoints.add(BreakpointKind::Execute(address));
+            self.breakpoint_ids.insert(address, id);
+        }
+
+        Ok("OK".to_string())
+    }
+
+    fn remove_breakpoint(&mut self, data: &str) -> Result<String, String> {
+        let Some(address) = software_breakpoint_address(data)? else {
+            return Ok(String::new());
+        };
+
+        if let Some(id) = self.breakpoint_ids.remove(&address) {
+            self.breakpoints.remove(id);
+        }
+
+        Ok("OK".to_string())
+    }
+}
+
+// Only software breakpoints, `0,addr,kind`, are supported. Returns `None` for
+// the other types so that they get the unsupported reply.
+fn software_breakpoint_address(data: &str) -> Result<Option<u16>, String> {
+    let mut fields = data.split(',');
+
+    if fields.next() != Some("0") {
+        return Ok(None);
+    }
+
+    let address = fields.next().ok_or("E01")?;
+    Ok(Some(parse_address(address)?))
+}
+
+// Returns `None` at the end of the stream, and `Some(None)` for a packet with
+// a bad checksum that should be sent again
+fn read_packet<R: Read>(reader: &mut R) -> io::Result<Option<Option<String>>> {
+    let mut byte = [0; 1];
+
+    // Skip acknowledgements until a packet starts. Interrupts (Ctrl-C) are
+    // skipped too, since the CPU is never running while we wait here.
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+
+        if byte[0] == b'$' {
+            break;
+        }
+    }
+
+    let mut data = Vec::new();
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+
+        if byte[0] == b'#' {
+            break;
+        }
+        data.push(byte[0]);
+    }
+
+    let mut checksum = [0; 2];
+    reader.read_exact(&mut checksum)?;
+
+    let expected = std::str::from_utf8(&checksum)
+        .ok()
+        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+    if expected != Some(packet_checksum(&data)) {
+        return Ok(Some(None));
+    }
+
+    Ok(Some(Some(String::from_utf8_lossy(&data).into_owned())))
+}
+
+fn write_packet<W: Write>(writer: &mut W, data: &str) -> io::Result<()> {
+    write!(writer, "${}#{:02x}", data, packet_checksum(data.as_bytes()))?;
+    writer.flush()
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_hex(text: &str) -> Result<u32, String> {
+    u32::from_str_radix(text, 16).map_err(|_| "E01".to_string())
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    u16::try_from(parse_hex(text)?).map_err(|_| "E01".to_string())
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) {
+        return Err("E01".to_string());
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| {
+            text.get(index..index + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| "E01".to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::{self, JoinHandle};
+
+    use super::{packet_checksum, GdbStub, MAX_MEMORY_READ};
+    use crate::bus::Bus;
+    use crate::cpu::tests::cpu_with_program;
+    use crate::cpu::Cpu;
+    use crate::nes::tests::rom;
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        // Sends a packet and returns the reply, checking both acknowledgements
+        fn send(&mut self, data: &str) -> String {
+            self.write(data);
+            self.reply()
+        }
+
+        fn write(&mut self, data: &str) {
+            let packet = format!("${}#{:02x}", data, packet_checksum(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+
+            let mut ack = [0; 1];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+', "no acknowledgement for {}", data);
+        }
+
+        fn reply(&mut self) -> String {
+            let mut reply = Vec::new();
+            let mut byte = [0; 1];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+
+            assert_eq!(reply[0], b'$');
+            let reply = String::from_utf8(reply[1..].to_vec()).unwrap();
+            assert_eq!(
+                std::str::from_utf8(&checksum).unwrap(),
+                format!("{:02x}", packet_checksum(reply.as_bytes()))
+            );
+
+            reply
+        }
+    }
+
+    fn cpu() -> Cpu {
+        cpu_with_program(
+            "
+            .org $0200
+                lda #$40
+                ldx #$03
+            loop:
+                inx
+                jmp loop
+            ",
+        )
+    }
+
+    // Serves `cpu()` on a thread, the handle returns the stub once GDB
+    // detaches
+    fn connect() -> (Client, JoinHandle<GdbStub>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let mut stub = GdbStub::new(cpu());
+            stub.listen(&listener).unwrap();
+            stub
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        (Client { stream }, server)
+    }
+
+    #[test]
+    fn scripted_session() {
+        let (mut client, server) = connect();
+
+        assert_eq!(client.send("qSupported:swbreak+"), "PacketSize=1000");
+        assert_eq!(client.send("?"), "S05");
+        assert_eq!(client.send("g"), "00000024fd0002");
+        assert_eq!(client.send("m200,4"), "a940a203");
+
+        assert_eq!(client.send("s"), "S05");
+        assert_eq!(client.send("p0"), "40");
+        assert_eq!(client.send("p5"), "0202");
+
+        assert_eq!(client.send("Z0,205,1"), "OK");
+        assert_eq!(client.send("c"), "S05");
+        assert_eq!(client.send("g"), "40040024fd0502");
+
+        // Continuing runs past the breakpoint and around the loop again
+        assert_eq!(client.send("c"), "S05");
+        assert_eq!(client.send("p1"), "05");
+        assert_eq!(client.send("z0,205,1"), "OK");
+
+        assert_eq!(client.send("G01020381fc0402"), "OK");
+        assert_eq!(client.send("P1=10"), "OK");
+        assert_eq!(client.send("g"), "01100381fc0402");
+
+        assert_eq!(client.send("M300,3:010203"), "OK");
+        assert_eq!(client.send("m300,3"), "010203");
+        assert_eq!(client.send("M300,2:01"), "E01");
+        assert_eq!(client.send("m10000,1"), "E01");
+        assert_eq!(client.send("M10000,1:01"), "E01");
+        assert_eq!(client.send("Z0,10000,1"), "E01");
+        assert_eq!(client.send("c10000"), "E01");
+
+        // Longer reads than fit in a packet are cut short
+        assert_eq!(
+            client.send("m0,ffffffff").len(),
+            2 * MAX_MEMORY_READ as usize
+        );
+        assert_eq!(client.send("Z2,300,1"), "");
+        assert_eq!(client.send("vMustReplyEmpty"), "");
+        assert_eq!(client.send("D"), "OK");
+
+        let stub = server.join().unwrap();
+        assert_eq!(stub.cpu().bus().peek(0x0301), 0x02);
+        assert_eq!(stub.cpu().state().register_x, 0x10);
+    }
+
+    #[test]
+    fn interrupts_stop_a_running_target() {
+        let (mut client, server) = connect();
+
+        // The program loops forever, only the interrupt brings it back
+        client.write("c");
+        client.stream.write_all(&[0x03]).unwrap();
+        assert_eq!(client.reply(), "S02");
+        assert_eq!(client.send("p0"), "40");
+        assert_eq!(client.send("D"), "OK");
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn bad_checksums_are_rejected() {
+        let mut stub = GdbStub::new(cpu());
+        let mut output = Vec::new();
+
+        stub.serve(&b"$g#00$?#3f"[..], &mut output).unwrap();
+        assert_eq!(output, b"-+$S05#b8");
+    }
+    #[test]
+    fn memory_writes_only_reach_ram() {
+        let mut stub = GdbStub::new(Cpu::new(Bus::with_rom(rom(".org $8000\n nop"))));
+
+        assert_eq!(stub.handle("M10,1:55"), "OK");
+        assert_eq!(stub.handle("m10,1"), "55");
+        assert_eq!(stub.handle("M8000,1:00"), "E01");
+        assert_eq!(stub.handle("M2000,1:80"), "E01");
+        assert_eq!(stub.handle("m8000,1"), "ea");
+    }
+}
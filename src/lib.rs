@@ -0,0 +1,25 @@
+pub mod asm;
+pub mod battery;
+pub mod breakpoints;
+pub mod bus;
+pub mod call_stack;
+pub mod cartridge;
+pub mod cdl;
+pub mod constants;
+pub mod controller;
+pub mod cpu;
+pub mod cpu_flags;
+pub mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod memory;
+pub mod movie;
+pub mod nes;
+pub mod operation;
+pub mod profiler;
+pub mod raw;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod symbols;
+pub mod trace;
@@ -1,9 +1,407 @@
-mod bus;
-mod constants;
-mod cpu;
-mod cpu_flags;
-mod operation;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use env_logger::Env;
+use log::warn;
+use rust_nes_emulator::battery::{save_path, BatterySave};
+use rust_nes_emulator::bus::{BadAccessPolicy, Bus};
+use rust_nes_emulator::cartridge::Rom;
+use rust_nes_emulator::cdl::CodeDataLogger;
+use rust_nes_emulator::constants::LogTargets;
+use rust_nes_emulator::cpu::Cpu;
+use rust_nes_emulator::debugger::{parse_number, Debugger};
+use rust_nes_emulator::gdb::GdbStub;
+use rust_nes_emulator::nes::frame_at_cycle;
+use rust_nes_emulator::profiler::Profiler;
+use rust_nes_emulator::raw::{run_raw, RawOptions};
+use rust_nes_emulator::symbols::SymbolTable;
+use rust_nes_emulator::trace::Tracer;
+
+const USAGE: &str = "usage:
+    nes debug <rom.nes> [session options]
+    nes gdb <rom.nes> [port] [session options]
+    nes run-raw <prog.bin> [--load ADDR] [--start ADDR] [--until-brk]
+        [--putchar ADDR | --no-putchar] [--max-cycles N] [--dump START:END]... [--json]
+
+session options, the files are written when the session ends:
+    --profile FILE          cycles by subroutine and address
+    --folded-stacks FILE    cycles by call path, for flamegraph.pl and inferno
+    --cdl FILE              FCEUX code/data log, added to when FILE exists
+    --trace FILE            every instruction as it runs, like nestest.log";
+
+const DEFAULT_GDB_PORT: &str = "2345";
+
+// About a second of frames
+const BATTERY_FLUSH_INTERVAL: u64 = 60;
+
+// Rows in each table of the profile
+const PROFILE_REPORT_LIMIT: usize = 40;
 
 fn main() {
-    println!("Hello, world!");
+    // RUST_LOG overrides this, for example RUST_LOG=bus=off,cpu=debug
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+
+    let arguments: Vec<String> = env::args().skip(1).collect();
+
+    let result = match arguments.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["debug", path, ref options @ ..] => debug(path, options),
+        ["gdb", path, port, ref options @ ..] if !port.starts_with("--") => {
+            gdb(path, port, options)
+        }
+        ["gdb", path, ref options @ ..] => gdb(path, DEFAULT_GDB_PORT, options),
+        ["run-raw", path, ref options @ ..] => raw(path, options),
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn load_rom(path: &str) -> Result<Rom, String> {
+    let raw = fs::read(path).map_err(|error| format!("Could not read {}: {}", path, error))?;
+    let rom = Rom::new(&raw)?;
+
+    if rom.mapper != 0 {
+        return Err(format!("Mapper {} is not supported", rom.mapper));
+    }
+
+    Ok(rom)
+}
+
+// Labels for the debugger and traces, from the symbol files next to the ROM
+fn load_symbols(path: &str) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+    for file in symbols.load_for_rom(Path::new(path))? {
+        println!("Loaded symbols from {}", file);
+    }
+    Ok(symbols)
+}
+
+// Cartridges with a battery keep their save RAM in a .sav file next to the
+// ROM. It is loaded before reset, written back every so often while the game
+// runs, see `flush_battery_periodically`, and again when the session ends.
+fn load_battery(
+    path: &str,
+    has_battery: bool,
+    bus: &mut Bus,
+) -> Result<Option<Arc<Mutex<BatterySave>>>, String> {
+    if !has_battery {
+        return Ok(None);
+    }
+
+    let mut battery = BatterySave::new(save_path(Path::new(path)), BATTERY_FLUSH_INTERVAL);
+    if battery.load(bus)? {
+        println!("Loaded save RAM from {}", battery.path().display());
+    }
+    Ok(Some(Arc::new(Mutex::new(battery))))
+}
+
+// Counts frames from the cycles the CPU runs, as `Nes` does, and offers
+// the battery a flush on each new one. A failed write is logged and tried
+// again later rather than stopping the session.
+fn flush_battery_periodically(cpu: &mut Cpu, battery: &Arc<Mutex<BatterySave>>) {
+    let battery = Arc::clone(battery);
+    let mut cycles = 0;
+    let mut frame = 0;
+
+    cpu.add_step_hook(move |cpu, step| {
+        cycles += step.cycles as u64;
+        let now = frame_at_cycle(cycles);
+        if now == frame {
+            return;
+        }
+        frame = now;
+
+        let mut battery = battery.lock().unwrap();
+        if let Err(error) = battery.flush_periodically(cpu.bus(), frame) {
+            warn!(target: LogTargets::MAPPER, "{}", error);
+        }
+    });
+}
+
+// Tools that watch the CPU for a whole debug or GDB session
+#[derive(Default)]
+struct SessionOptions {
+    profile: Option<String>,
+    folded_stacks: Option<String>,
+    cdl: Option<String>,
+    trace: Option<String>,
+}
+
+fn parse_session_options(arguments: &[&str]) -> Result<SessionOptions, String> {
+    let mut options = SessionOptions::default();
+    let mut arguments = arguments.iter();
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .map(|value| value.to_string())
+                .ok_or_else(|| format!("{} needs a value", argument))
+        };
+
+        match *argument {
+            "--profile" => options.profile = Some(value()?),
+            "--folded-stacks" => options.folded_stacks = Some(value()?),
+            "--cdl" => options.cdl = Some(value()?),
+            "--trace" => options.trace = Some(value()?),
+            _ => return Err(format!("Unknown option {}\n{}", argument, USAGE)),
+        }
+    }
+
+    Ok(options)
+}
+
+// The session's tools, hooked into the CPU until `finish` writes them out.
+// The trace is written as it goes.
+struct SessionTools {
+    profiler: Option<Arc<Mutex<Profiler>>>,
+    cdl: Option<Arc<Mutex<CodeDataLogger>>>,
+    tracer: Option<Arc<Mutex<Tracer<BufWriter<File>>>>>,
+}
+
+impl SessionTools {
+    fn new(options: &SessionOptions, rom: &Rom, symbols: &SymbolTable) -> Result<Self, String> {
+        let profiler = (options.profile.is_some() || options.folded_stacks.is_some())
+            .then(|| Arc::new(Mutex::new(Profiler::new())));
+
+        let cdl = match &options.cdl {
+            Some(path) => {
+                let (prg_rom_size, chr_rom_size) = (rom.prg_rom.len(), rom.chr_rom.len());
+                let logger = match fs::read(path) {
+                    Ok(raw) => CodeDataLogger::from_bytes(&raw, prg_rom_size, chr_rom_size)
+                        .map_err(|error| format!("{}: {}", path, error))?,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                        CodeDataLogger::new(prg_rom_size, chr_rom_size)
+                    }
+                    Err(error) => return Err(format!("Could not read {}: {}", path, error)),
+                };
+                Some(Arc::new(Mutex::new(logger)))
+            }
+            None => None,
+        };
+
+        let tracer = match &options.trace {
+            Some(path) => {
+                let file = File::create(path)
+                    .map_err(|error| format!("Could not write {}: {}", path, error))?;
+                let tracer = Tracer::new(BufWriter::new(file), symbols.clone());
+                Some(Arc::new(Mutex::new(tracer)))
+            }
+            None => None,
+        };
+
+        Ok(SessionTools {
+            profiler,
+            cdl,
+            tracer,
+        })
+    }
+
+    fn attach(&self, cpu: &mut Cpu) {
+        if let Some(profiler) = &self.profiler {
+            let profiler = Arc::clone(profiler);
+            cpu.add_step_hook(move |cpu, step| profiler.lock().unwrap().record_step(cpu, step));
+        }
+        if let Some(cdl) = &self.cdl {
+            let cdl = Arc::clone(cdl);
+            cpu.add_step_hook(move |cpu, step| cdl.lock().unwrap().record_step(cpu, step));
+        }
+        if let Some(tracer) = &self.tracer {
+            tracer.lock().unwrap().begin(cpu);
+            let tracer = Arc::clone(tracer);
+            cpu.add_step_hook(move |cpu, step| tracer.lock().unwrap().record_step(cpu, step));
+        }
+    }
+
+    // Writes every file, even after one fails, and returns the first error
+    fn finish(&self, options: &SessionOptions) -> Result<(), String> {
+        let mut results = Vec::new();
+
+        if let Some(profiler) = &self.profiler {
+            let profiler = profiler.lock().unwrap();
+            if let Some(path) = &options.profile {
+                results.push(write_file(path, |file| {
+                    profiler.write_report(file, PROFILE_REPORT_LIMIT)
+                }));
+            }
+            if let Some(path) = &options.folded_stacks {
+                results.push(write_file(path, |file| profiler.write_folded_stacks(file)));
+            }
+        }
+
+        if let (Some(cdl), Some(path)) = (&self.cdl, &options.cdl) {
+            let bytes = cdl.lock().unwrap().to_bytes();
+            results.push(write_file(path, |file| file.write_all(&bytes)));
+        }
+
+        if let (Some(tracer), Some(path)) = (&self.tracer, &options.trace) {
+            let finished = tracer.lock().unwrap().finish();
+            results.push(finished.map_err(|error| format!("Could not write {}: {}", path, error)));
+        }
+
+        results.into_iter().collect()
+    }
+}
+
+// Writes out the save RAM and the session's tools, all of them even when
+// one fails, then returns the first error. The session's own error comes
+// before the rest.
+fn end_session(
+    result: Result<(), String>,
+    battery: Option<&Arc<Mutex<BatterySave>>>,
+    bus: &Bus,
+    tools: &SessionTools,
+    options: &SessionOptions,
+) -> Result<(), String> {
+    let flushed = match battery {
+        Some(battery) => battery.lock().unwrap().flush(bus).map(|_| ()),
+        None => Ok(()),
+    };
+    let finished = tools.finish(options);
+
+    result.and(flushed).and(finished)
+}
+
+fn write_file<F: FnOnce(&mut BufWriter<File>) -> io::Result<()>>(
+    path: &str,
+    write: F,
+) -> Result<(), String> {
+    let error = |error: io::Error| format!("Could not write {}: {}", path, error);
+    let mut file = BufWriter::new(File::create(path).map_err(error)?);
+    write(&mut file).and_then(|()| file.flush()).map_err(error)
+}
+
+fn debug(path: &str, arguments: &[&str]) -> Result<(), String> {
+    let options = parse_session_options(arguments)?;
+    let rom = load_rom(path)?;
+    let has_battery = rom.has_battery;
+    let symbols = load_symbols(path)?;
+    let tools = SessionTools::new(&options, &rom, &symbols)?;
+
+    // Stray accesses stop at the prompt instead of scrolling past
+    let mut bus = Bus::with_rom(rom);
+    bus.set_bad_access_policy(BadAccessPolicy::Trap);
+    let battery = load_battery(path, has_battery, &mut bus)?;
+
+    let mut cpu = Cpu::new(bus);
+    cpu.reset();
+    tools.attach(&mut cpu);
+    if let Some(battery) = &battery {
+        flush_battery_periodically(&mut cpu, battery);
+    }
+
+    let mut debugger = Debugger::new(cpu);
+    debugger.set_symbols(symbols);
+    // Ctrl-C stops a running command rather than the whole session
+    signal_hook::flag::register(signal_hook::consts::SIGINT, debugger.interrupt_flag())
+        .map_err(|error| format!("Could not handle Ctrl-C: {}", error))?;
+    let result = debugger
+        .run(io::stdin().lock(), &mut io::stdout())
+        .map_err(|error| error.to_string());
+
+    end_session(
+        result,
+        battery.as_ref(),
+        debugger.cpu().bus(),
+        &tools,
+        &options,
+    )
+}
+
+// Only listens on localhost, the protocol has no authentication
+fn gdb(path: &str, port: &str, arguments: &[&str]) -> Result<(), String> {
+    let options = parse_session_options(arguments)?;
+    let rom = load_rom(path)?;
+    let has_battery = rom.has_battery;
+    let symbols = load_symbols(path)?;
+    let tools = SessionTools::new(&options, &rom, &symbols)?;
+
+    let mut bus = Bus::with_rom(rom);
+    let battery = load_battery(path, has_battery, &mut bus)?;
+
+    let mut cpu = Cpu::new(bus);
+    cpu.reset();
+    tools.attach(&mut cpu);
+    if let Some(battery) = &battery {
+        flush_battery_periodically(&mut cpu, battery);
+    }
+
+    let address = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&address).map_err(|error| error.to_string())?;
+    println!("Waiting for GDB on {}", address);
+
+    let mut stub = GdbStub::new(cpu);
+    let result = stub.listen(&listener).map_err(|error| error.to_string());
+
+    end_session(result, battery.as_ref(), stub.cpu().bus(), &tools, &options)
+}
+
+// Runs a plain 6502 binary on a flat memory map. The program's output goes
+// to stdout, followed by either a summary or a JSON result for scripts.
+fn raw(path: &str, arguments: &[&str]) -> Result<(), String> {
+    let program = fs::read(path).map_err(|error| format!("Could not read {}: {}", path, error))?;
+
+    let mut options = RawOptions::default();
+    let mut json = false;
+    let mut start = None;
+    let mut arguments = arguments.iter();
+
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or_else(|| format!("{} needs a value", argument))
+        };
+
+        match *argument {
+            "--load" => options.load_address = parse_address(value()?)?,
+            "--start" => start = Some(parse_address(value()?)?),
+            "--until-brk" => options.until_brk = true,
+            "--putchar" => options.putchar_port = Some(parse_address(value()?)?),
+            "--no-putchar" => options.putchar_port = None,
+            "--max-cycles" => options.max_cycles = parse_number(value()?)?,
+            "--dump" => {
+                let range = value()?;
+                let (first, last) = range
+                    .split_once(':')
+                    .ok_or_else(|| format!("dump range '{}' is not START:END", range))?;
+                options
+                    .dumps
+                    .push((parse_address(first)?, parse_address(last)?));
+            }
+            "--json" => json = true,
+            _ => return Err(format!("Unknown option {}\n{}", argument, USAGE)),
+        }
+    }
+
+    // Programs start where they are loaded unless told otherwise
+    options.start_address = start.unwrap_or(options.load_address);
+
+    let result = run_raw(&program, &options)?;
+
+    if json {
+        println!("{}", result.to_json());
+    } else {
+        let output = String::from_utf8_lossy(&result.output);
+        print!("{}", output);
+        if !output.is_empty() && !output.ends_with('\n') {
+            println!();
+        }
+        println!("{}", result.summary());
+    }
+
+    Ok(())
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let value = parse_number(text)?;
+    u16::try_from(value).map_err(|_| format!("address '{}' is out of range", text))
 }
@@ -0,0 +1,145 @@
+use crate::constants::FLAT_MEMORY_SIZE;
+
+// Everything the CPU needs from the machine it is plugged into. The NES `Bus`
+// is one implementation, other 6502 machines or test harnesses can provide
+// their own.
+pub trait Memory {
+    fn read(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    // Reads without any side effects, for debuggers and disassemblers
+    fn peek(&self, address: u16) -> u8;
+
+    // Reads the first byte of an instruction. Only differs from `read` for
+    // memories that tell opcode fetches apart.
+    fn fetch_opcode(&mut self, address: u16) -> u8 {
+        self.read(address)
+    }
+
+    // Called after every instruction with the cycles it took, so the rest of
+    // the machine can catch up
+    fn tick(&mut self, _cycles: u8) {}
+
+    // The levels of the interrupt inputs, true when asserted. NMI is edge
+    // triggered, IRQ is level triggered.
+    fn nmi_line(&self) -> bool {
+        false
+    }
+
+    fn irq_line(&self) -> bool {
+        false
+    }
+}
+
+// 64 KiB of plain RAM with no devices or mirroring
+pub struct FlatMemory {
+    data: Vec<u8>,
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory {
+            data: vec![0; FLAT_MEMORY_SIZE],
+        }
+    }
+
+    // Copies `bytes` in starting at `address`, wrapping at the end of memory
+    pub fn load(&mut self, address: u16, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.data[address.wrapping_add(offset as u16) as usize] = *byte;
+        }
+    }
+}
+
+impl Memory for FlatMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        self.data[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.data[address as usize] = value;
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.data[address as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlatMemory, Memory};
+    use crate::asm::assemble;
+    use crate::cpu::Cpu;
+
+    // Counts cycles and refuses to let the program touch the page it is
+    // watching, to show the CPU only goes through the trait
+    struct CountingMemory {
+        inner: FlatMemory,
+        cycles: u64,
+    }
+
+    impl Memory for CountingMemory {
+        fn read(&mut self, address: u16) -> u8 {
+            self.inner.read(address)
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            if address >> 8 != 0x03 {
+                self.inner.write(address, value);
+            }
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            self.inner.peek(address)
+        }
+
+        fn tick(&mut self, cycles: u8) {
+            self.cycles += cycles as u64;
+        }
+    }
+
+    #[test]
+    fn runs_on_any_memory() {
+        let assembly = assemble(
+            "
+            .org $8000
+                ldx #3
+            loop:
+                txa
+                sta $0200,x
+                sta $0300,x
+                dex
+                bne loop
+            done:
+                jmp done
+            ",
+        )
+        .unwrap();
+
+        let mut memory = FlatMemory::new();
+        memory.load(assembly.origin, &assembly.bytes);
+        memory.load(0xFFFC, &[0x00, 0x80]);
+
+        let mut cpu = Cpu::new(CountingMemory {
+            inner: memory,
+            cycles: 0,
+        });
+        cpu.reset();
+
+        assert_eq!(cpu.run_until_trap(1000), Ok(0x800C));
+        assert_eq!(cpu.bus().peek(0x0203), 3);
+        assert_eq!(cpu.bus().peek(0x0201), 1);
+        assert_eq!(cpu.bus().peek(0x0303), 0);
+
+        // LDX, three loops of TXA STA STA DEX BNE with the last branch not
+        // taken, and the first JMP
+        assert_eq!(cpu.bus().cycles, 2 + 3 * (2 + 5 + 5 + 2 + 3) - 1 + 3);
+    }
+}
@@ -0,0 +1,491 @@
+use std::fmt::Write;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bitflags::bitflags;
+
+use crate::controller::Buttons;
+use crate::nes::Nes;
+
+// The button characters of an input field, leftmost is the highest bit
+const BUTTON_CHARACTERS: &[u8; 8] = b"RLDUTSBA";
+
+bitflags! {
+    #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+    pub struct MovieCommands: u8 {
+        // The reset button
+        const RESET = 0b01;
+        // Turning the console off and on
+        const POWER = 0b10;
+    }
+}
+
+// What happens in one frame: commands first, then the frame runs with the
+// buttons held on both controllers
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct MovieFrame {
+    pub commands: MovieCommands,
+    pub buttons: [Buttons; 2],
+}
+
+// An input movie in FCEUX's FM2 text format, see:
+// https://fceux.com/web/FM2.html
+//
+// Only NTSC movies with standard controllers are supported. Movies that start
+// from a save state embed one of ours, FCEUX's own can't be loaded.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Movie {
+    pub rom_filename: String,
+    // MD5 of the PRG and CHR ROM
+    pub rom_checksum: [u8; 16],
+    pub rerecord_count: u32,
+    pub comments: Vec<String>,
+    // Where playback starts, power on when there is none
+    pub savestate: Option<Vec<u8>>,
+    pub frames: Vec<MovieFrame>,
+    // Header keys nothing here uses, written back as they were
+    pub other_headers: Vec<(String, String)>,
+}
+
+impl Movie {
+    pub fn new(rom_filename: &str, rom_checksum: [u8; 16]) -> Self {
+        Movie {
+            rom_filename: rom_filename.to_string(),
+            rom_checksum,
+            rerecord_count: 0,
+            comments: Vec::new(),
+            savestate: None,
+            frames: Vec::new(),
+            other_headers: Vec::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::new("", [0; 16]);
+        let mut version = None;
+        let mut rom_checksum = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let error = |message: String| format!("Line {}: {}", index + 1, message);
+
+            if line.starts_with('|') {
+                movie.frames.push(parse_frame(line).map_err(error)?);
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let unsupported = |what: &str| Err(error(format!("{} are not supported", what)));
+
+            match key {
+                "version" => version = Some(value.to_string()),
+                "binary" if value != "0" => return unsupported("Binary movies"),
+                "palFlag" if value != "0" => return unsupported("PAL movies"),
+                "fourscore" if value != "0" => return unsupported("Four Score movies"),
+                "port0" | "port1" if value != "0" && value != "1" => {
+                    return unsupported("Controllers other than the standard one")
+                }
+                "port2" if value != "0" => return unsupported("Expansion port devices"),
+                "binary" | "palFlag" | "fourscore" | "port0" | "port1" | "port2" => {}
+                "rerecordCount" => {
+                    movie.rerecord_count = value
+                        .parse()
+                        .map_err(|_| error(format!("'{}' is not a count", value)))?;
+                }
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => {
+                    let checksum = parse_binary(value).map_err(error)?;
+                    let checksum = checksum
+                        .try_into()
+                        .map_err(|_| error("The ROM checksum is not an MD5".to_string()))?;
+                    rom_checksum = Some(checksum);
+                }
+                "savestate" => movie.savestate = Some(parse_binary(value).map_err(error)?),
+                "comment" => movie.comments.push(value.to_string()),
+                _ => movie
+                    .other_headers
+                    .push((key.to_string(), value.to_string())),
+            }
+        }
+
+        match version.as_deref() {
+            Some("3") => {}
+            Some(version) => return Err(format!("FM2 version {} is not supported", version)),
+            None => return Err("File is not an FM2 movie".to_string()),
+        }
+        movie.rom_checksum = rom_checksum.ok_or("Movie has no ROM checksum")?;
+
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut text = String::new();
+
+        writeln!(text, "version 3").unwrap();
+        writeln!(text, "rerecordCount {}", self.rerecord_count).unwrap();
+        writeln!(text, "palFlag 0").unwrap();
+        writeln!(text, "romFilename {}", self.rom_filename).unwrap();
+        writeln!(
+            text,
+            "romChecksum base64:{}",
+            STANDARD.encode(self.rom_checksum)
+        )
+        .unwrap();
+        writeln!(text, "fourscore 0").unwrap();
+        writeln!(text, "port0 1").unwrap();
+        writeln!(text, "port1 1").unwrap();
+        writeln!(text, "port2 0").unwrap();
+        if let Some(savestate) = &self.savestate {
+            writeln!(text, "savestate base64:{}", STANDARD.encode(savestate)).unwrap();
+        }
+        for (key, value) in &self.other_headers {
+            writeln!(text, "{} {}", key, value).unwrap();
+        }
+        for comment in &self.comments {
+            writeln!(text, "comment {}", comment).unwrap();
+        }
+
+        for frame in &self.frames {
+            let fields: Vec<String> = frame.buttons.iter().map(|b| format_buttons(*b)).collect();
+            writeln!(text, "|{}|{}||", frame.commands.bits(), fields.join("|")).unwrap();
+        }
+
+        text
+    }
+}
+
+// "|commands|port 0|port 1|port 2|", an empty port field means no controller
+fn parse_frame(line: &str) -> Result<MovieFrame, String> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 5 {
+        return Err(format!("'{}' is not an input line", line));
+    }
+
+    let commands: u8 = fields[1]
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a command", fields[1]))?;
+    let commands = MovieCommands::from_bits(commands)
+        .ok_or_else(|| format!("Command {} is not supported", commands))?;
+
+    Ok(MovieFrame {
+        commands,
+        buttons: [parse_buttons(fields[2])?, parse_buttons(fields[3])?],
+    })
+}
+
+// Any character other than '.' or a space is a pressed button
+fn parse_buttons(field: &str) -> Result<Buttons, String> {
+    if field.is_empty() {
+        return Ok(Buttons::empty());
+    }
+    if field.len() != BUTTON_CHARACTERS.len() {
+        return Err(format!("'{}' is not an RLDUTSBA input field", field));
+    }
+
+    let mut buttons = Buttons::empty();
+    for (index, character) in field.bytes().enumerate() {
+        if character != b'.' && character != b' ' {
+            buttons |= Buttons::from_bits_retain(0x80 >> index);
+        }
+    }
+    Ok(buttons)
+}
+
+fn format_buttons(buttons: Buttons) -> String {
+    BUTTON_CHARACTERS
+        .iter()
+        .enumerate()
+        .map(|(index, character)| {
+            if buttons.bits() & (0x80 >> index) != 0 {
+                *character as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+// FM2 binary values are either "base64:..." or "0x" and hex digits
+fn parse_binary(value: &str) -> Result<Vec<u8>, String> {
+    if let Some(encoded) = value.strip_prefix("base64:") {
+        return STANDARD
+            .decode(encoded)
+            .map_err(|error| format!("'{}' is not base64: {}", encoded, error));
+    }
+
+    let hex = value
+        .strip_prefix("0x")
+        .ok_or_else(|| format!("'{}' is neither base64 nor hex", value))?;
+    // Digits are sliced by byte, which only lines up with characters in ASCII
+    if !hex.is_ascii() {
+        return Err(format!("'{}' is not hex", value));
+    }
+    if hex.len() % 2 != 0 {
+        return Err(format!("'{}' has an odd number of hex digits", value));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .map_err(|_| format!("'{}' is not hex", value))
+        })
+        .collect()
+}
+
+pub(crate) fn run_frame(nes: &mut Nes, frame: &MovieFrame) {
+    if frame.commands.contains(MovieCommands::POWER) {
+        nes.power_cycle();
+    }
+    if frame.commands.contains(MovieCommands::RESET) {
+        nes.reset();
+    }
+
+    for (port, buttons) in frame.buttons.iter().enumerate() {
+        nes.set_buttons(port, *buttons);
+    }
+    nes.run_frame();
+}
+
+// Records the inputs of each frame as it runs
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    // Turns the console off and on, and records from there
+    pub fn from_power_on(nes: &mut Nes, rom_filename: &str) -> Self {
+        nes.power_cycle();
+        MovieRecorder {
+            movie: Movie::new(rom_filename, nes.rom_md5()),
+        }
+    }
+
+    // Records from wherever the console is now
+    pub fn from_savestate(nes: &Nes, rom_filename: &str) -> Self {
+        let mut movie = Movie::new(rom_filename, nes.rom_md5());
+        movie.savestate = Some(nes.save_state());
+        MovieRecorder { movie }
+    }
+
+    // Runs a frame with the given inputs and adds it to the movie
+    pub fn record_frame(&mut self, nes: &mut Nes, frame: MovieFrame) {
+        run_frame(nes, &frame);
+        self.movie.frames.push(frame);
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// Plays a movie back, overriding the controllers with its inputs
+pub struct MoviePlayer {
+    movie: Movie,
+    position: usize,
+}
+
+impl MoviePlayer {
+    // Checks the movie was made with this ROM and puts the console where it
+    // starts
+    pub fn start(movie: Movie, nes: &mut Nes) -> Result<Self, String> {
+        if movie.rom_checksum != nes.rom_md5() {
+            return Err(format!(
+                "Movie is for a different ROM ({}, checksum {})",
+                movie.rom_filename,
+                STANDARD.encode(movie.rom_checksum)
+            ));
+        }
+
+        match &movie.savestate {
+            Some(state) => nes.load_state(state)?,
+            None => nes.power_cycle(),
+        }
+
+        Ok(MoviePlayer { movie, position: 0 })
+    }
+
+    // Runs the next frame of the movie, returns false once it has ended
+    pub fn play_frame(&mut self, nes: &mut Nes) -> bool {
+        let Some(frame) = self.movie.frames.get(self.position) else {
+            return false;
+        };
+
+        run_frame(nes, frame);
+        self.position += 1;
+        true
+    }
+
+    // The number of frames played
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.movie.frames.len()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_binary, Movie, MovieCommands, MovieFrame, MoviePlayer, MovieRecorder};
+    use crate::controller::Buttons;
+    use crate::nes::tests::rom;
+    use crate::nes::Nes;
+
+    // Logs controller 1 to $0200 onwards, one byte per read of all buttons
+    const READER: &str = "
+        .org $8000
+            ldy #0
+        loop:
+            lda #1
+            sta $4016
+            lda #0
+            sta $4016
+            ldx #8
+        read:
+            lda $4016
+            lsr a
+            rol $00
+            dex
+            bne read
+            lda $00
+            sta $0200,y
+            iny
+            jmp loop
+        ";
+
+    fn inputs() -> Vec<MovieFrame> {
+        (0..12)
+            .map(|frame| MovieFrame {
+                commands: if frame == 6 {
+                    MovieCommands::RESET
+                } else {
+                    MovieCommands::empty()
+                },
+                buttons: [
+                    Buttons::from_bits_retain((frame * 37) as u8),
+                    Buttons::START,
+                ],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_back_what_was_recorded() {
+        let mut nes = Nes::new(rom(READER));
+        // Whatever ran before recording doesn't matter
+        nes.run_frame();
+
+        let mut recorder = MovieRecorder::from_power_on(&mut nes, "reader");
+        let mut states = Vec::new();
+        for frame in inputs() {
+            recorder.record_frame(&mut nes, frame);
+            states.push(nes.save_state());
+        }
+        // The ROM saw the last frame's buttons, A first
+        let last = inputs().last().unwrap().buttons[0];
+        assert_eq!(nes.cpu().bus().peek(0x0200), last.bits().reverse_bits());
+
+        let text = recorder.finish().to_fm2();
+        assert!(text.contains("\n|1|RL.UTSB.|....T...||\n"), "{}", text);
+        let movie = Movie::parse(&text).unwrap();
+        assert_eq!(movie.frames, inputs());
+
+        let mut other = Nes::new(rom(READER));
+        let mut player = MoviePlayer::start(movie, &mut other).unwrap();
+        for state in &states {
+            assert!(player.play_frame(&mut other));
+            assert_eq!(&other.save_state(), state);
+        }
+        assert!(!player.play_frame(&mut other));
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn starts_from_a_save_state() {
+        let mut nes = Nes::new(rom(READER));
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+
+        let mut recorder = MovieRecorder::from_savestate(&nes, "reader");
+        for frame in inputs() {
+            recorder.record_frame(&mut nes, frame);
+        }
+        let movie = Movie::parse(&recorder.finish().to_fm2()).unwrap();
+
+        let mut other = Nes::new(rom(READER));
+        let mut player = MoviePlayer::start(movie, &mut other).unwrap();
+        assert_eq!(other.frame(), 3);
+        while player.play_frame(&mut other) {}
+        assert_eq!(other.save_state(), nes.save_state());
+    }
+
+    #[test]
+    fn reads_fceux_movies() {
+        let nes = Nes::new(rom(READER));
+        let checksum =
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, nes.rom_md5());
+        let text = format!(
+            "version 3\r\nemuVersion 22020\r\nrerecordCount 12\r\npalFlag 0\r\n\
+             romFilename reader\r\nromChecksum base64:{}\r\n\
+             guid 2B7B9F4E-0000-0000-0000-000000000000\r\nfourscore 0\r\n\
+             microphone 0\r\nport0 1\r\nport1 0\r\nport2 0\r\nFDS 0\r\nNewPPU 0\r\n\
+             comment author someone\r\n\
+             |0|R......A|||\r\n|2|        |||\r\n",
+            checksum
+        );
+
+        let movie = Movie::parse(&text).unwrap();
+        assert_eq!(movie.rom_checksum, nes.rom_md5());
+        assert_eq!(movie.rerecord_count, 12);
+        assert_eq!(movie.comments, ["author someone"]);
+        assert_eq!(
+            movie.other_headers[0],
+            ("emuVersion".into(), "22020".into())
+        );
+        assert_eq!(
+            movie.frames,
+            [
+                MovieFrame {
+                    commands: MovieCommands::empty(),
+                    buttons: [Buttons::RIGHT | Buttons::A, Buttons::empty()],
+                },
+                MovieFrame {
+                    commands: MovieCommands::POWER,
+                    buttons: [Buttons::empty(); 2],
+                },
+            ]
+        );
+
+        let mut other = Nes::new(rom(".org $8000\n loop:\n jmp loop"));
+        let error = MoviePlayer::start(movie, &mut other).err().unwrap();
+        assert!(error.contains("different ROM"), "{}", error);
+
+        let pal = text.replace("palFlag 0", "palFlag 1");
+        assert!(Movie::parse(&pal).unwrap_err().contains("PAL"));
+        let hex = text.replace(&format!("base64:{}", checksum), "0x00");
+        assert!(Movie::parse(&hex).unwrap_err().contains("MD5"));
+    }
+    #[test]
+    fn parses_binary_values() {
+        assert_eq!(parse_binary("0x0aFF").unwrap(), [0x0A, 0xFF]);
+        assert_eq!(parse_binary("base64:Cv8=").unwrap(), [0x0A, 0xFF]);
+        assert!(parse_binary("0xabc").unwrap_err().contains("odd"));
+        assert!(parse_binary("0xa€bc").unwrap_err().contains("not hex"));
+        assert!(parse_binary("0xzz").unwrap_err().contains("not hex"));
+    }
+}
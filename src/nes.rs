@@ -0,0 +1,355 @@
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::constants::{PPU_DOTS_PER_CPU_CYCLE, PPU_DOTS_PER_FRAME};
+use crate::controller::Buttons;
+use crate::cpu::Cpu;
+use crate::savestate::{crc32, decode_chunks, encode_chunks, Savestate, StateReader, StateWriter};
+
+const NES_CHUNK: [u8; 4] = *b"NES ";
+const CPU_CHUNK: [u8; 4] = *b"CPU ";
+const BUS_CHUNK: [u8; 4] = *b"BUS ";
+
+// The frame the PPU would be drawing `cycles` CPU cycles after power on, for
+// code that counts cycles without a `Nes`
+pub fn frame_at_cycle(cycles: u64) -> u64 {
+    cycles * PPU_DOTS_PER_CPU_CYCLE / PPU_DOTS_PER_FRAME
+}
+
+// The whole console. So far that is the CPU and its bus with an NROM
+// cartridge and controllers. The PPU, APU and other mappers don't exist yet
+// and will add their own save state chunks when they do.
+//
+// Emulation is deterministic: the same ROM, power on seed and inputs give
+// bit for bit the same machine on every run and platform. Emulation never
+// reads the clock or depends on hash map order, and RAM contents at power on
+// come from an explicit seed.
+pub struct Nes {
+    cpu: Cpu,
+    // CPU cycles since power on
+    cycles: u64,
+    // CRC-32 of the PRG and CHR ROM, so states from another game are refused
+    rom_checksum: u32,
+    // MD5 of the same, which is what FCEUX movies identify the ROM by
+    rom_md5: [u8; 16],
+    has_battery: bool,
+    // Fills RAM at power on, which is zeroed without one
+    power_on_seed: Option<u64>,
+}
+
+impl Nes {
+    // Inserts the cartridge and powers on
+    pub fn new(rom: Rom) -> Self {
+        let mut contents = rom.prg_rom.clone();
+        contents.extend_from_slice(&rom.chr_rom);
+
+        let has_battery = rom.has_battery;
+
+        let mut nes = Nes {
+            cpu: Cpu::new(Bus::with_rom(rom)),
+            cycles: 0,
+            rom_checksum: crc32(&contents),
+            rom_md5: md5::compute(&contents).0,
+            has_battery,
+            power_on_seed: None,
+        };
+        nes.reset();
+        nes
+    }
+
+    // The reset button, RAM keeps its contents
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    // Turns the console off and on again
+    pub fn power_cycle(&mut self) {
+        self.cpu
+            .bus_mut()
+            .power_on(self.has_battery, self.power_on_seed);
+        self.cpu.reset();
+        self.cycles = 0;
+    }
+
+    // Takes effect at the next power cycle. Random RAM shakes out code that
+    // reads memory before writing it, the seed keeps such runs repeatable.
+    pub fn set_power_on_seed(&mut self, seed: Option<u64>) {
+        self.power_on_seed = seed;
+    }
+
+    // Holds `buttons` down on the controller in `port`, 0 or 1
+    pub fn set_buttons(&mut self, port: usize, buttons: Buttons) {
+        self.cpu.bus_mut().set_buttons(port, buttons);
+    }
+
+    // Runs one instruction and returns the cycles it took
+    pub fn step(&mut self) -> u8 {
+        let cycles = self.cpu.step();
+        self.cycles += cycles as u64;
+        cycles
+    }
+
+    // Runs until the next frame starts. Without a PPU frames are counted in
+    // CPU cycles, so they line up with where the PPU would start one.
+    pub fn run_frame(&mut self) {
+        let frame = self.frame();
+        while self.frame() == frame {
+            self.step();
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Frames since power on
+    pub fn frame(&self) -> u64 {
+        frame_at_cycle(self.cycles)
+    }
+
+    pub fn rom_checksum(&self) -> u32 {
+        self.rom_checksum
+    }
+
+    pub fn rom_md5(&self) -> [u8; 16] {
+        self.rom_md5
+    }
+
+    // Whether the cartridge keeps its save RAM with the power off
+    pub fn has_battery(&self) -> bool {
+        self.has_battery
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    // A fingerprint of the whole machine, for checking two runs stay in step
+    // frame by frame. It hashes the same component state a save state holds,
+    // which is all of the state by design, without building the file.
+    pub fn frame_hash(&self) -> u64 {
+        let mut hasher = StateWriter::hasher();
+        self.save_machine(&mut hasher);
+        self.cpu.save(&mut hasher);
+        self.cpu.bus().save(&mut hasher);
+        hasher.hash()
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut machine = StateWriter::new();
+        self.save_machine(&mut machine);
+
+        let mut cpu = StateWriter::new();
+        self.cpu.save(&mut cpu);
+
+        let mut bus = StateWriter::new();
+        self.cpu.bus().save(&mut bus);
+
+        encode_chunks(&[
+            (NES_CHUNK, machine.into_bytes()),
+            (CPU_CHUNK, cpu.into_bytes()),
+            (BUS_CHUNK, bus.into_bytes()),
+        ])
+    }
+
+    fn save_machine(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.rom_checksum);
+        writer.write_u64(self.cycles);
+    }
+
+    // Restores a state from `save_state`. Nothing changes if it fails.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let backup = self.save_state();
+
+        if let Err(error) = self.apply_state(state) {
+            self.apply_state(&backup)
+                .expect("A state saved moments ago loads");
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    fn apply_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let chunks = decode_chunks(state)?;
+        let chunk = |tag: [u8; 4]| {
+            chunks
+                .iter()
+                .find(|(chunk_tag, _)| *chunk_tag == tag)
+                .map(|(_, data)| StateReader::new(data))
+                .ok_or_else(|| {
+                    format!(
+                        "Save state has no {} chunk",
+                        String::from_utf8_lossy(&tag).trim_end()
+                    )
+                })
+        };
+
+        let mut machine = chunk(NES_CHUNK)?;
+        let rom_checksum = machine.read_u32()?;
+        if rom_checksum != self.rom_checksum {
+            return Err(format!(
+                "Save state is for a different ROM (checksum {:08X}, this one is {:08X})",
+                rom_checksum, self.rom_checksum
+            ));
+        }
+        self.cycles = machine.read_u64()?;
+        machine.finish()?;
+
+        self.cpu.load(&mut chunk(CPU_CHUNK)?)?;
+        self.cpu.bus_mut().load(&mut chunk(BUS_CHUNK)?)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::Nes;
+    use crate::asm::assemble;
+    use crate::cartridge::Rom;
+    use crate::constants::{CHR_ROM_PAGE_SIZE, INES_TAG, PRG_ROM_PAGE_SIZE};
+    use crate::controller::Buttons;
+    use crate::savestate::{decode_chunks, encode_chunks};
+
+    // An NROM cartridge with `source` at $8000, which is also the reset vector
+    pub(crate) fn rom(source: &str) -> Rom {
+        let assembly = assemble(source).unwrap();
+
+        let mut raw = INES_TAG.to_vec();
+        raw.extend_from_slice(&[1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+        prg_rom[..assembly.bytes.len()].copy_from_slice(&assembly.bytes);
+        prg_rom[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend_from_slice(&prg_rom);
+        raw.extend_from_slice(&vec![0; CHR_ROM_PAGE_SIZE]);
+
+        Rom::new(&raw).unwrap()
+    }
+
+    pub(crate) const COUNTER: &str = "
+        .org $8000
+        loop:
+            inc $10
+            lda $10
+            sta $0300,y
+            iny
+            jmp loop
+        ";
+
+    #[test]
+    fn restores_the_machine() {
+        let mut nes = Nes::new(rom(COUNTER));
+        for _ in 0..100 {
+            nes.step();
+        }
+
+        let state = nes.save_state();
+        let cpu = nes.cpu().state();
+        let cycles = nes.cycles();
+
+        for _ in 0..100 {
+            nes.step();
+        }
+        let later = nes.save_state();
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.cpu().state(), cpu);
+        assert_eq!(nes.cycles(), cycles);
+        assert_eq!(nes.save_state(), state);
+
+        // Running on from the restored state ends up in the same place
+        for _ in 0..100 {
+            nes.step();
+        }
+        assert_eq!(nes.save_state(), later);
+    }
+
+    fn run_twice(seed: Option<u64>) -> (Vec<u64>, Vec<u64>) {
+        let run = || {
+            let mut nes = Nes::new(rom(COUNTER));
+            nes.set_power_on_seed(seed);
+            nes.power_cycle();
+
+            (0..20)
+                .map(|frame| {
+                    nes.set_buttons(0, Buttons::from_bits_retain(frame * 13));
+                    nes.run_frame();
+                    nes.frame_hash()
+                })
+                .collect()
+        };
+        (run(), run())
+    }
+
+    #[test]
+    fn runs_are_repeatable() {
+        let (first, second) = run_twice(Some(0x5EED));
+        assert_eq!(first, second);
+
+        // COUNTER increments a byte it never initialised, so RAM at power on
+        // shows up in every frame
+        let (zeroed, _) = run_twice(None);
+        let (other_seed, _) = run_twice(Some(0x5EED + 1));
+        for frame in 0..20 {
+            assert_ne!(first[frame], zeroed[frame]);
+            assert_ne!(first[frame], other_seed[frame]);
+        }
+
+        let mut nes = Nes::new(rom(COUNTER));
+        assert!((0..0x800).all(|address| nes.cpu().bus().peek(address) == 0));
+        nes.set_power_on_seed(Some(1));
+        nes.power_cycle();
+        assert!((0..0x800).any(|address| nes.cpu().bus().peek(address) != 0));
+    }
+
+    #[test]
+    fn runs_games_that_touch_the_ppu() {
+        let mut nes = Nes::new(rom("
+            .org $8000
+                lda #$80
+                sta $2000
+                lda $2002
+            loop:
+                jmp loop
+            "));
+        nes.run_frame();
+        assert_eq!(nes.cpu().state().program_counter, 0x8008);
+    }
+
+    #[test]
+    fn refuses_states_it_cannot_use() {
+        let mut nes = Nes::new(rom(COUNTER));
+        for _ in 0..10 {
+            nes.step();
+        }
+        let state = nes.save_state();
+
+        let mut other = Nes::new(rom(".org $8000\n loop:\n jmp loop"));
+        let before = other.save_state();
+        let error = other.load_state(&state).unwrap_err();
+        assert!(error.contains("different ROM"), "{}", error);
+        assert_eq!(other.save_state(), before);
+
+        // The CPU chunk loads but the bus chunk is bad, so the CPU is rolled
+        // back too
+        let mut chunks: Vec<_> = decode_chunks(&state)
+            .unwrap()
+            .into_iter()
+            .map(|(tag, data)| (tag, data.to_vec()))
+            .collect();
+        chunks[2].1.truncate(10);
+        for _ in 0..10 {
+            nes.step();
+        }
+        let before = nes.save_state();
+        assert!(nes.load_state(&encode_chunks(&chunks)).is_err());
+        assert_eq!(nes.save_state(), before);
+
+        let mut corrupt = state.clone();
+        corrupt[40] ^= 1;
+        assert!(nes.load_state(&corrupt).is_err());
+        assert!(nes.load_state(&state[..state.len() - 1]).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum AddressingModes {
     Implicit,
     Accumulator,
@@ -15,29 +15,219 @@ pub enum AddressingModes {
     IndirectIndexed,
 }
 
+// Instruction names, which is what the CPU dispatches on
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Mnemonic {
+    Adc,
+    And,
+    Asl,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Jmp,
+    Jsr,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rol,
+    Ror,
+    Rti,
+    Rts,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sta,
+    Stx,
+    Sty,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+
+    // Unofficial
+    Alr,
+    Anc,
+    Ane,
+    Arr,
+    Dcp,
+    Isc,
+    Jam,
+    Las,
+    Lax,
+    Lxa,
+    Rla,
+    Rra,
+    Sax,
+    Sbx,
+    Sha,
+    Shx,
+    Shy,
+    Slo,
+    Sre,
+    Tas,
+}
+
+impl Mnemonic {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Mnemonic::Adc => "ADC",
+            Mnemonic::Alr => "ALR",
+            Mnemonic::Anc => "ANC",
+            Mnemonic::And => "AND",
+            Mnemonic::Ane => "ANE",
+            Mnemonic::Arr => "ARR",
+            Mnemonic::Asl => "ASL",
+            Mnemonic::Bcc => "BCC",
+            Mnemonic::Bcs => "BCS",
+            Mnemonic::Beq => "BEQ",
+            Mnemonic::Bit => "BIT",
+            Mnemonic::Bmi => "BMI",
+            Mnemonic::Bne => "BNE",
+            Mnemonic::Bpl => "BPL",
+            Mnemonic::Brk => "BRK",
+            Mnemonic::Bvc => "BVC",
+            Mnemonic::Bvs => "BVS",
+            Mnemonic::Clc => "CLC",
+            Mnemonic::Cld => "CLD",
+            Mnemonic::Cli => "CLI",
+            Mnemonic::Clv => "CLV",
+            Mnemonic::Cmp => "CMP",
+            Mnemonic::Cpx => "CPX",
+            Mnemonic::Cpy => "CPY",
+            Mnemonic::Dcp => "DCP",
+            Mnemonic::Dec => "DEC",
+            Mnemonic::Dex => "DEX",
+            Mnemonic::Dey => "DEY",
+            Mnemonic::Eor => "EOR",
+            Mnemonic::Inc => "INC",
+            Mnemonic::Inx => "INX",
+            Mnemonic::Iny => "INY",
+            Mnemonic::Isc => "ISC",
+            Mnemonic::Jam => "JAM",
+            Mnemonic::Jmp => "JMP",
+            Mnemonic::Jsr => "JSR",
+            Mnemonic::Las => "LAS",
+            Mnemonic::Lax => "LAX",
+            Mnemonic::Lda => "LDA",
+            Mnemonic::Ldx => "LDX",
+            Mnemonic::Ldy => "LDY",
+            Mnemonic::Lsr => "LSR",
+            Mnemonic::Lxa => "LXA",
+            Mnemonic::Nop => "NOP",
+            Mnemonic::Ora => "ORA",
+            Mnemonic::Pha => "PHA",
+            Mnemonic::Php => "PHP",
+            Mnemonic::Pla => "PLA",
+            Mnemonic::Plp => "PLP",
+            Mnemonic::Rla => "RLA",
+            Mnemonic::Rol => "ROL",
+            Mnemonic::Ror => "ROR",
+            Mnemonic::Rra => "RRA",
+            Mnemonic::Rti => "RTI",
+            Mnemonic::Rts => "RTS",
+            Mnemonic::Sax => "SAX",
+            Mnemonic::Sbc => "SBC",
+            Mnemonic::Sbx => "SBX",
+            Mnemonic::Sec => "SEC",
+            Mnemonic::Sed => "SED",
+            Mnemonic::Sei => "SEI",
+            Mnemonic::Sha => "SHA",
+            Mnemonic::Shx => "SHX",
+            Mnemonic::Shy => "SHY",
+            Mnemonic::Slo => "SLO",
+            Mnemonic::Sre => "SRE",
+            Mnemonic::Sta => "STA",
+            Mnemonic::Stx => "STX",
+            Mnemonic::Sty => "STY",
+            Mnemonic::Tas => "TAS",
+            Mnemonic::Tax => "TAX",
+            Mnemonic::Tay => "TAY",
+            Mnemonic::Tsx => "TSX",
+            Mnemonic::Txa => "TXA",
+            Mnemonic::Txs => "TXS",
+            Mnemonic::Tya => "TYA",
+        }
+    }
+}
+
 #[derive(PartialEq, Clone, Copy)]
 pub struct Operation {
+    pub mnemonic: Mnemonic,
     pub name: &'static str,
     pub instruction_cycles: u8,
     pub instruction_size: u8,
     pub instruction_page_cycles: u8,
     pub instruction_addressing_mode: AddressingModes,
+    pub is_official: bool,
 }
 
 impl Operation {
-    pub fn new(
-        name: &'static str,
+    pub const fn new(
+        mnemonic: Mnemonic,
         instruction_cycles: u8,
         instruction_size: u8,
         instruction_page_cycles: u8,
         instruction_addressing_mode: AddressingModes,
     ) -> Self {
         Operation {
-            name,
+            mnemonic,
+            name: mnemonic.name(),
             instruction_cycles,
             instruction_size,
             instruction_page_cycles,
             instruction_addressing_mode,
+            is_official: true,
+        }
+    }
+
+    // Undocumented opcodes, see: https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+    pub const fn unofficial(
+        mnemonic: Mnemonic,
+        instruction_cycles: u8,
+        instruction_size: u8,
+        instruction_page_cycles: u8,
+        instruction_addressing_mode: AddressingModes,
+    ) -> Self {
+        Operation {
+            is_official: false,
+            ..Operation::new(
+                mnemonic,
+                instruction_cycles,
+                instruction_size,
+                instruction_page_cycles,
+                instruction_addressing_mode,
+            )
         }
     }
 }
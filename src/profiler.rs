@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::call_stack::Frame;
+use crate::cpu::{Cpu, StepInfo};
+use crate::memory::Memory;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct AddressProfile {
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct SubroutineProfile {
+    pub calls: u64,
+    // Cycles spent in the subroutine and everything it called
+    pub inclusive_cycles: u64,
+    // Cycles spent in the subroutine's own instructions
+    pub exclusive_cycles: u64,
+}
+
+// One node per call path, for flamegraphs. Node 0 is the root, the code that
+// was running when the call stack was last empty.
+struct CallPath {
+    parent: usize,
+    subroutine: Option<u16>,
+    // Exclusive cycles spent on exactly this path
+    cycles: u64,
+}
+
+// Counts executions and cycles for every address, and attributes cycles to
+// subroutines using the CPU's call stack, so returning early with PLA/PLA or
+// using RTS as a jump does not confuse it.
+//
+// Steps come from a CPU step hook. The path of the last step is kept and only
+// looked up again when the call stack changes, so most steps just add up
+// counters.
+pub struct Profiler {
+    addresses: Vec<AddressProfile>,
+    // By address, None for addresses nothing was attributed to
+    subroutines: Vec<Option<SubroutineProfile>>,
+    paths: Vec<CallPath>,
+    // The path for each (parent path, subroutine) pair
+    path_children: HashMap<(usize, u16), usize>,
+    // The subroutines on the call stack of the last step, its path, and the
+    // subroutines again without repeats, so recursion only counts once
+    current_targets: Vec<u16>,
+    current_path: usize,
+    current_subroutines: Vec<u16>,
+    total_cycles: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            addresses: vec![AddressProfile::default(); 0x10000],
+            subroutines: vec![None; 0x10000],
+            paths: vec![CallPath {
+                parent: 0,
+                subroutine: None,
+                cycles: 0,
+            }],
+            path_children: HashMap::new(),
+            current_targets: Vec::new(),
+            current_path: 0,
+            current_subroutines: Vec::new(),
+            total_cycles: 0,
+        }
+    }
+
+    // Records one step, called from a hook added with `Cpu::add_step_hook`
+    pub fn record_step<M: Memory>(&mut self, cpu: &Cpu<M>, step: &StepInfo) {
+        self.follow_call_stack(step.frames);
+
+        let cycles = step.cycles as u64;
+        let address = &mut self.addresses[step.program_counter as usize];
+        address.executions += 1;
+        address.cycles += cycles;
+        self.total_cycles += cycles;
+        self.paths[self.current_path].cycles += cycles;
+
+        if let Some(&subroutine) = self.current_targets.last() {
+            self.subroutines[subroutine as usize]
+                .get_or_insert_with(SubroutineProfile::default)
+                .exclusive_cycles += cycles;
+        }
+        for &subroutine in &self.current_subroutines {
+            self.subroutines[subroutine as usize]
+                .get_or_insert_with(SubroutineProfile::default)
+                .inclusive_cycles += cycles;
+        }
+
+        // JSR and BRK push one frame, nothing both pushes and unwinds
+        let depth = step.frames.len();
+        if let Some(frame) = cpu.call_stack().frames().get(depth) {
+            self.subroutines[frame.target as usize]
+                .get_or_insert_with(SubroutineProfile::default)
+                .calls += 1;
+        }
+    }
+
+    fn follow_call_stack(&mut self, frames: &[Frame]) {
+        let unchanged = frames.len() == self.current_targets.len()
+            && frames
+                .iter()
+                .zip(&self.current_targets)
+                .all(|(frame, target)| frame.target == *target);
+        if unchanged {
+            return;
+        }
+
+        self.current_targets.clear();
+        self.current_targets
+            .extend(frames.iter().map(|frame| frame.target));
+
+        let mut path = 0;
+        for &subroutine in &self.current_targets {
+            path = *self
+                .path_children
+                .entry((path, subroutine))
+                .or_insert_with(|| {
+                    self.paths.push(CallPath {
+                        parent: path,
+                        subroutine: Some(subroutine),
+                        cycles: 0,
+                    });
+                    self.paths.len() - 1
+                });
+        }
+        self.current_path = path;
+
+        self.current_subroutines.clear();
+        for &subroutine in &self.current_targets {
+            if !self.current_subroutines.contains(&subroutine) {
+                self.current_subroutines.push(subroutine);
+            }
+        }
+    }
+
+    pub fn address(&self, address: u16) -> AddressProfile {
+        self.addresses[address as usize]
+    }
+
+    pub fn subroutine(&self, address: u16) -> Option<SubroutineProfile> {
+        self.subroutines[address as usize]
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    // Writes the subroutines by inclusive cycles and the `limit` busiest
+    // addresses by cycles
+    pub fn write_report<W: Write>(&self, writer: &mut W, limit: usize) -> io::Result<()> {
+        let total = self.total_cycles.max(1) as f64;
+
+        let mut subroutines: Vec<(usize, &SubroutineProfile)> = self
+            .subroutines
+            .iter()
+            .enumerate()
+            .filter_map(|(address, profile)| Some((address, profile.as_ref()?)))
+            .collect();
+        subroutines.sort_by(|left, right| {
+            (right.1.inclusive_cycles, left.0).cmp(&(left.1.inclusive_cycles, right.0))
+        });
+
+        writeln!(writer, "Total cycles: {}", self.total_cycles)?;
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:<10}{:>10}  {:>10}  {:>6}  {:>10}  {:>6}",
+            "Subroutine", "Calls", "Inclusive", "%", "Exclusive", "%"
+        )?;
+        for (address, profile) in subroutines.iter().take(limit) {
+            writeln!(
+                writer,
+                "${:04X}     {:>10}  {:>10}  {:>6.2}  {:>10}  {:>6.2}",
+                address,
+                profile.calls,
+                profile.inclusive_cycles,
+                profile.inclusive_cycles as f64 * 100.0 / total,
+                profile.exclusive_cycles,
+                profile.exclusive_cycles as f64 * 100.0 / total
+            )?;
+        }
+
+        let mut addresses: Vec<(usize, &AddressProfile)> = self
+            .addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, profile)| profile.executions > 0)
+            .collect();
+        addresses.sort_by(|left, right| (right.1.cycles, left.0).cmp(&(left.1.cycles, right.0)));
+
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:<10}{:>10}  {:>10}  {:>6}",
+            "Address", "Executions", "Cycles", "%"
+        )?;
+        for (address, profile) in addresses.iter().take(limit) {
+            writeln!(
+                writer,
+                "${:04X}     {:>10}  {:>10}  {:>6.2}",
+                address,
+                profile.executions,
+                profile.cycles,
+                profile.cycles as f64 * 100.0 / total
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Writes one `root;$C000;$C123 cycles` line per call path, the folded
+    // format read by flamegraph.pl and inferno
+    pub fn write_folded_stacks<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut lines: Vec<String> = (0..self.paths.len())
+            .filter(|&path| self.paths[path].cycles > 0)
+            .map(|path| {
+                let mut frames = Vec::new();
+                let mut node = path;
+                loop {
+                    frames.push(match self.paths[node].subroutine {
+                        Some(address) => format!("${:04X}", address),
+                        None => "root".to_string(),
+                    });
+                    if node == 0 {
+                        break;
+                    }
+                    node = self.paths[node].parent;
+                }
+                frames.reverse();
+                format!("{} {}", frames.join(";"), self.paths[path].cycles)
+            })
+            .collect();
+        lines.sort();
+
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{AddressProfile, Profiler, SubroutineProfile};
+    use crate::cpu::tests::cpu_with_program;
+    use crate::cpu::Cpu;
+
+    fn attach(cpu: &mut Cpu) -> Arc<Mutex<Profiler>> {
+        let profiler = Arc::new(Mutex::new(Profiler::new()));
+        let hook = Arc::clone(&profiler);
+        cpu.add_step_hook(move |cpu, step| hook.lock().unwrap().record_step(cpu, step));
+        profiler
+    }
+
+    #[test]
+    fn subroutine_cycles_are_inclusive_and_exclusive() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+                jsr outer
+                jsr leaf
+            done:
+                jmp done
+            outer:
+                nop
+                jsr leaf
+                rts
+            leaf:
+                inx
+                rts
+            ",
+        );
+
+        let profiler = attach(&mut cpu);
+        while cpu.state().program_counter != 0x0206 {
+            cpu.step();
+        }
+        let profiler = profiler.lock().unwrap();
+
+        // JSR 6, RTS 6, NOP 2, INX 2
+        let outer = 0x0209;
+        let leaf = 0x020E;
+        assert_eq!(
+            profiler.subroutine(outer),
+            Some(SubroutineProfile {
+                calls: 1,
+                inclusive_cycles: 2 + 6 + (2 + 6) + 6,
+                exclusive_cycles: 2 + 6 + 6,
+            })
+        );
+        assert_eq!(
+            profiler.subroutine(leaf),
+            Some(SubroutineProfile {
+                calls: 2,
+                inclusive_cycles: 16,
+                exclusive_cycles: 16,
+            })
+        );
+        assert_eq!(
+            profiler.address(0x020E),
+            AddressProfile {
+                executions: 2,
+                cycles: 4
+            }
+        );
+        assert_eq!(profiler.total_cycles(), 12 + 22 + 8);
+
+        let mut folded = Vec::new();
+        profiler.write_folded_stacks(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "root 12\nroot;$0209 14\nroot;$0209;$020E 8\nroot;$020E 8\n"
+        );
+
+        let mut report = Vec::new();
+        profiler.write_report(&mut report, 2).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("$0209              1          22   52.38          14   33.33"));
+    }
+
+    #[test]
+    fn unwinding_the_stack_pops_frames() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $0200
+                jsr outer
+            done:
+                jmp done
+            outer:
+                jsr unwind
+                rts
+            unwind:
+                pla
+                pla
+                rts
+            ",
+        );
+
+        let profiler = attach(&mut cpu);
+        for _ in 0..5 {
+            cpu.step();
+        }
+
+        // `unwind` drops its own return address and returns from `outer`
+        assert_eq!(cpu.state().program_counter, 0x0203);
+        assert_eq!(cpu.call_stack().depth(), 0);
+
+        // The RTS ran in `outer` once `unwind` was gone
+        let mut folded = Vec::new();
+        profiler
+            .lock()
+            .unwrap()
+            .write_folded_stacks(&mut folded)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "root 6\nroot;$0206 12\nroot;$0206;$020A 8\n"
+        );
+    }
+}
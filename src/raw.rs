@@ -0,0 +1,344 @@
+use std::fmt::Write;
+
+use crate::cpu::{Cpu, CpuState};
+use crate::memory::{FlatMemory, Memory};
+
+const BRK_OPCODE: u8 = 0x00;
+
+// A flat 64 KiB machine for running plain 6502 code, where bytes written to
+// the putchar port are collected as output
+pub struct RawMemory {
+    memory: FlatMemory,
+    putchar_port: Option<u16>,
+    output: Vec<u8>,
+}
+
+impl RawMemory {
+    pub fn new(putchar_port: Option<u16>) -> Self {
+        RawMemory {
+            memory: FlatMemory::new(),
+            putchar_port,
+            output: Vec::new(),
+        }
+    }
+
+    pub fn load(&mut self, address: u16, bytes: &[u8]) {
+        self.memory.load(address, bytes);
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl Memory for RawMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if self.putchar_port == Some(address) {
+            self.output.push(value);
+        } else {
+            self.memory.write(address, value);
+        }
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory.peek(address)
+    }
+}
+
+pub struct RawOptions {
+    pub load_address: u16,
+    pub start_address: u16,
+    // Stops before executing a BRK instead of taking the interrupt
+    pub until_brk: bool,
+    pub putchar_port: Option<u16>,
+    pub max_cycles: u64,
+    // Inclusive ranges included in the result
+    pub dumps: Vec<(u16, u16)>,
+}
+
+impl Default for RawOptions {
+    fn default() -> Self {
+        RawOptions {
+            load_address: 0x0200,
+            start_address: 0x0200,
+            until_brk: false,
+            putchar_port: Some(0xF001),
+            max_cycles: 100_000_000,
+            dumps: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RawStop {
+    Brk,
+    // An instruction jumped or branched to itself
+    Trap,
+    CycleLimit,
+}
+
+impl RawStop {
+    fn name(&self) -> &'static str {
+        match self {
+            RawStop::Brk => "brk",
+            RawStop::Trap => "trap",
+            RawStop::CycleLimit => "cycle_limit",
+        }
+    }
+}
+
+pub struct RawResult {
+    pub stop: RawStop,
+    pub state: CpuState,
+    pub cycles: u64,
+    pub instructions: u64,
+    pub output: Vec<u8>,
+    pub dumps: Vec<(u16, Vec<u8>)>,
+}
+
+// Loads `program` into an otherwise zeroed memory and runs it from the start
+// address. Decimal mode works, as on a stock 6502.
+pub fn run_raw(program: &[u8], options: &RawOptions) -> Result<RawResult, String> {
+    if options.load_address as usize + program.len() > 0x10000 {
+        return Err(format!(
+            "{} bytes do not fit at ${:04X}",
+            program.len(),
+            options.load_address
+        ));
+    }
+
+    let mut memory = RawMemory::new(options.putchar_port);
+    memory.load(options.load_address, program);
+
+    let mut cpu = Cpu::new(memory);
+    cpu.set_decimal_mode_supported(true);
+    cpu.set_state(CpuState {
+        program_counter: options.start_address,
+        ..cpu.state()
+    });
+
+    let mut cycles = 0;
+    let mut instructions = 0;
+
+    let stop = loop {
+        let program_counter = cpu.state().program_counter;
+
+        if options.until_brk && cpu.bus().peek(program_counter) == BRK_OPCODE {
+            break RawStop::Brk;
+        }
+        if cycles >= options.max_cycles {
+            break RawStop::CycleLimit;
+        }
+
+        cycles += cpu.step() as u64;
+        instructions += 1;
+
+        if cpu.state().program_counter == program_counter {
+            break RawStop::Trap;
+        }
+    };
+
+    let dumps = options
+        .dumps
+        .iter()
+        .map(|&(start, end)| {
+            let bytes = (start..=end).map(|address| cpu.bus().peek(address));
+            (start, bytes.collect())
+        })
+        .collect();
+
+    Ok(RawResult {
+        stop,
+        state: cpu.state(),
+        cycles,
+        instructions,
+        output: cpu.bus().output().to_vec(),
+        dumps,
+    })
+}
+
+impl RawResult {
+    pub fn to_json(&self) -> String {
+        let state = &self.state;
+        let mut json = String::new();
+
+        write!(
+            json,
+            "{{\"stop\":\"{}\",\"cycles\":{},\"instructions\":{},",
+            self.stop.name(),
+            self.cycles,
+            self.instructions
+        )
+        .unwrap();
+        write!(
+            json,
+            "\"registers\":{{\"pc\":{},\"a\":{},\"x\":{},\"y\":{},\"sp\":{},\"p\":{}}},",
+            state.program_counter,
+            state.register_accumulator,
+            state.register_x,
+            state.register_y,
+            state.stack_pointer,
+            state.register_status.bits()
+        )
+        .unwrap();
+        write!(
+            json,
+            "\"output\":{},\"memory\":[",
+            json_string(&String::from_utf8_lossy(&self.output))
+        )
+        .unwrap();
+
+        for (index, (start, bytes)) in self.dumps.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            let bytes: Vec<String> = bytes.iter().map(u8::to_string).collect();
+            write!(
+                json,
+                "{{\"start\":{},\"bytes\":[{}]}}",
+                start,
+                bytes.join(",")
+            )
+            .unwrap();
+        }
+
+        json.push_str("]}");
+        json
+    }
+
+    // The registers and a hex dump of each range, 16 bytes a line
+    pub fn summary(&self) -> String {
+        let mut lines = vec![
+            format!(
+                "Stopped ({}) after {} instructions, {} cycles",
+                self.stop.name(),
+                self.instructions,
+                self.cycles
+            ),
+            self.state.to_string(),
+        ];
+
+        for (start, bytes) in &self.dumps {
+            for (row, chunk) in bytes.chunks(16).enumerate() {
+                let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+                let address = start.wrapping_add(row as u16 * 16);
+                lines.push(format!("${:04X}  {}", address, hex.join(" ")));
+            }
+        }
+
+        lines.join("\n")
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+
+    for character in text.chars() {
+        match character {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            character if (character as u32) < 0x20 => {
+                write!(json, "\\u{:04x}", character as u32).unwrap();
+            }
+            character => json.push(character),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_raw, RawOptions, RawStop};
+    use crate::asm::assemble;
+
+    const PROGRAM: &str = "
+        putchar = $F001
+
+        .org $0200
+            ldx #0
+        print:
+            lda message,x
+            beq finished
+            sta putchar
+            inx
+            bne print
+        finished:
+            sed
+            clc
+            lda #$19
+            adc #$01
+            sta $10
+            brk
+        message:
+            .byte \"Hi\", 10, 0
+        ";
+
+    #[test]
+    fn runs_until_brk_and_reports_json() {
+        let assembly = assemble(PROGRAM).unwrap();
+        let options = RawOptions {
+            until_brk: true,
+            dumps: vec![(0x0010, 0x0011)],
+            ..RawOptions::default()
+        };
+
+        let result = run_raw(&assembly.bytes, &options).unwrap();
+        assert_eq!(result.stop, RawStop::Brk);
+        assert_eq!(result.output, b"Hi\n");
+        assert_eq!(result.state.register_accumulator, 0x20);
+
+        let json: serde_json::Value = serde_json::from_str(&result.to_json()).unwrap();
+        assert_eq!(json["stop"], "brk");
+        assert_eq!(json["output"], "Hi\n");
+        assert_eq!(json["registers"]["pc"], assembly.labels["finished"] + 8);
+        assert_eq!(json["registers"]["a"], 0x20);
+        assert_eq!(json["memory"][0]["start"], 0x10);
+        assert_eq!(json["memory"][0]["bytes"], serde_json::json!([0x20, 0]));
+
+        let summary = result.summary();
+        assert!(summary.starts_with("Stopped (brk) after"));
+        assert!(summary.ends_with("$0010  20 00"));
+    }
+
+    #[test]
+    fn stops_at_the_cycle_limit() {
+        let assembly = assemble(
+            "
+            .org $0300
+            loop:
+                inx
+                jmp loop
+            ",
+        )
+        .unwrap();
+        let options = RawOptions {
+            load_address: 0x0300,
+            start_address: 0x0300,
+            max_cycles: 50,
+            ..RawOptions::default()
+        };
+
+        let result = run_raw(&assembly.bytes, &options).unwrap();
+        assert_eq!(result.stop, RawStop::CycleLimit);
+        assert_eq!(result.cycles, 50);
+        assert_eq!(result.state.register_x, 10);
+
+        assert!(run_raw(
+            &[0; 16],
+            &RawOptions {
+                load_address: 0xFFF8,
+                ..RawOptions::default()
+            }
+        )
+        .is_err());
+    }
+}
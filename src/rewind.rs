@@ -0,0 +1,359 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::movie::{self, MovieFrame};
+use crate::nes::Nes;
+
+// An older snapshot, stored as the run length encoded XOR of it and the
+// snapshot after it. Consecutive states differ in few bytes, so most of the
+// XOR is zeros.
+struct Delta {
+    frame: u64,
+    length: usize,
+    data: Vec<u8>,
+}
+
+// Recent history for rewinding. The frames are run through here, so that
+// each frame's input is kept alongside the snapshots and going back replays
+// exactly what happened. A snapshot is taken before every `interval`th
+// frame, the newest is kept whole and each older one as a delta against its
+// successor. The oldest are dropped to stay within `budget` bytes.
+//
+// Frames are counted from when the history began rather than taken from the
+// console, whose count starts over when it is power cycled.
+pub struct Rewind {
+    interval: u64,
+    budget: usize,
+    // Frames run since the history began
+    position: u64,
+    latest: Option<(u64, Vec<u8>)>,
+    // Oldest first
+    history: VecDeque<Delta>,
+    // The input of every frame from the oldest snapshot up to `position`
+    inputs: VecDeque<MovieFrame>,
+    used: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: u64, budget: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+            position: 0,
+            latest: None,
+            history: VecDeque::new(),
+            inputs: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    // Runs one frame with `input` and keeps it for rewinding
+    pub fn run_frame(&mut self, nes: &mut Nes, input: MovieFrame) {
+        if self.position.is_multiple_of(self.interval) && self.newest_frame() != Some(self.position)
+        {
+            self.snapshot(nes);
+        }
+
+        movie::run_frame(nes, &input);
+        self.inputs.push_back(input);
+        self.used += mem::size_of::<MovieFrame>();
+        self.position += 1;
+        self.drop_oldest();
+    }
+
+    fn snapshot(&mut self, nes: &Nes) {
+        let state = nes.save_state();
+        if let Some((previous_frame, previous)) = self.latest.take() {
+            let delta = Delta {
+                frame: previous_frame,
+                length: previous.len(),
+                data: compress(&xor(&previous, &state)),
+            };
+            self.used += delta.data.len();
+            self.used -= previous.len();
+            self.history.push_back(delta);
+        }
+
+        self.used += state.len();
+        self.latest = Some((self.position, state));
+    }
+
+    fn drop_oldest(&mut self) {
+        while self.used > self.budget {
+            let Some(oldest) = self.history.pop_front() else {
+                break;
+            };
+            self.used -= oldest.data.len();
+
+            // Inputs from before the new oldest snapshot can't be replayed
+            let oldest_frame = self.oldest_frame().unwrap_or(self.position);
+            while self.first_input_frame() < oldest_frame {
+                self.inputs.pop_front();
+                self.used -= mem::size_of::<MovieFrame>();
+            }
+        }
+    }
+
+    // Goes back one frame, by loading the newest snapshot from before it and
+    // replaying the inputs since. Snapshots after the new frame are thrown
+    // away. Returns false without changing anything when the history doesn't
+    // go back that far.
+    pub fn step_back(&mut self, nes: &mut Nes) -> Result<bool, String> {
+        let Some(target) = self.position.checked_sub(1) else {
+            return Ok(false);
+        };
+        if self.oldest_frame().is_none_or(|oldest| oldest > target) {
+            return Ok(false);
+        }
+
+        while self.newest_frame().is_some_and(|frame| frame > target) {
+            self.pop_latest();
+        }
+
+        let (frame, state) = self.latest.as_ref().expect("The oldest snapshot is kept");
+        nes.load_state(state)?;
+
+        let first = self.first_input_frame();
+        for frame in *frame..target {
+            movie::run_frame(nes, &self.inputs[(frame - first) as usize]);
+        }
+
+        self.inputs.pop_back();
+        self.used -= mem::size_of::<MovieFrame>();
+        self.position = target;
+        Ok(true)
+    }
+
+    // Frames run since the history began, less the ones stepped back over
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn newest_frame(&self) -> Option<u64> {
+        self.latest.as_ref().map(|(frame, _)| *frame)
+    }
+
+    pub fn oldest_frame(&self) -> Option<u64> {
+        match self.history.front() {
+            Some(delta) => Some(delta.frame),
+            None => self.newest_frame(),
+        }
+    }
+
+    fn first_input_frame(&self) -> u64 {
+        self.position - self.inputs.len() as u64
+    }
+
+    // The number of snapshots held
+    pub fn len(&self) -> usize {
+        self.history.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    // Bytes used by the snapshots and inputs
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+
+    // Starts the history over, counting frames from 0 again
+    pub fn clear(&mut self) {
+        self.position = 0;
+        self.latest = None;
+        self.history.clear();
+        self.inputs.clear();
+        self.used = 0;
+    }
+
+    fn pop_latest(&mut self) {
+        let Some((_, latest)) = self.latest.take() else {
+            return;
+        };
+        self.used -= latest.len();
+
+        if let Some(delta) = self.history.pop_back() {
+            let mut state = xor(&decompress(&delta.data), &latest);
+            state.truncate(delta.length);
+            self.used -= delta.data.len();
+            self.used += state.len();
+            self.latest = Some((delta.frame, state));
+        }
+    }
+}
+
+// XORs two states, the shorter one is padded with zeros
+fn xor(first: &[u8], second: &[u8]) -> Vec<u8> {
+    let length = first.len().max(second.len());
+    (0..length)
+        .map(|index| first.get(index).unwrap_or(&0) ^ second.get(index).unwrap_or(&0))
+        .collect()
+}
+
+// Runs of zeros and literal bytes. A control byte with the top bit set is
+// followed by nothing and stands for (c & $7F) + 1 zeros, otherwise it is
+// followed by c + 1 literal bytes.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+
+    while index < data.len() {
+        let zeros = data[index..]
+            .iter()
+            .take(128)
+            .take_while(|byte| **byte == 0)
+            .count();
+        if zeros > 0 {
+            output.push(0x80 | (zeros - 1) as u8);
+            index += zeros;
+            continue;
+        }
+
+        let literals = data[index..]
+            .iter()
+            .take(128)
+            .take_while(|byte| **byte != 0)
+            .count();
+        output.push((literals - 1) as u8);
+        output.extend_from_slice(&data[index..index + literals]);
+        index += literals;
+    }
+
+    output
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+
+    while index < data.len() {
+        let control = data[index] as usize;
+        index += 1;
+
+        if control & 0x80 != 0 {
+            output.resize(output.len() + (control & 0x7F) + 1, 0);
+        } else {
+            output.extend_from_slice(&data[index..index + control + 1]);
+            index += control + 1;
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress, Rewind};
+    use crate::controller::Buttons;
+    use crate::movie::{MovieCommands, MovieFrame};
+    use crate::nes::tests::{rom, COUNTER};
+    use crate::nes::Nes;
+
+    #[test]
+    fn compresses_runs_of_zeros() {
+        let mut data = vec![0; 1000];
+        data[3] = 1;
+        data[500..700].fill(7);
+        data[999] = 9;
+
+        let compressed = compress(&data);
+        assert!(compressed.len() < 230, "{}", compressed.len());
+        assert_eq!(decompress(&compressed), data);
+        assert!(compress(&[]).is_empty());
+    }
+
+    #[test]
+    fn steps_back_frame_by_frame() {
+        let mut nes = Nes::new(rom(COUNTER));
+        let mut rewind = Rewind::new(4, usize::MAX);
+        let mut states = vec![nes.save_state()];
+
+        for _ in 0..20 {
+            rewind.run_frame(&mut nes, MovieFrame::default());
+            states.push(nes.save_state());
+        }
+        assert_eq!(rewind.position(), 20);
+        assert_eq!(rewind.len(), 5);
+
+        // Each step lands exactly where playing forward was at that frame
+        for frame in (0..20).rev() {
+            assert_eq!(rewind.step_back(&mut nes), Ok(true));
+            assert_eq!(rewind.position(), frame as u64);
+            assert_eq!(nes.save_state(), states[frame]);
+        }
+        assert_eq!(rewind.step_back(&mut nes), Ok(false));
+        assert_eq!(rewind.len(), 1);
+
+        // The oldest snapshot is kept for playing on and rewinding again
+        rewind.run_frame(&mut nes, MovieFrame::default());
+        rewind.run_frame(&mut nes, MovieFrame::default());
+        assert_eq!(rewind.step_back(&mut nes), Ok(true));
+        assert_eq!(nes.save_state(), states[1]);
+    }
+
+    #[test]
+    fn replays_the_input_of_each_frame() {
+        // Adds up the A button of the first controller as fast as it can
+        let mut nes = Nes::new(rom("
+            .org $8000
+            loop:
+                lda #1
+                sta $4016
+                lda #0
+                sta $4016
+                lda $4016
+                and #1
+                clc
+                adc $10
+                sta $10
+                bcc loop
+                inc $11
+                jmp loop
+            "));
+        let mut rewind = Rewind::new(8, usize::MAX);
+        let mut states = vec![nes.save_state()];
+
+        for frame in 0..30u8 {
+            let commands = match frame {
+                11 => MovieCommands::RESET,
+                19 => MovieCommands::POWER,
+                _ => MovieCommands::empty(),
+            };
+            let input = MovieFrame {
+                commands,
+                buttons: [Buttons::from_bits_retain(frame % 3), Buttons::empty()],
+            };
+            rewind.run_frame(&mut nes, input);
+            states.push(nes.save_state());
+        }
+
+        // Holding no buttons now must not change what the past frames read
+        for frame in (0..30).rev() {
+            assert_eq!(rewind.step_back(&mut nes), Ok(true));
+            assert_eq!(nes.save_state(), states[frame], "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn stays_within_the_budget() {
+        let mut nes = Nes::new(rom(COUNTER));
+        let state_size = nes.save_state().len();
+        let mut rewind = Rewind::new(1, state_size + 2000);
+
+        for _ in 0..30 {
+            rewind.run_frame(&mut nes, MovieFrame::default());
+            assert!(rewind.memory_used() <= state_size + 2000);
+        }
+
+        // The deltas are much smaller than whole states
+        assert!(rewind.len() > 3, "{}", rewind.len());
+        assert_eq!(rewind.newest_frame(), Some(29));
+
+        let oldest = rewind.oldest_frame().unwrap();
+        while rewind.position() > oldest {
+            assert_eq!(rewind.step_back(&mut nes), Ok(true));
+        }
+        assert_eq!(rewind.step_back(&mut nes), Ok(false));
+    }
+}
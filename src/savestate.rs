@@ -0,0 +1,308 @@
+use crate::constants::{SAVESTATE_HEADER_SIZE, SAVESTATE_MAGIC, SAVESTATE_VERSION};
+
+// A component of the machine that can be written to and restored from a save
+// state. Each one owns a chunk of the file and decides its own layout.
+pub trait Savestate {
+    fn save(&self, writer: &mut StateWriter);
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), String>;
+}
+
+// 64 bit FNV-1a
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+// Little endian values appended to a chunk. A hasher keeps only a hash of
+// them instead, for fingerprinting state without storing it.
+pub struct StateWriter {
+    data: Vec<u8>,
+    hash: Option<u64>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter {
+            data: Vec::new(),
+            hash: None,
+        }
+    }
+
+    pub fn hasher() -> Self {
+        StateWriter {
+            data: Vec::new(),
+            hash: Some(FNV_OFFSET_BASIS),
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.append(&[value]);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.append(&[value as u8]);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.append(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.append(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.append(&value.to_le_bytes());
+    }
+
+    // Writes the length first, so readers can check it
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.append(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    // The hash of everything written to a `hasher`
+    pub fn hash(&self) -> u64 {
+        self.hash.expect("Only a hasher has a hash")
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        match &mut self.hash {
+            Some(hash) => {
+                for byte in bytes {
+                    *hash = (*hash ^ *byte as u64).wrapping_mul(FNV_PRIME);
+                }
+            }
+            None => self.data.extend_from_slice(bytes),
+        }
+    }
+}
+
+// Reads back what a `StateWriter` wrote, failing instead of panicking on
+// short or malformed chunks
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, position: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.position < length {
+            return Err("Save state chunk is truncated".to_string());
+        }
+
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(format!("Save state has {} where a flag belongs", value)),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], String> {
+        let length = self.read_u32()? as usize;
+        self.take(length)
+    }
+
+    // Reads bytes written by `write_bytes` into a buffer of the same size
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), String> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(format!(
+                "Save state has {} bytes where {} belong",
+                bytes.len(),
+                buffer.len()
+            ));
+        }
+
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    // Fails if anything is left over, which means the layout doesn't match
+    pub fn finish(&self) -> Result<(), String> {
+        if self.position != self.data.len() {
+            return Err("Save state chunk is longer than expected".to_string());
+        }
+        Ok(())
+    }
+}
+
+// A tag and the data that follows it
+pub type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+// The file is a 16 byte header followed by chunks:
+//
+//   header: "NESS", version (u16), reserved (u16), payload length (u32),
+//           CRC-32 of the payload (u32)
+//   chunk:  tag (4 bytes), length (u32), data
+//
+// All numbers are little endian. Readers skip chunks they don't know, so a
+// newer file can carry extra chunks, anything incompatible bumps the version.
+pub fn encode_chunks(chunks: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for (tag, data) in chunks {
+        payload.extend_from_slice(tag);
+        payload.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        payload.extend_from_slice(data);
+    }
+
+    let mut file = Vec::with_capacity(SAVESTATE_HEADER_SIZE + payload.len());
+    file.extend_from_slice(&SAVESTATE_MAGIC);
+    file.extend_from_slice(&SAVESTATE_VERSION.to_le_bytes());
+    file.extend_from_slice(&0u16.to_le_bytes());
+    file.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    file.extend_from_slice(&crc32(&payload).to_le_bytes());
+    file.extend_from_slice(&payload);
+    file
+}
+
+// Checks the header and checksum and splits the payload into chunks
+pub fn decode_chunks(file: &[u8]) -> Result<Vec<Chunk<'_>>, String> {
+    if file.len() < SAVESTATE_HEADER_SIZE || file[0..4] != SAVESTATE_MAGIC {
+        return Err("File is not a save state".to_string());
+    }
+
+    let header = |offset: usize, length: usize| &file[offset..offset + length];
+    let version = u16::from_le_bytes(header(4, 2).try_into().unwrap());
+    let length = u32::from_le_bytes(header(8, 4).try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header(12, 4).try_into().unwrap());
+
+    if version != SAVESTATE_VERSION {
+        return Err(format!(
+            "Save state version {} is not supported, expected {}",
+            version, SAVESTATE_VERSION
+        ));
+    }
+
+    let payload = &file[SAVESTATE_HEADER_SIZE..];
+    if payload.len() != length {
+        return Err("Save state is truncated".to_string());
+    }
+    if crc32(payload) != checksum {
+        return Err("Save state is corrupt, the checksum does not match".to_string());
+    }
+
+    let mut chunks = Vec::new();
+    let mut reader = StateReader::new(payload);
+    while reader.position < payload.len() {
+        let tag = reader.take(4)?.try_into().unwrap();
+        let length = reader.read_u32()? as usize;
+        chunks.push((tag, reader.take(length)?));
+    }
+
+    Ok(chunks)
+}
+
+// CRC-32 as used by zip and PNG
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, decode_chunks, encode_chunks, StateReader, StateWriter};
+    use crate::constants::SAVESTATE_VERSION;
+
+    #[test]
+    fn values_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u64(u64::MAX - 1);
+        writer.write_bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.read_u8(), Ok(0x12));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x3456));
+        assert_eq!(reader.read_u64(), Ok(u64::MAX - 1));
+
+        let mut buffer = [0; 2];
+        assert!(reader.read_into(&mut buffer).is_err());
+        assert!(reader.finish().is_ok());
+
+        let mut reader = StateReader::new(&data[..3]);
+        reader.read_u8().unwrap();
+        reader.read_bool().unwrap();
+        assert!(reader.read_u16().is_err());
+        assert!(reader.finish().is_err());
+    }
+
+    #[test]
+    fn hashers_keep_only_a_hash() {
+        let mut hasher = StateWriter::hasher();
+        for byte in b"123456789" {
+            hasher.write_u8(*byte);
+        }
+        assert_eq!(hasher.hash(), 0x06D5_5739_23C6_CDFC);
+        assert!(hasher.into_bytes().is_empty());
+    }
+
+    #[test]
+    fn checks_the_header_and_checksum() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let file = encode_chunks(&[(*b"ONE ", vec![1, 2]), (*b"TWO ", vec![])]);
+        let chunks = decode_chunks(&file).unwrap();
+        assert_eq!(chunks, vec![(*b"ONE ", &[1, 2][..]), (*b"TWO ", &[][..])]);
+
+        let mut corrupt = file.clone();
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        assert!(decode_chunks(&corrupt).unwrap_err().contains("checksum"));
+
+        let mut newer = file.clone();
+        newer[4..6].copy_from_slice(&(SAVESTATE_VERSION + 1).to_le_bytes());
+        let error = decode_chunks(&newer).unwrap_err();
+        assert!(error.contains("is not supported"), "{}", error);
+
+        assert!(decode_chunks(&file[..file.len() - 1]).is_err());
+        assert!(decode_chunks(b"NES\x1A").is_err());
+    }
+}
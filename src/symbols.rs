@@ -0,0 +1,376 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::bus::Bus;
+use crate::constants::{INES_HEADER_SIZE, PRG_ROM_PAGE_SIZE};
+
+// Start of the cartridge work RAM that Mesen's W and S labels are relative to
+const WORK_RAM_START: u16 = 0x6000;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SymbolLocation {
+    // RAM, registers and anything else that does not move with banking
+    Cpu(u16),
+    // An offset into the PRG ROM, whichever bank it is mapped in through
+    PrgRom(usize),
+}
+
+// Labels loaded from assembler and emulator symbol files. PRG ROM labels are
+// stored by ROM offset, so a label only shows up while its bank is mapped.
+#[derive(Default, Clone)]
+pub struct SymbolTable {
+    cpu: BTreeMap<u16, String>,
+    prg_rom: BTreeMap<usize, String>,
+    names: HashMap<String, SymbolLocation>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // The first label for a location wins, later ones can still be looked
+    // up by name
+    pub fn insert(&mut self, name: &str, location: SymbolLocation) {
+        match location {
+            SymbolLocation::Cpu(address) => {
+                self.cpu.entry(address).or_insert_with(|| name.to_string());
+            }
+            SymbolLocation::PrgRom(offset) => {
+                self.prg_rom
+                    .entry(offset)
+                    .or_insert_with(|| name.to_string());
+            }
+        }
+
+        self.names.insert(name.to_string(), location);
+    }
+
+    // The label for a CPU address with the banks currently mapped on `bus`
+    pub fn label(&self, bus: &Bus, address: u16) -> Option<&str> {
+        match bus.prg_rom_offset(address) {
+            Some(offset) => self.prg_rom.get(&offset),
+            None => self.cpu.get(&address),
+        }
+        .map(String::as_str)
+    }
+
+    // The CPU address a label is visible at right now, if its bank is mapped
+    pub fn address(&self, bus: &Bus, name: &str) -> Option<u16> {
+        match *self.names.get(name)? {
+            SymbolLocation::Cpu(address) => Some(address),
+            SymbolLocation::PrgRom(offset) => bus.prg_rom_address(offset),
+        }
+    }
+
+    // Loads the files that sit next to a ROM: `game.dbg` from ld65,
+    // `game.mlb` from Mesen and `game.nes.ram.nl`, `game.nes.0.nl`, ... from
+    // FCEUX. Returns the names of the files that were loaded.
+    pub fn load_for_rom(&mut self, rom_path: &Path) -> Result<Vec<String>, String> {
+        let mut candidates = vec![
+            (rom_path.with_extension("dbg"), SymbolFormat::Ca65),
+            (rom_path.with_extension("mlb"), SymbolFormat::Mesen),
+            (append(rom_path, ".ram.nl"), SymbolFormat::Fceux(None)),
+        ];
+        for bank in 0..256 {
+            let path = append(rom_path, &format!(".{:X}.nl", bank));
+            candidates.push((path, SymbolFormat::Fceux(Some(bank))));
+        }
+
+        let mut loaded = Vec::new();
+
+        for (path, format) in candidates {
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+
+            let result = match format {
+                SymbolFormat::Ca65 => self.load_ca65_dbg(&text),
+                SymbolFormat::Mesen => self.load_mesen_mlb(&text),
+                SymbolFormat::Fceux(bank) => self.load_fceux_nl(&text, bank),
+            };
+
+            result.map_err(|error| format!("{}: {}", path.display(), error))?;
+            loaded.push(path.display().to_string());
+        }
+
+        Ok(loaded)
+    }
+
+    // FCEUX name lists, lines of `$C000#name#comment`, see:
+    // https://fceux.com/web/help/NLFilesFormat.html
+    // A bank file numbers 16 KiB PRG banks, without a bank the addresses are
+    // plain CPU addresses as in the `.ram.nl` file.
+    pub fn load_fceux_nl(&mut self, text: &str, bank: Option<usize>) -> Result<(), String> {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let Some(line) = line.strip_prefix('$') else {
+                continue;
+            };
+
+            let mut fields = line.splitn(3, '#');
+            let address = fields.next().unwrap_or_default();
+            let name = fields.next().unwrap_or_default().trim();
+
+            if name.is_empty() {
+                continue;
+            }
+
+            // `$0300/10` labels an array, the label goes on its first byte
+            let address = address.split('/').next().unwrap_or_default();
+            let address = u16::from_str_radix(address, 16)
+                .map_err(|_| format!("line {}: malformed address '{}'", index + 1, address))?;
+
+            let location = match bank {
+                Some(bank) if address >= 0x8000 => SymbolLocation::PrgRom(
+                    bank * PRG_ROM_PAGE_SIZE + (address as usize % PRG_ROM_PAGE_SIZE),
+                ),
+                _ => SymbolLocation::Cpu(address),
+            };
+
+            self.insert(name, location);
+        }
+
+        Ok(())
+    }
+
+    // Mesen label files, lines of `P:1234:name:comment` where the letter is
+    // the memory type. Mesen 2 writes the full type names instead.
+    pub fn load_mesen_mlb(&mut self, text: &str) -> Result<(), String> {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut fields = line.splitn(4, ':');
+            let (Some(kind), Some(address), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(format!("line {}: expected type:address:label", index + 1));
+            };
+
+            if name.is_empty() {
+                continue;
+            }
+
+            // Ranges label their first byte
+            let address = address.split('-').next().unwrap_or_default();
+            let address = usize::from_str_radix(address, 16)
+                .map_err(|_| format!("line {}: malformed address '{}'", index + 1, address))?;
+
+            let location = match kind {
+                "P" | "NesPrgRom" => SymbolLocation::PrgRom(address),
+                "R" | "NesInternalRam" | "G" | "NesMemory" => SymbolLocation::Cpu(address as u16),
+                "W" | "NesWorkRam" | "S" | "NesSaveRam" => {
+                    SymbolLocation::Cpu(WORK_RAM_START.wrapping_add(address as u16))
+                }
+                // CHR and other PPU memory are not CPU addresses
+                _ => continue,
+            };
+
+            self.insert(name, location);
+        }
+
+        Ok(())
+    }
+
+    // Debug info written by `ld65 --dbgfile`. Labels in segments that end up
+    // in the ROM file are placed by their file offset, so they stay correct
+    // for banked code.
+    pub fn load_ca65_dbg(&mut self, text: &str) -> Result<(), String> {
+        // Segment id to the PRG offset of the segment start, and its address
+        let mut segments: HashMap<String, (usize, u16)> = HashMap::new();
+        let mut symbols = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let Some((kind, attributes)) = line.split_once('\t') else {
+                continue;
+            };
+
+            let attributes = parse_dbg_attributes(attributes);
+            let attribute = |name: &str| {
+                attributes
+                    .get(name)
+                    .copied()
+                    .ok_or_else(|| format!("line {}: {} has no {}", index + 1, kind, name))
+            };
+
+            match kind {
+                "seg" => {
+                    // Segments that are not written to a file are RAM
+                    let Some(file_offset) = attributes.get("ooffs") else {
+                        continue;
+                    };
+
+                    let file_offset = parse_dbg_number(file_offset)
+                        .ok_or_else(|| format!("line {}: malformed ooffs", index + 1))?;
+                    let start = parse_dbg_number(attribute("start")?)
+                        .ok_or_else(|| format!("line {}: malformed start", index + 1))?;
+
+                    if let Some(offset) = file_offset.checked_sub(INES_HEADER_SIZE) {
+                        segments.insert(attribute("id")?.to_string(), (offset, start as u16));
+                    }
+                }
+                "sym" => {
+                    if attributes.get("type") != Some(&"lab") {
+                        continue;
+                    }
+
+                    let value = parse_dbg_number(attribute("val")?)
+                        .ok_or_else(|| format!("line {}: malformed val", index + 1))?;
+                    let name = attribute("name")?.trim_matches('"').to_string();
+                    let segment = attributes.get("seg").map(|segment| segment.to_string());
+
+                    symbols.push((name, value as u16, segment));
+                }
+                _ => {}
+            }
+        }
+
+        // Segments can come after the symbols that use them
+        for (name, value, segment) in symbols {
+            let location = match segment.and_then(|segment| segments.get(&segment)) {
+                Some((offset, start)) => {
+                    SymbolLocation::PrgRom(offset + value.wrapping_sub(*start) as usize)
+                }
+                None => SymbolLocation::Cpu(value),
+            };
+
+            self.insert(&name, location);
+        }
+
+        Ok(())
+    }
+}
+
+enum SymbolFormat {
+    Ca65,
+    Mesen,
+    Fceux(Option<usize>),
+}
+
+fn append(path: &Path, suffix: &str) -> std::path::PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+// Splits `id=0,name="main",val=0xC000` into its attributes. Quoted values
+// may contain commas.
+fn parse_dbg_attributes(text: &str) -> HashMap<&str, &str> {
+    let mut attributes = HashMap::new();
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let Some((key, value)) = rest.split_once('=') else {
+            break;
+        };
+
+        let end = if let Some(quoted) = value.strip_prefix('"') {
+            quoted.find('"').map_or(value.len(), |end| end + 2)
+        } else {
+            value.find(',').unwrap_or(value.len())
+        };
+
+        attributes.insert(key, &value[..end]);
+        rest = value[end..].strip_prefix(',').unwrap_or_default();
+    }
+
+    attributes
+}
+
+fn parse_dbg_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(digits) => usize::from_str_radix(digits, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SymbolLocation, SymbolTable};
+    use crate::bus::Bus;
+    use crate::nes::tests::rom;
+
+    fn nrom_bus() -> Bus {
+        Bus::with_rom(rom(".org $8000"))
+    }
+
+    #[test]
+    fn loads_fceux_name_lists() {
+        let mut symbols = SymbolTable::new();
+        symbols
+            .load_fceux_nl("$0300/10#buffer#Sixteen bytes\n$0010##\n", None)
+            .unwrap();
+        symbols
+            .load_fceux_nl("$C3A0#update_player#\\\nMoves the player\n", Some(1))
+            .unwrap();
+
+        // Bank 1 is past the end of a 16 KiB ROM, so its label never shows
+        let bus = nrom_bus();
+        assert_eq!(symbols.label(&bus, 0x0300), Some("buffer"));
+        assert_eq!(symbols.label(&bus, 0x0010), None);
+        assert_eq!(
+            symbols.names.get("update_player"),
+            Some(&SymbolLocation::PrgRom(0x43A0))
+        );
+        assert_eq!(symbols.label(&bus, 0xC3A0), None);
+        assert_eq!(symbols.address(&bus, "update_player"), None);
+        assert!(symbols.load_fceux_nl("$XYZ#bad#\n", None).is_err());
+    }
+
+    #[test]
+    fn loads_mesen_labels() {
+        let mut symbols = SymbolTable::new();
+        symbols
+            .load_mesen_mlb(
+                "P:03A0:update_player:Moves the player\n\
+                 R:0010-0011:pointer\n\
+                 W:0000:save_data\n\
+                 G:2000:PpuControl\n\
+                 P:0000::comment only\n",
+            )
+            .unwrap();
+
+        // A 16 KiB ROM is mirrored, so the label shows up in both halves
+        let bus = nrom_bus();
+        assert_eq!(symbols.label(&bus, 0x83A0), Some("update_player"));
+        assert_eq!(symbols.label(&bus, 0xC3A0), Some("update_player"));
+        assert_eq!(symbols.address(&bus, "update_player"), Some(0x83A0));
+        assert_eq!(symbols.label(&bus, 0x0010), Some("pointer"));
+        assert_eq!(symbols.label(&bus, 0x6000), Some("save_data"));
+        assert_eq!(symbols.label(&bus, 0x2000), Some("PpuControl"));
+        assert_eq!(symbols.label(&bus, 0x8000), None);
+        assert!(symbols.load_mesen_mlb("P:12\n").is_err());
+    }
+
+    #[test]
+    fn loads_ca65_debug_info() {
+        let text = "\
+version\tmajor=2,minor=0
+sym\tid=0,name=\"update_player\",addrsize=absolute,scope=0,def=1,val=0xC3A0,seg=1,type=lab
+sym\tid=1,name=\"player_x\",addrsize=zeropage,scope=0,def=2,val=0x10,seg=0,type=lab
+sym\tid=2,name=\"SPEED\",addrsize=zeropage,scope=0,def=3,val=0x2,type=equ
+seg\tid=0,name=\"ZEROPAGE\",start=0x000010,size=0x0002,addrsize=zeropage,type=rw
+seg\tid=1,name=\"CODE, MAIN\",start=0x00C000,size=0x0400,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16
+";
+
+        let mut symbols = SymbolTable::new();
+        symbols.load_ca65_dbg(text).unwrap();
+
+        let bus = nrom_bus();
+        assert_eq!(
+            symbols.names.get("update_player"),
+            Some(&SymbolLocation::PrgRom(0x03A0))
+        );
+        assert_eq!(symbols.label(&bus, 0xC3A0), Some("update_player"));
+        assert_eq!(symbols.label(&bus, 0x0010), Some("player_x"));
+        assert_eq!(symbols.address(&bus, "SPEED"), None);
+    }
+}
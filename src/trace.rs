@@ -0,0 +1,159 @@
+use std::io::{self, Write};
+
+use crate::cpu::{Cpu, StepInfo};
+use crate::disasm::disassemble_instruction_with_labels;
+use crate::symbols::SymbolTable;
+
+// Formats the instruction at the program counter and the registers before it
+// runs, in the layout of the nestest.log reference trace:
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD
+//
+// Operands with a label in `symbols` are written by name.
+pub fn trace(cpu: &Cpu, symbols: &SymbolTable) -> String {
+    let state = cpu.state();
+    let bus = cpu.bus();
+
+    let instruction = disassemble_instruction_with_labels(
+        state.program_counter,
+        |address| bus.peek(address),
+        |address| symbols.label(bus, address).map(str::to_string),
+    );
+
+    let bytes: Vec<String> = instruction
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+
+    format!(
+        "{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+        state.program_counter,
+        bytes.join(" "),
+        instruction.text,
+        state.register_accumulator,
+        state.register_x,
+        state.register_y,
+        state.register_status.bits(),
+        state.stack_pointer
+    )
+}
+
+// Writes a `trace` line for every instruction the CPU runs, from a hook added
+// with `Cpu::add_step_hook`. Hooks run after the step, so each line is
+// formatted ahead of time and only written once its instruction has run.
+// Interrupts are left out, as in nestest.log.
+pub struct Tracer<W: Write> {
+    output: W,
+    symbols: SymbolTable,
+    // The upcoming instruction's address and line
+    next: Option<(u16, String)>,
+    // The first failed write, after which nothing more is written
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W, symbols: SymbolTable) -> Self {
+        Tracer {
+            output,
+            symbols,
+            next: None,
+            error: None,
+        }
+    }
+
+    // Formats the first instruction, call it before the CPU steps
+    pub fn begin(&mut self, cpu: &Cpu) {
+        self.next = Some((cpu.state().program_counter, trace(cpu, &self.symbols)));
+    }
+
+    pub fn record_step(&mut self, cpu: &Cpu, step: &StepInfo) {
+        if let Some((program_counter, line)) = self.next.take() {
+            // An interrupt fetches no instruction, and the line is stale when
+            // the program counter was set by hand since
+            let ran = step.fetches != 0 && step.program_counter == program_counter;
+            if ran && self.error.is_none() {
+                if let Err(error) = writeln!(self.output, "{}", line) {
+                    self.error = Some(error);
+                }
+            }
+        }
+
+        self.next = Some((cpu.state().program_counter, trace(cpu, &self.symbols)));
+    }
+
+    // Flushes the output and reports the first failed write
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{trace, Tracer};
+    use crate::cpu::tests::cpu_with_program;
+    use crate::symbols::{SymbolLocation, SymbolTable};
+
+    #[test]
+    fn formats_like_nestest() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $C000
+                jsr $C3A0
+                lda $10,x
+            ",
+        );
+
+        let mut symbols = SymbolTable::new();
+        assert_eq!(
+            trace(&cpu, &symbols),
+            "C000  20 A0 C3  JSR $C3A0                       A:00 X:00 Y:00 P:24 SP:FD"
+        );
+
+        symbols.insert("update_player", SymbolLocation::Cpu(0xC3A0));
+        assert_eq!(
+            trace(&cpu, &symbols),
+            "C000  20 A0 C3  JSR update_player               A:00 X:00 Y:00 P:24 SP:FD"
+        );
+
+        let mut state = cpu.state();
+        state.program_counter = 0xC003;
+        cpu.set_state(state);
+        symbols.insert("player_x", SymbolLocation::Cpu(0x0010));
+        assert_eq!(
+            trace(&cpu, &symbols),
+            "C003  B5 10     LDA player_x,X                  A:00 X:00 Y:00 P:24 SP:FD"
+        );
+    }
+    #[test]
+    fn tracers_log_each_instruction_before_it_runs() {
+        let mut cpu = cpu_with_program(
+            "
+            .org $C000
+                ldx #$01
+                inx
+                nop
+            ",
+        );
+
+        let tracer = Arc::new(Mutex::new(Tracer::new(Vec::new(), SymbolTable::new())));
+        tracer.lock().unwrap().begin(&cpu);
+        let hook = Arc::clone(&tracer);
+        cpu.add_step_hook(move |cpu, step| hook.lock().unwrap().record_step(cpu, step));
+
+        cpu.step();
+        cpu.step();
+        let mut tracer = tracer.lock().unwrap();
+        tracer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(tracer.output.clone()).unwrap(),
+            "C000  A2 01     LDX #$01                        A:00 X:00 Y:00 P:24 SP:FD\n\
+             C002  E8        INX                             A:00 X:01 Y:00 P:24 SP:FD\n"
+        );
+    }
+}
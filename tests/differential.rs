@@ -0,0 +1,55 @@
+// Property tests comparing `Cpu` with the reference model in
+// tests/reference. The seed is fixed so every run checks the same cases, the
+// fuzz target in fuzz/ explores further.
+
+mod reference;
+
+use proptest::prelude::*;
+use proptest::test_runner::{Config, RngSeed};
+
+use reference::{check, is_official, Case};
+
+fn official_opcodes() -> Vec<u8> {
+    (0..=0xFF).filter(|opcode| is_official(*opcode)).collect()
+}
+
+fn case() -> impl Strategy<Value = Case> {
+    (
+        any::<u16>(),
+        any::<[u8; 5]>(),
+        prop::sample::select(official_opcodes()),
+        any::<[u8; 2]>(),
+        any::<u64>(),
+    )
+        .prop_map(|(pc, [a, x, y, s, p], opcode, operand, seed)| Case {
+            pc,
+            a,
+            x,
+            y,
+            s,
+            p,
+            instruction: [opcode, operand[0], operand[1]],
+            seed,
+        })
+}
+
+#[test]
+fn models_every_official_opcode() {
+    assert_eq!(official_opcodes().len(), 151);
+}
+
+proptest! {
+    #![proptest_config(Config {
+        cases: 1024,
+        rng_seed: RngSeed::Fixed(0x6502),
+        failure_persistence: None,
+        ..Config::default()
+    })]
+
+    #[test]
+    fn cpu_matches_the_reference_model(case in case()) {
+        if let Err(divergence) = check(&case) {
+            prop_assert!(false, "{}", divergence);
+        }
+    }
+}
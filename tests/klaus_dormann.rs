@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::Path;
+
+use rust_nes_emulator::bus::Bus;
+use rust_nes_emulator::cpu::Cpu;
+
+// See tests/roms/README.md for where these come from
+const ROM_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms");
+
+const FUNCTIONAL_TEST_START: u16 = 0x0400;
+const FUNCTIONAL_TEST_SUCCESS: u16 = 0x3469;
+// The functional test keeps the number of the running test here
+const FUNCTIONAL_TEST_CASE: u16 = 0x0200;
+
+const DECIMAL_TEST_START: u16 = 0x0200;
+// Zero once the decimal test passed
+const DECIMAL_TEST_ERROR: u16 = 0x000B;
+
+// The functional test runs about 30 million instructions
+const MAX_INSTRUCTIONS: u64 = 100_000_000;
+
+fn load_rom(name: &str) -> Vec<u8> {
+    let path = Path::new(ROM_DIRECTORY).join(name);
+
+    match fs::read(&path) {
+        Ok(rom) => rom,
+        Err(error) => panic!(
+            "Could not read {}, see tests/roms/README.md: {}",
+            path.display(),
+            error
+        ),
+    }
+}
+
+fn flat_cpu(image: &[u8], load_address: u16, start: u16) -> Cpu {
+    let mut bus = Bus::new_flat();
+    bus.load_program(load_address, image);
+
+    let [lo, hi] = start.to_le_bytes();
+    bus.mem_write(0xFFFC, lo);
+    bus.mem_write(0xFFFD, hi);
+
+    let mut cpu = Cpu::new(bus);
+    cpu.set_decimal_mode_supported(true);
+    cpu.reset();
+    cpu
+}
+
+// The ROMs are not checked in, run with `cargo test -- --ignored` once they
+// are in tests/roms
+#[test]
+#[ignore = "needs tests/roms/6502_functional_test.bin"]
+fn functional_test() {
+    let rom = load_rom("6502_functional_test.bin");
+
+    let mut cpu = flat_cpu(&rom, 0x0000, FUNCTIONAL_TEST_START);
+    let trap = cpu.run_until_trap(MAX_INSTRUCTIONS);
+    let test_case = cpu.bus_mut().mem_read(FUNCTIONAL_TEST_CASE);
+    let trap = trap.unwrap_or_else(|error| panic!("{} in test case ${:02X}", error, test_case));
+
+    assert_eq!(
+        trap,
+        FUNCTIONAL_TEST_SUCCESS,
+        "Trapped at ${:04X} in test case ${:02X}\n{}",
+        trap,
+        test_case,
+        cpu.call_stack().format_backtrace(trap, |_| None)
+    );
+}
+
+#[test]
+#[ignore = "needs tests/roms/6502_decimal_test.bin"]
+fn decimal_test() {
+    let rom = load_rom("6502_decimal_test.bin");
+
+    let mut cpu = flat_cpu(&rom, DECIMAL_TEST_START, DECIMAL_TEST_START);
+    let trap = cpu.run_until_trap(MAX_INSTRUCTIONS).unwrap();
+    let error = cpu.bus_mut().mem_read(DECIMAL_TEST_ERROR);
+
+    assert_eq!(
+        error,
+        0,
+        "Trapped at ${:04X} with ERROR set\n{}",
+        trap,
+        cpu.call_stack().format_backtrace(trap, |_| None)
+    );
+}
@@ -0,0 +1,617 @@
+// A deliberately plain 6502 model for differential testing. It shares no code
+// with the emulator: opcodes are decoded from their aaabbbcc bit pattern and
+// cycle counts come from the addressing rules rather than a table. Only the
+// 151 official opcodes are modelled, with the NES's lack of decimal mode.
+//
+// Used by tests/differential.rs and the fuzz target in fuzz/.
+
+#![allow(dead_code)]
+
+use rust_nes_emulator::cpu::{Cpu, CpuState, ExecutionMode};
+use rust_nes_emulator::cpu_flags::CpuFlags;
+use rust_nes_emulator::memory::{FlatMemory, Memory};
+
+const CARRY: u8 = 0x01;
+const ZERO: u8 = 0x02;
+const INTERRUPT: u8 = 0x04;
+const BREAK: u8 = 0x10;
+const UNUSED: u8 = 0x20;
+const OVERFLOW: u8 = 0x40;
+const NEGATIVE: u8 = 0x80;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    IndirectX,
+    IndirectY,
+    Indirect,
+    Relative,
+}
+
+fn decode(opcode: u8) -> Option<(&'static str, Mode)> {
+    use Mode::*;
+
+    let single = match opcode {
+        0x00 => Some(("BRK", Implied)),
+        0x20 => Some(("JSR", Absolute)),
+        0x40 => Some(("RTI", Implied)),
+        0x60 => Some(("RTS", Implied)),
+        0x08 => Some(("PHP", Implied)),
+        0x28 => Some(("PLP", Implied)),
+        0x48 => Some(("PHA", Implied)),
+        0x68 => Some(("PLA", Implied)),
+        0x88 => Some(("DEY", Implied)),
+        0xA8 => Some(("TAY", Implied)),
+        0xC8 => Some(("INY", Implied)),
+        0xE8 => Some(("INX", Implied)),
+        0x18 => Some(("CLC", Implied)),
+        0x38 => Some(("SEC", Implied)),
+        0x58 => Some(("CLI", Implied)),
+        0x78 => Some(("SEI", Implied)),
+        0x98 => Some(("TYA", Implied)),
+        0xB8 => Some(("CLV", Implied)),
+        0xD8 => Some(("CLD", Implied)),
+        0xF8 => Some(("SED", Implied)),
+        0x8A => Some(("TXA", Implied)),
+        0x9A => Some(("TXS", Implied)),
+        0xAA => Some(("TAX", Implied)),
+        0xBA => Some(("TSX", Implied)),
+        0xCA => Some(("DEX", Implied)),
+        0xEA => Some(("NOP", Implied)),
+        0x4C => Some(("JMP", Absolute)),
+        0x6C => Some(("JMP", Indirect)),
+        _ => None,
+    };
+    if single.is_some() {
+        return single;
+    }
+
+    if opcode & 0x1F == 0x10 {
+        let names = ["BPL", "BMI", "BVC", "BVS", "BCC", "BCS", "BNE", "BEQ"];
+        return Some((names[(opcode >> 5) as usize], Relative));
+    }
+
+    let aaa = opcode >> 5;
+    let bbb = (opcode >> 2) & 0b111;
+
+    match opcode & 0b11 {
+        0b01 => {
+            let name = ["ORA", "AND", "EOR", "ADC", "STA", "LDA", "CMP", "SBC"][aaa as usize];
+            let mode = [
+                IndirectX, ZeroPage, Immediate, Absolute, IndirectY, ZeroPageX, AbsoluteY,
+                AbsoluteX,
+            ][bbb as usize];
+            if name == "STA" && mode == Immediate {
+                return None;
+            }
+            Some((name, mode))
+        }
+        0b10 => {
+            let name = ["ASL", "ROL", "LSR", "ROR", "STX", "LDX", "DEC", "INC"][aaa as usize];
+            let uses_y = name == "STX" || name == "LDX";
+            let mode = match bbb {
+                0 if name == "LDX" => Immediate,
+                1 => ZeroPage,
+                2 if aaa < 4 => Accumulator,
+                3 => Absolute,
+                5 if uses_y => ZeroPageY,
+                5 => ZeroPageX,
+                7 if name == "LDX" => AbsoluteY,
+                7 if name != "STX" => AbsoluteX,
+                _ => return None,
+            };
+            Some((name, mode))
+        }
+        0b00 => {
+            let name = [
+                None,
+                Some("BIT"),
+                None,
+                None,
+                Some("STY"),
+                Some("LDY"),
+                Some("CPY"),
+                Some("CPX"),
+            ][aaa as usize]?;
+            let mode = match bbb {
+                0 if matches!(name, "LDY" | "CPY" | "CPX") => Immediate,
+                1 => ZeroPage,
+                3 => Absolute,
+                5 if matches!(name, "STY" | "LDY") => ZeroPageX,
+                7 if name == "LDY" => AbsoluteX,
+                _ => return None,
+            };
+            Some((name, mode))
+        }
+        _ => None,
+    }
+}
+
+pub fn is_official(opcode: u8) -> bool {
+    decode(opcode).is_some()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Machine {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub memory: Vec<u8>,
+}
+
+impl Machine {
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn read_u16(&self, address: u16) -> u16 {
+        self.read(address) as u16 | (self.read(address.wrapping_add(1)) as u16) << 8
+    }
+
+    fn read_zero_page_u16(&self, address: u8) -> u16 {
+        self.read(address as u16) as u16 | (self.read(address.wrapping_add(1) as u16) as u16) << 8
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x0100 | self.s as u16, value);
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        self.read(0x0100 | self.s as u16)
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.p & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn set_zn(&mut self, value: u8) {
+        self.set_flag(ZERO, value == 0);
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+    }
+
+    fn add(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + (self.p & CARRY) as u16;
+        let result = sum as u8;
+        self.set_flag(CARRY, sum > 0xFF);
+        self.set_flag(OVERFLOW, (self.a ^ result) & (value ^ result) & 0x80 != 0);
+        self.a = result;
+        self.set_zn(result);
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(CARRY, register >= value);
+        self.set_zn(register.wrapping_sub(value));
+    }
+
+    // Executes one instruction and returns its cycles, or None for an
+    // opcode this model doesn't know
+    pub fn step(&mut self) -> Option<u8> {
+        let opcode = self.read(self.pc);
+        let (name, mode) = decode(opcode)?;
+        let operand = self.pc.wrapping_add(1);
+
+        let (address, crossed, size) = match mode {
+            Mode::Implied | Mode::Accumulator => (0, false, 1),
+            Mode::Immediate | Mode::Relative => (operand, false, 2),
+            Mode::ZeroPage => (self.read(operand) as u16, false, 2),
+            Mode::ZeroPageX => (self.read(operand).wrapping_add(self.x) as u16, false, 2),
+            Mode::ZeroPageY => (self.read(operand).wrapping_add(self.y) as u16, false, 2),
+            Mode::Absolute => (self.read_u16(operand), false, 3),
+            Mode::AbsoluteX | Mode::AbsoluteY => {
+                let base = self.read_u16(operand);
+                let index = if mode == Mode::AbsoluteX {
+                    self.x
+                } else {
+                    self.y
+                };
+                let address = base.wrapping_add(index as u16);
+                (address, base >> 8 != address >> 8, 3)
+            }
+            Mode::IndirectX => {
+                let pointer = self.read(operand).wrapping_add(self.x);
+                (self.read_zero_page_u16(pointer), false, 2)
+            }
+            Mode::IndirectY => {
+                let base = self.read_zero_page_u16(self.read(operand));
+                let address = base.wrapping_add(self.y as u16);
+                (address, base >> 8 != address >> 8, 2)
+            }
+            Mode::Indirect => {
+                // The high byte is read without carrying into the next page
+                let pointer = self.read_u16(operand);
+                let hi = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
+                let address = self.read(pointer) as u16 | (self.read(hi) as u16) << 8;
+                (address, false, 3)
+            }
+        };
+
+        let next = self.pc.wrapping_add(size);
+        self.pc = next;
+
+        let base_cycles = match mode {
+            Mode::Implied | Mode::Accumulator | Mode::Immediate | Mode::Relative => 2,
+            Mode::ZeroPage => 3,
+            Mode::ZeroPageX | Mode::ZeroPageY | Mode::Absolute => 4,
+            Mode::AbsoluteX | Mode::AbsoluteY => 4,
+            Mode::IndirectX => 6,
+            Mode::IndirectY => 5,
+            Mode::Indirect => 5,
+        };
+        let read_cycles = base_cycles + crossed as u8;
+        let store_cycles = match mode {
+            Mode::AbsoluteX | Mode::AbsoluteY => 5,
+            Mode::IndirectY => 6,
+            _ => base_cycles,
+        };
+        let modify_cycles = match mode {
+            Mode::Accumulator => 2,
+            Mode::ZeroPage => 5,
+            Mode::ZeroPageX | Mode::Absolute => 6,
+            _ => 7,
+        };
+
+        let cycles = match name {
+            "LDA" | "LDX" | "LDY" => {
+                let value = self.read(address);
+                match name {
+                    "LDA" => self.a = value,
+                    "LDX" => self.x = value,
+                    _ => self.y = value,
+                }
+                self.set_zn(value);
+                read_cycles
+            }
+            "STA" | "STX" | "STY" => {
+                let value = match name {
+                    "STA" => self.a,
+                    "STX" => self.x,
+                    _ => self.y,
+                };
+                self.write(address, value);
+                store_cycles
+            }
+            "ORA" | "AND" | "EOR" => {
+                let value = self.read(address);
+                self.a = match name {
+                    "ORA" => self.a | value,
+                    "AND" => self.a & value,
+                    _ => self.a ^ value,
+                };
+                self.set_zn(self.a);
+                read_cycles
+            }
+            "ADC" => {
+                self.add(self.read(address));
+                read_cycles
+            }
+            "SBC" => {
+                self.add(!self.read(address));
+                read_cycles
+            }
+            "CMP" | "CPX" | "CPY" => {
+                let register = match name {
+                    "CMP" => self.a,
+                    "CPX" => self.x,
+                    _ => self.y,
+                };
+                self.compare(register, self.read(address));
+                read_cycles
+            }
+            "BIT" => {
+                let value = self.read(address);
+                self.set_flag(ZERO, self.a & value == 0);
+                self.set_flag(NEGATIVE, value & 0x80 != 0);
+                self.set_flag(OVERFLOW, value & 0x40 != 0);
+                read_cycles
+            }
+            "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" => {
+                let value = if mode == Mode::Accumulator {
+                    self.a
+                } else {
+                    self.read(address)
+                };
+                let carry_in = self.p & CARRY;
+                let result = match name {
+                    "ASL" => value << 1,
+                    "LSR" => value >> 1,
+                    "ROL" => value << 1 | carry_in,
+                    "ROR" => value >> 1 | carry_in << 7,
+                    "INC" => value.wrapping_add(1),
+                    _ => value.wrapping_sub(1),
+                };
+                match name {
+                    "ASL" | "ROL" => self.set_flag(CARRY, value & 0x80 != 0),
+                    "LSR" | "ROR" => self.set_flag(CARRY, value & 0x01 != 0),
+                    _ => {}
+                }
+                self.set_zn(result);
+                if mode == Mode::Accumulator {
+                    self.a = result;
+                } else {
+                    self.write(address, result);
+                }
+                modify_cycles
+            }
+            "INX" | "INY" | "DEX" | "DEY" => {
+                let register = match name {
+                    "INX" | "DEX" => &mut self.x,
+                    _ => &mut self.y,
+                };
+                *register = if name.starts_with("IN") {
+                    register.wrapping_add(1)
+                } else {
+                    register.wrapping_sub(1)
+                };
+                let value = *register;
+                self.set_zn(value);
+                2
+            }
+            "TAX" | "TAY" | "TXA" | "TYA" | "TSX" => {
+                let value = match name {
+                    "TAX" | "TAY" => self.a,
+                    "TXA" => self.x,
+                    "TYA" => self.y,
+                    _ => self.s,
+                };
+                match name {
+                    "TAX" | "TSX" => self.x = value,
+                    "TAY" => self.y = value,
+                    _ => self.a = value,
+                }
+                self.set_zn(value);
+                2
+            }
+            "TXS" => {
+                self.s = self.x;
+                2
+            }
+            "CLC" | "SEC" | "CLI" | "SEI" | "CLV" | "CLD" | "SED" => {
+                match name {
+                    "CLC" => self.set_flag(CARRY, false),
+                    "SEC" => self.set_flag(CARRY, true),
+                    "CLI" => self.set_flag(INTERRUPT, false),
+                    "SEI" => self.set_flag(INTERRUPT, true),
+                    "CLV" => self.set_flag(OVERFLOW, false),
+                    "CLD" => self.p &= !0x08,
+                    _ => self.p |= 0x08,
+                }
+                2
+            }
+            "NOP" => 2,
+            "PHA" => {
+                self.push(self.a);
+                3
+            }
+            "PHP" => {
+                self.push(self.p | BREAK | UNUSED);
+                3
+            }
+            "PLA" => {
+                self.a = self.pull();
+                self.set_zn(self.a);
+                4
+            }
+            "PLP" => {
+                self.p = self.pull();
+                4
+            }
+            "JMP" => {
+                self.pc = address;
+                if mode == Mode::Absolute {
+                    3
+                } else {
+                    5
+                }
+            }
+            "JSR" => {
+                let last = next.wrapping_sub(1);
+                self.push((last >> 8) as u8);
+                self.push(last as u8);
+                self.pc = address;
+                6
+            }
+            "RTS" => {
+                let lo = self.pull() as u16;
+                let hi = self.pull() as u16;
+                self.pc = (hi << 8 | lo).wrapping_add(1);
+                6
+            }
+            "RTI" => {
+                self.p = self.pull();
+                let lo = self.pull() as u16;
+                let hi = self.pull() as u16;
+                self.pc = hi << 8 | lo;
+                6
+            }
+            "BRK" => {
+                let return_address = next.wrapping_add(1);
+                self.push((return_address >> 8) as u8);
+                self.push(return_address as u8);
+                self.push(self.p | BREAK | UNUSED);
+                self.set_flag(INTERRUPT, true);
+                self.pc = self.read_u16(0xFFFE);
+                7
+            }
+            _ => {
+                let flag_set = match name {
+                    "BPL" | "BMI" => self.flag(NEGATIVE),
+                    "BVC" | "BVS" => self.flag(OVERFLOW),
+                    "BCC" | "BCS" => self.flag(CARRY),
+                    _ => self.flag(ZERO),
+                };
+                let wanted = matches!(name, "BMI" | "BVS" | "BCS" | "BEQ");
+
+                if flag_set == wanted {
+                    let offset = self.read(address) as i8;
+                    let target = next.wrapping_add(offset as u16);
+                    self.pc = target;
+                    3 + (next >> 8 != target >> 8) as u8
+                } else {
+                    2
+                }
+            }
+        };
+
+        Some(cycles)
+    }
+}
+
+// One differential test: the registers, the instruction bytes placed at the
+// program counter and a seed that fills the rest of memory
+#[derive(Debug, Clone, Copy)]
+pub struct Case {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub instruction: [u8; 3],
+    pub seed: u64,
+}
+
+impl Case {
+    // Reads a case from raw fuzzer input, None when there isn't enough
+    pub fn from_bytes(data: &[u8]) -> Option<Case> {
+        if data.len() < 18 {
+            return None;
+        }
+
+        Some(Case {
+            pc: u16::from_le_bytes([data[0], data[1]]),
+            a: data[2],
+            x: data[3],
+            y: data[4],
+            s: data[5],
+            p: data[6],
+            instruction: [data[7], data[8], data[9]],
+            seed: u64::from_le_bytes(data[10..18].try_into().unwrap()),
+        })
+    }
+
+    fn memory(&self) -> Vec<u8> {
+        // xorshift64, so pointers and operands are varied but reproducible
+        let mut state = self.seed | 1;
+        let mut memory: Vec<u8> = (0..0x10000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        for (offset, byte) in self.instruction.iter().enumerate() {
+            memory[self.pc.wrapping_add(offset as u16) as usize] = *byte;
+        }
+        memory
+    }
+}
+
+// Runs the case on the reference model and on `Cpu` in both execution modes,
+// returning the first difference. Unofficial opcodes are skipped.
+pub fn check(case: &Case) -> Result<(), String> {
+    let memory = case.memory();
+
+    let mut expected = Machine {
+        pc: case.pc,
+        a: case.a,
+        x: case.x,
+        y: case.y,
+        s: case.s,
+        p: case.p,
+        memory: memory.clone(),
+    };
+    let Some(expected_cycles) = expected.step() else {
+        return Ok(());
+    };
+
+    for mode in [ExecutionMode::Instruction, ExecutionMode::CycleStepped] {
+        let mut flat = FlatMemory::new();
+        flat.load(0, &memory);
+
+        let mut cpu = Cpu::new(flat);
+        cpu.set_execution_mode(mode);
+        cpu.set_state(CpuState {
+            program_counter: case.pc,
+            stack_pointer: case.s,
+            register_accumulator: case.a,
+            register_x: case.x,
+            register_y: case.y,
+            register_status: CpuFlags::from_bits_truncate(case.p),
+        });
+
+        let cycles = cpu.step();
+        let state = cpu.state();
+
+        let context = format!(
+            "{:?} opcode ${:02X} in {:?} ({:?} mode)",
+            decode(case.instruction[0]).unwrap().0,
+            case.instruction[0],
+            case,
+            mode
+        );
+
+        // B and the unused bit only exist on the stack
+        let ignored = BREAK | UNUSED;
+        let registers = [
+            ("PC", state.program_counter, expected.pc),
+            ("A", state.register_accumulator as u16, expected.a as u16),
+            ("X", state.register_x as u16, expected.x as u16),
+            ("Y", state.register_y as u16, expected.y as u16),
+            ("S", state.stack_pointer as u16, expected.s as u16),
+            (
+                "P",
+                (state.register_status.bits() & !ignored) as u16,
+                (expected.p & !ignored) as u16,
+            ),
+            ("cycles", cycles as u16, expected_cycles as u16),
+        ];
+        for (name, actual, wanted) in registers {
+            if actual != wanted {
+                return Err(format!(
+                    "{} is ${:04X}, expected ${:04X}: {}",
+                    name, actual, wanted, context
+                ));
+            }
+        }
+
+        for address in 0..=0xFFFF_u16 {
+            let actual = cpu.bus().peek(address);
+            let wanted = expected.memory[address as usize];
+            if actual != wanted {
+                return Err(format!(
+                    "${:04X} is ${:02X}, expected ${:02X}: {}",
+                    address, actual, wanted, context
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}